@@ -0,0 +1,153 @@
+//! Harness replaying a sensor trace into the logic and checking its outputs
+//!
+//! The logic of the layout runs on mock pins with the mock clock advanced in steps of one
+//! millisecond. Whenever an output changes a line is added to the snapshot:
+//!
+//! ```text
+//! 1523 stopper 3 locked
+//! 2001 light 1 0 R
+//! 2001 servo 0 120
+//! ```
+//!
+//! Lights are read from their pins as `G`, `Y`, `R`, `RY` or `OFF`, the signal heads of pedestrian
+//! crossings as `G`, `R` or `OFF` and the lights of level crossings as `R` or `OFF`, servos are
+//! given by the id and angle written to the servo controller. The snapshot is compared to the
+//! expected one, so an incident captured on the layout stays fixed once its trace and snapshot are
+//! checked in.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+
+use car_system::log::Level;
+use car_system::serial::Serial;
+
+use crate::layout::Layout;
+use crate::model;
+use crate::trace::Edge;
+
+/// Milliseconds the logic keeps running after the last edge of the trace
+const TRAILING_TIME: u64 = 2_000;
+
+/// Output of the logic which is not needed by the harness
+struct Discard;
+
+impl Serial for Discard {
+    fn write_str(&mut self, _s: &str) {}
+}
+
+/// Prints the log messages of the logic
+struct Stdout;
+
+impl Serial for Stdout {
+    fn write_str(&mut self, s: &str) {
+        print!("{}", s);
+    }
+}
+
+/// Result of checking a trace against its snapshot
+pub enum Outcome {
+    /// The outputs match the snapshot
+    Passed,
+    /// The snapshot was written from the outputs
+    Blessed,
+    /// The outputs differ, holds a description of the first difference
+    Failed(String),
+}
+
+/// Replays the trace into the logic of the layout and compares the outputs to the snapshot
+///
+/// # Arguments
+///
+/// * `layout` - the layout the trace was captured on
+/// * `edges` - the trace ordered by time
+/// * `snapshot` - path of the file with the expected outputs
+/// * `bless` - if the snapshot should be written from the outputs instead of compared
+/// * `log_level` - the level up to which the log messages of the logic are printed
+pub fn run(
+    layout: &Layout,
+    edges: &[Edge],
+    snapshot: &str,
+    bless: bool,
+    log_level: Level,
+) -> Result<Outcome, Box<dyn Error>> {
+    let actual = replay(layout, edges, log_level)?;
+    if bless {
+        let mut text = String::from("# outputs of the logic, written by `car-ctl check --bless`\n");
+        for line in &actual {
+            text.push_str(line);
+            text.push('\n');
+        }
+        fs::write(snapshot, text)?;
+        return Ok(Outcome::Blessed);
+    }
+    // a snapshot which can't be read fails the check, it is only written when asked for
+    let expected = fs::read_to_string(snapshot)
+        .map_err(|error| format!("{}: {}, write it with `--bless`", snapshot, error))?;
+
+    let mut expected = expected
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'));
+    let mut actual = actual.iter();
+    loop {
+        let outcome = match (expected.next(), actual.next()) {
+            (None, None) => Outcome::Passed,
+            (Some((_, expected)), Some(actual)) if expected.trim() == actual => continue,
+            (Some((number, expected)), Some(actual)) => Outcome::Failed(format!(
+                "{}: line {} expected `{}` but got `{}`",
+                snapshot,
+                number + 1,
+                expected.trim(),
+                actual
+            )),
+            (Some((number, expected)), None) => Outcome::Failed(format!(
+                "{}: line {} expected `{}` but the outputs ended",
+                snapshot,
+                number + 1,
+                expected.trim()
+            )),
+            (None, Some(actual)) => Outcome::Failed(format!(
+                "{}: ended but got `{}`",
+                snapshot, actual
+            )),
+        };
+        return Ok(outcome);
+    }
+}
+
+/// Runs the logic through the trace and returns the lines of the snapshot
+fn replay(layout: &Layout, edges: &[Edge], log_level: Level) -> Result<Vec<String>, Box<dyn Error>> {
+    let end = edges.last().map(|edge| edge.time).unwrap_or(0) + TRAILING_TIME;
+    let serial = RefCell::new(Discard);
+    let log_sink = RefCell::new(Stdout);
+    model::run(layout, &serial, &log_sink, |model| {
+        model.log().set_level(None, log_level);
+        let mut lines = Vec::new();
+        let mut outputs = BTreeMap::new();
+        let mut edges = edges.iter().peekable();
+        for time in 0..=end {
+            while let Some(edge) = edges.next_if(|edge| edge.time <= time) {
+                if !model.set_sensor(edge.sensor, edge.active) {
+                    return Err(format!("the layout has no sensor {}", edge.sensor).into());
+                }
+            }
+            model.tick(time);
+
+            // outputs which did not change keep their line from before
+            for (name, value) in model.outputs() {
+                if outputs.get(&name) != Some(&value) {
+                    lines.push(format!("{} {} {}", time, name, value));
+                    outputs.insert(name, value);
+                }
+            }
+            for bytes in model.take_servo_writes() {
+                if let [id, angle] = bytes[..] {
+                    lines.push(format!("{} servo {} {}", time, id, angle));
+                }
+            }
+        }
+        Ok(lines)
+    })
+}
@@ -0,0 +1,338 @@
+//! Terminal dashboard showing a live map of the layout
+//!
+//! Sections are coloured by the number of cars inside, stoppers are marked locked or released
+//! and intersections show the light of every arm. Below the map the events scroll by. The events
+//! either come live from the controller or from a recorded session which is replayed.
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::io::{self, Stdout};
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::time::{Duration, Instant};
+
+use car_system::intersection::IntersectionActionLight;
+use car_system::intersection::IntersectionActionLight::*;
+use car_system::protocol::light_token;
+use crossterm::event::{self, Event as TerminalEvent, KeyCode};
+use crossterm::execute;
+use crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
+use tui::backend::CrosstermBackend;
+use tui::layout::{Constraint, Direction, Layout as Split, Rect};
+use tui::style::{Color, Modifier, Style};
+use tui::text::{Span, Spans};
+use tui::widgets::{Block, Borders, List, ListItem, Paragraph};
+use tui::{Frame, Terminal};
+
+use crate::layout::Layout;
+use crate::link::Line;
+use crate::session::Recorder;
+use crate::state::{self, State};
+
+/// Number of entries kept in the event log
+const LOG_LENGTH: usize = 500;
+
+type Backend = CrosstermBackend<Stdout>;
+
+/// Where the dashboard gets its lines from
+pub enum Source {
+    /// Lines received from the controller, optionally recorded to a session file
+    Live {
+        name: String,
+        lines: Receiver<Line>,
+        recorder: Option<Recorder>,
+        start: Instant,
+    },
+    /// Lines of a recorded session
+    Replay {
+        name: String,
+        lines: Vec<(u64, String)>,
+        next: usize,
+        /// Milliseconds into the session
+        position: f64,
+        speed: f64,
+        paused: bool,
+        last_tick: Instant,
+    },
+}
+
+impl Source {
+    /// Returns a source replaying the lines of a session
+    pub fn replay(name: &str, lines: Vec<(u64, String)>) -> Source {
+        Source::Replay {
+            name: name.to_string(),
+            lines,
+            next: 0,
+            position: 0.0,
+            speed: 1.0,
+            paused: false,
+            last_tick: Instant::now(),
+        }
+    }
+
+    /// Returns the lines which are due with their time in milliseconds
+    fn poll(&mut self) -> Result<Vec<(u64, Line)>, Box<dyn Error>> {
+        let mut due = Vec::new();
+        match self {
+            Source::Live {
+                lines,
+                recorder,
+                start,
+                ..
+            } => loop {
+                match lines.try_recv() {
+                    Ok(line) => {
+                        if let Some(recorder) = recorder {
+                            recorder.record(&line.text())?;
+                        }
+                        due.push((start.elapsed().as_millis() as u64, line));
+                    }
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => return Err("connection lost".into()),
+                }
+            },
+            Source::Replay {
+                lines,
+                next,
+                position,
+                speed,
+                paused,
+                last_tick,
+                ..
+            } => {
+                if !*paused {
+                    *position += last_tick.elapsed().as_secs_f64() * 1_000.0 * *speed;
+                }
+                *last_tick = Instant::now();
+                while let Some((time, text)) = lines.get(*next) {
+                    if *time as f64 > *position {
+                        break;
+                    }
+                    due.push((*time, Line::parse(text)));
+                    *next += 1;
+                }
+            }
+        }
+        Ok(due)
+    }
+
+    /// Returns the text of the status bar
+    fn status(&self) -> String {
+        match self {
+            Source::Live { name, recorder, .. } => match recorder {
+                Some(_) => format!("live: {} (recording)", name),
+                None => format!("live: {}", name),
+            },
+            Source::Replay {
+                name,
+                lines,
+                position,
+                speed,
+                paused,
+                ..
+            } => {
+                let length = lines.last().map(|(time, _)| *time).unwrap_or(0);
+                format!(
+                    "replay: {} {:.1} s / {:.1} s x{}{}",
+                    name,
+                    position.min(length as f64) / 1_000.0,
+                    length as f64 / 1_000.0,
+                    speed,
+                    if *paused { " (paused)" } else { "" }
+                )
+            }
+        }
+    }
+
+    /// Handles the keys which control a replay
+    fn key(&mut self, code: KeyCode) {
+        if let Source::Replay { speed, paused, .. } = self {
+            match code {
+                KeyCode::Char(' ') => *paused = !*paused,
+                KeyCode::Char('+') => *speed = (*speed * 2.0).min(64.0),
+                KeyCode::Char('-') => *speed = (*speed / 2.0).max(1.0 / 64.0),
+                _ => (),
+            }
+        }
+    }
+}
+
+/// Restores the terminal when the dashboard ends, also on errors
+struct TerminalGuard;
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = terminal::disable_raw_mode();
+        let _ = execute!(io::stdout(), LeaveAlternateScreen);
+    }
+}
+
+/// Runs the dashboard until `q` is pressed
+pub fn run(layout: &Layout, mut source: Source) -> Result<(), Box<dyn Error>> {
+    terminal::enable_raw_mode()?;
+    let _guard = TerminalGuard;
+    execute!(io::stdout(), EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+
+    let mut state = State::default();
+    let mut log: VecDeque<String> = VecDeque::new();
+    let mut verbose = false;
+
+    loop {
+        for (time, line) in source.poll()? {
+            let entry = match &line {
+                Line::Event(event) => {
+                    state.apply(event);
+                    state::describe(event)
+                }
+                Line::History(time, event) => {
+                    format!("history {} ms: {}", time, state::describe(event))
+                }
+                Line::Text(text) if verbose => format!("| {}", text),
+                Line::Text(_) => continue,
+            };
+            log.push_back(format!("[{:>8.1} s] {}", time as f64 / 1_000.0, entry));
+            if log.len() > LOG_LENGTH {
+                log.pop_front();
+            }
+        }
+
+        let status = source.status();
+        terminal.draw(|frame| draw(frame, layout, &state, &log, &status))?;
+
+        if event::poll(Duration::from_millis(50))? {
+            if let TerminalEvent::Key(key) = event::read()? {
+                match key.code {
+                    KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                    KeyCode::Char('v') => verbose = !verbose,
+                    code => source.key(code),
+                }
+            }
+        }
+    }
+}
+
+fn draw(frame: &mut Frame<Backend>, layout: &Layout, state: &State, log: &VecDeque<String>, status: &str) {
+    let areas = Split::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Percentage(65),
+            Constraint::Min(5),
+            Constraint::Length(1),
+        ])
+        .split(frame.size());
+
+    draw_map(frame, areas[0], layout, state);
+
+    // show the newest entries which fit into the log
+    let height = areas[1].height.saturating_sub(2) as usize;
+    let entries: Vec<ListItem> = log
+        .iter()
+        .skip(log.len().saturating_sub(height))
+        .map(|entry| ListItem::new(entry.as_str()))
+        .collect();
+    frame.render_widget(
+        List::new(entries).block(Block::default().borders(Borders::ALL).title("events")),
+        areas[1],
+    );
+
+    let mut status_line = vec![Span::raw(status)];
+    if state.emergency_stop {
+        status_line.insert(
+            0,
+            Span::styled(
+                "EMERGENCY STOP ",
+                Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+            ),
+        );
+    }
+    status_line.push(Span::styled(
+        "  q quit  v debug output  space pause  +/- speed",
+        Style::default().fg(Color::DarkGray),
+    ));
+    frame.render_widget(Paragraph::new(Spans::from(status_line)), areas[2]);
+}
+
+fn draw_map(frame: &mut Frame<Backend>, area: Rect, layout: &Layout, state: &State) {
+    let positions = layout.positions();
+    let rows = positions.iter().map(|(row, _)| row + 1).max().unwrap_or(1);
+    let columns = positions.iter().map(|(_, column)| column + 1).max().unwrap_or(1);
+    let cell = |(row, column): (u16, u16)| Rect {
+        x: area.x + column * (area.width / columns),
+        y: area.y + row * (area.height / rows),
+        width: area.width / columns,
+        height: area.height / rows,
+    };
+    let mut positions = positions.into_iter();
+
+    for section in &layout.sections {
+        let locks = state.sections.get(&section.id).copied();
+        let color = match locks {
+            None => Color::DarkGray,
+            Some(0) => Color::Green,
+            Some(1) => Color::Yellow,
+            Some(locks) if locks > 1 => Color::Red,
+            // a car left which never entered
+            Some(_) => Color::Magenta,
+        };
+        let mut lines = vec![Spans::from(match locks {
+            Some(locks) => format!("cars: {}", locks),
+            None => "cars: ?".to_string(),
+        })];
+        for stopper in &section.stoppers {
+            lines.push(stopper_line(state, *stopper, "stopper"));
+        }
+        lines.push(Spans::from(Span::styled(
+            format!("sensors {:?} -> {:?}", section.start_sensors, section.end_sensors),
+            Style::default().fg(Color::DarkGray),
+        )));
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(color))
+            .title(format!("section {}", section.id));
+        frame.render_widget(Paragraph::new(lines).block(block), cell(positions.next().unwrap()));
+    }
+
+    for intersection in &layout.intersections {
+        let mut lines = Vec::new();
+        for (arm, stopper) in intersection.arms.iter().enumerate() {
+            let light = state.lights.get(&(intersection.id, arm as u8));
+            lines.push(Spans::from(vec![
+                Span::raw(format!("arm {} ", arm)),
+                match light {
+                    Some(light) => Span::styled(
+                        format!("● {:<3}", light_token(light)),
+                        Style::default().fg(light_color(light)),
+                    ),
+                    None => Span::raw("? "),
+                },
+            ]));
+            lines.push(stopper_line(state, *stopper, "  entry"));
+        }
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .title(format!("intersection {}", intersection.id));
+        frame.render_widget(Paragraph::new(lines).block(block), cell(positions.next().unwrap()));
+    }
+}
+
+fn stopper_line(state: &State, stopper: u8, label: &str) -> Spans<'static> {
+    let (text, color) = match state.stoppers.get(&stopper) {
+        Some(true) => ("locked", Color::Red),
+        Some(false) => ("released", Color::Green),
+        None => ("?", Color::DarkGray),
+    };
+    Spans::from(vec![
+        Span::raw(format!("{} {}: ", label, stopper)),
+        Span::styled(text, Style::default().fg(color)),
+    ])
+}
+
+fn light_color(light: &IntersectionActionLight) -> Color {
+    match light {
+        Green(_) => Color::Green,
+        Yellow | FlashingYellow => Color::Yellow,
+        Red => Color::Red,
+        RedYellow => Color::LightRed,
+        Off => Color::DarkGray,
+    }
+}
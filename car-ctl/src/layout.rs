@@ -0,0 +1,1086 @@
+//! Description of the track topology read from a layout file
+//!
+//! Every line describes one section or intersection, `#` starts a comment:
+//!
+//! ```text
+//! section 1 at 0 0 stoppers 1 start 3 2 end 5 6
+//! intersection 1 at 2 1 arms 8 9 10
+//! ```
+//!
+//! `at <row> <column>` places the element on the map of the dashboard, elements without a
+//! position are placed after the others. The `arms` of an intersection are given by their entry
+//! stoppers, their index starts at 0.
+//!
+//! An intersection without a plan is the T-junction preset with the arms left, right and upper.
+//! Otherwise every `phase` line adds a phase with its seconds and the light of each arm, and the
+//! movements which may have green together are listed with `allow`:
+//!
+//! ```text
+//! intersection 2 arms 11 12
+//! phase 2 10 GR R
+//! phase 2 2 Y RY
+//! phase 2 10 R GL
+//! phase 2 2 RY Y
+//! allow 2 0 GR 1 GL
+//! ```
+//!
+//! A `clearance` line holds new greens back for the given seconds while conflicting movements clear
+//! the intersection, and after `box` until its sensors inside the intersection detect no car:
+//!
+//! ```text
+//! clearance 2 1.5 box 16 17
+//! ```
+//!
+//! The servos of an intersection get a settle time before their arms turn green, and a timeout
+//! after which a servo which can't be commanded is a fault, 2 seconds if not given:
+//!
+//! ```text
+//! servos 2 settle 0.5 timeout 3
+//! ```
+//!
+//! The lights are `GR`, `GL` (green to the right or left), `Y`, `R`, `RY` and `OFF`. Times are
+//! given in seconds with up to three decimals, like `0.5` for a short all-red phase.
+//!
+//! An intersection runs its green phases by demand with an `actuated` line giving the minimum
+//! and maximum green and the gap in seconds and optionally the number of the rest phase, counted
+//! from 1. The sensors reporting the cars arriving on an arm are given with `approach`:
+//!
+//! ```text
+//! actuated 2 min 5 max 30 gap 3 rest 1
+//! approach 2 0 13
+//! approach 2 1 14 15
+//! ```
+//!
+//! A `night` line sets up the night mode with flashing yellow lights, switched on by the `night`
+//! command or from and to the given seconds of the clock. The arms of the `main` road and the arms
+//! which `yield` are given with the green of the movement they let in, the T-junction preset uses
+//! the left and right arm as the main road if they are left out. Cars of a yielding arm get the
+//! `crossing` seconds, 3 if not given, to cross the intersection:
+//!
+//! ```text
+//! night 2 from 600 to 900 crossing 2 main 0 GR yield 1 GL
+//! ```
+//!
+//! The seconds `from` and `to` repeat every day, which starts with the clock and lasts 24 hours or
+//! the seconds given with `day`. A night from a later to an earlier time spans the end of the day:
+//!
+//! ```text
+//! night 2 from 900 to 300 day 1200
+//! ```
+//!
+//! Every `crossing` line adds a pedestrian crossing with the arms whose cars pass it and the
+//! sensors which are its push buttons, numbered from 0. The `pedestrians` line sets the seconds of
+//! walk and of the flashing green after it, 5 and 3 if not given, and lets all crossings walk in an
+//! `exclusive` phase instead of together with the states:
+//!
+//! ```text
+//! crossing 2 arms 0 buttons 18
+//! pedestrians 2 walk 4 clearance 2 exclusive
+//! ```
+//!
+//! Intersections with a `coordination` line keep to a common cycle of the given seconds, which
+//! their phases have to last, and start it the `offset` in seconds after the clock, 0 if not given:
+//!
+//! ```text
+//! coordination 2 cycle 24 offset 8
+//! ```
+//!
+//! Emergency vehicles preempt the states with `preemption` lines, giving the movement and the
+//! sensors on its approach calling green for it, or `clear` and the sensors telling a vehicle
+//! left the intersection:
+//!
+//! ```text
+//! preemption 2 0 GR 19
+//! preemption 2 clear 20
+//! ```
+//!
+//! A `level` crossing of the road with a train line has the stoppers of the road, the sensors on
+//! the line where trains `approach` and `exit` and the ids of the servos of its `barriers`. The
+//! lights flash for the `warning` seconds before the barriers lower, they rise the `open` seconds
+//! after the exit sensor last fired and take the `rise` seconds to do so, 3, 2 and 2 if not given.
+//! Level crossings aren't shown on the map:
+//!
+//! ```text
+//! level 1 stoppers 21 22 approach 23 exit 24 barriers 8 9 warning 3 open 2 rise 2
+//! ```
+//!
+//! A `roundabout` has no lights, its cars yield to the circulating ones. Every `entry` line adds an
+//! entry in the direction the cars circulate with its entry stopper, the section of the circle
+//! upstream of it and optionally the sensors on its `approach`. An entry is released the `gap`
+//! seconds after the last car left that section, and an entry holding a waiting car for `wait`
+//! seconds holds the entry upstream of it until it was released, 1 and 20 if not given.
+//! Roundabouts aren't shown on the map:
+//!
+//! ```text
+//! roundabout 1 gap 1 wait 20
+//! entry 1 25 section 4 approach 26
+//! entry 1 27 section 5
+//! ```
+//!
+//! A `turnout` is moved by the servo with the id given with `servo`, it is locked while a car is in
+//! one of its `sections` and diverges `from` and `to` the given seconds of every day if given. A
+//! `route` sets the turnouts given by their id to `straight` or `diverging` together, both are
+//! commanded with `turnout <id> straight|diverging` and `route <id>`. Turnouts aren't shown on the
+//! map:
+//!
+//! ```text
+//! turnout 1 servo 10 sections 4 from 600 to 900
+//! route 1 1 diverging 2 straight
+//! ```
+
+use std::error::Error;
+use std::fs;
+
+use car_system::actuation::Actuation;
+use car_system::coordination::Coordination;
+use car_system::intersection::IntersectionActionLight::{self, *};
+use car_system::intersection::{MAX_ARMS, SERVO_TIMEOUT};
+use car_system::level_crossing::LevelCrossingTiming;
+use car_system::monitor::{ConflictMatrix, Movement};
+use car_system::night::NightMode;
+use car_system::pedestrian::{PedestrianTiming, MAX_CROSSINGS};
+use car_system::plan::{PhasePlan, PlanError, MAX_PHASES};
+use car_system::protocol::parse_light;
+use car_system::remote::MAX_STOPPERS;
+use car_system::roundabout::{RoundaboutTiming, MAX_ENTRIES};
+use car_system::schedule::Schedule;
+use car_system::time::{DailyWindow, DAY};
+use car_system::turnout::{TurnoutPosition, MAX_ROUTE_TURNOUTS};
+
+/// A section of the track
+pub struct SectionLayout {
+    pub id: u8,
+    pub position: Option<(u16, u16)>,
+    pub stoppers: Vec<u8>,
+    pub start_sensors: Vec<u8>,
+    pub end_sensors: Vec<u8>,
+}
+
+/// An intersection with the entry stopper of each arm
+pub struct IntersectionLayout {
+    pub id: u8,
+    pub position: Option<(u16, u16)>,
+    pub arms: Vec<u8>,
+    /// The phases with their milliseconds and lights, empty for the T-junction preset
+    pub phases: Vec<(u32, Vec<IntersectionActionLight>)>,
+    /// The movements which may have green together
+    pub allowed: Vec<(Movement, Movement)>,
+    /// The sensors on the approach of an arm as (arm index, sensor id)
+    pub approaches: Vec<(usize, u8)>,
+    /// The settings if the intersection is actuated
+    pub actuation: Option<Actuation>,
+    /// The milliseconds of the all-red clearance
+    pub clearance: u32,
+    /// The sensors inside the intersection
+    pub box_sensors: Vec<u8>,
+    /// The milliseconds the servos settle and may take to be commanded
+    pub servo_timing: (u32, u32),
+    /// The settings of the night mode as given, without the arms for the T-junction preset
+    night: Option<NightMode>,
+    /// The pedestrian crossings as the arms passing them and the sensors of their push buttons
+    pub crossings: Vec<(Vec<usize>, Vec<u8>)>,
+    /// How the pedestrian crossings walk
+    pub pedestrian_timing: PedestrianTiming,
+    /// The common cycle the intersection keeps to
+    pub coordination: Option<Coordination>,
+    /// The sensors calling green for emergency vehicles as (movement, sensor id)
+    pub preemption_calls: Vec<(Movement, u8)>,
+    /// The sensors telling an emergency vehicle cleared the intersection
+    pub preemption_clears: Vec<u8>,
+}
+
+/// A level crossing of the road with a train line
+pub struct LevelCrossingLayout {
+    pub id: u8,
+    /// The stoppers of the road
+    pub stoppers: Vec<u8>,
+    /// The sensors reporting trains approaching
+    pub approach_sensors: Vec<u8>,
+    /// The sensors reporting trains leaving
+    pub exit_sensors: Vec<u8>,
+    /// The ids of the servos of the barriers
+    pub barriers: Vec<u8>,
+    pub timing: LevelCrossingTiming,
+}
+
+impl LevelCrossingLayout {
+    /// Parses the words of a `level` line after the id
+    fn parse<'a>(id: u8, mut words: impl Iterator<Item = &'a str>) -> Result<Self, String> {
+        let mut level_crossing = LevelCrossingLayout {
+            id,
+            stoppers: Vec::new(),
+            approach_sensors: Vec::new(),
+            exit_sensors: Vec::new(),
+            barriers: Vec::new(),
+            timing: LevelCrossingTiming::default(),
+        };
+        // the list the numbers are added to
+        let mut list = None;
+        while let Some(word) = words.next() {
+            match word {
+                "stoppers" => list = Some(&mut level_crossing.stoppers),
+                "approach" => list = Some(&mut level_crossing.approach_sensors),
+                "exit" => list = Some(&mut level_crossing.exit_sensors),
+                "barriers" => list = Some(&mut level_crossing.barriers),
+                "warning" => level_crossing.timing.warning = parse_seconds(words.next())?,
+                "open" => level_crossing.timing.open_time = parse_seconds(words.next())?,
+                "rise" => level_crossing.timing.rise = parse_seconds(words.next())?,
+                number => match list.as_mut() {
+                    Some(list) => list.push(parse_number(Some(number))?),
+                    None => return Err(format!("unexpected `{}`", number)),
+                },
+            }
+        }
+        if level_crossing.approach_sensors.is_empty() || level_crossing.exit_sensors.is_empty() {
+            return Err("a level crossing needs `approach` and `exit` sensors".to_string());
+        }
+        if level_crossing.stoppers.len() > 2 || level_crossing.barriers.len() > 2 {
+            return Err("no more than two stoppers and barriers allowed".to_string());
+        }
+        Ok(level_crossing)
+    }
+}
+
+/// A roundabout with its entries
+pub struct RoundaboutLayout {
+    pub id: u8,
+    /// The entries in the direction the cars circulate
+    pub entries: Vec<EntryLayout>,
+    pub timing: RoundaboutTiming,
+}
+
+/// An entry of a roundabout
+pub struct EntryLayout {
+    pub stopper: u8,
+    /// The id of the section of the circle upstream of the entry
+    pub section: u8,
+    /// The sensors on the approach
+    pub approach_sensors: Vec<u8>,
+}
+
+impl RoundaboutLayout {
+    /// Parses the words of a `roundabout` line after the id
+    fn parse<'a>(id: u8, mut words: impl Iterator<Item = &'a str>) -> Result<Self, String> {
+        let mut timing = RoundaboutTiming::default();
+        while let Some(word) = words.next() {
+            match word {
+                "gap" => timing.gap = parse_seconds(words.next())?,
+                "wait" => timing.max_wait = parse_seconds(words.next())?,
+                word => return Err(format!("unexpected `{}`", word)),
+            }
+        }
+        Ok(RoundaboutLayout {
+            id,
+            entries: Vec::new(),
+            timing,
+        })
+    }
+
+    fn parse_entry<'a>(&mut self, mut words: impl Iterator<Item = &'a str>) -> Result<(), String> {
+        if self.entries.len() == MAX_ENTRIES {
+            return Err(format!("no more than {} entries allowed", MAX_ENTRIES));
+        }
+        let stopper = parse_number(words.next())?;
+        let section = match words.next() {
+            Some("section") => parse_number(words.next())?,
+            _ => return Err("an entry needs the `section` upstream of it".to_string()),
+        };
+        let mut approach_sensors = Vec::new();
+        match words.next() {
+            Some("approach") => {
+                let mut sensors = words.peekable();
+                if sensors.peek().is_none() {
+                    return Err("an approach needs a sensor".to_string());
+                }
+                for sensor in sensors {
+                    approach_sensors.push(parse_number(Some(sensor))?);
+                }
+            }
+            Some(word) => return Err(format!("unexpected `{}`", word)),
+            None => (),
+        }
+        self.entries.push(EntryLayout {
+            stopper,
+            section,
+            approach_sensors,
+        });
+        Ok(())
+    }
+}
+
+/// A turnout moved by a servo
+pub struct TurnoutLayout {
+    pub id: u8,
+    /// The id of the servo
+    pub servo: u8,
+    /// The ids of the sections covering the switch
+    pub sections: Vec<u8>,
+    /// The time of every day the turnout diverges
+    pub schedule: Option<DailyWindow>,
+}
+
+impl TurnoutLayout {
+    /// Parses the words of a `turnout` line after the id
+    fn parse<'a>(id: u8, mut words: impl Iterator<Item = &'a str>) -> Result<Self, String> {
+        let (mut servo, mut sections) = (None, Vec::new());
+        let (mut from, mut to, mut day) = (None, None, None);
+        // if the sections are being listed
+        let mut listing = false;
+        while let Some(word) = words.next() {
+            match (word, listing) {
+                ("sections", _) => listing = true,
+                ("servo", _) => servo = Some(parse_number(words.next())?),
+                ("from", _) => from = Some(parse_seconds(words.next())? as u64),
+                ("to", _) => to = Some(parse_seconds(words.next())? as u64),
+                ("day", _) => day = Some(parse_seconds(words.next())? as u64),
+                (number, true) => sections.push(parse_number(Some(number))?),
+                (word, false) => return Err(format!("unexpected `{}`", word)),
+            }
+        }
+        if sections.len() > 2 {
+            return Err("no more than two sections allowed".to_string());
+        }
+        Ok(TurnoutLayout {
+            id,
+            servo: servo.ok_or("a turnout needs a `servo`")?,
+            sections,
+            schedule: parse_window(from, to, day)?,
+        })
+    }
+}
+
+/// A route with the positions of its turnouts
+pub struct RouteLayout {
+    pub id: u8,
+    /// The turnouts as (turnout id, position)
+    pub turnouts: Vec<(u8, TurnoutPosition)>,
+}
+
+impl RouteLayout {
+    /// Parses the words of a `route` line after the id
+    fn parse<'a>(id: u8, mut words: impl Iterator<Item = &'a str>) -> Result<Self, String> {
+        let mut turnouts = Vec::new();
+        while let Some(word) = words.next() {
+            let turnout = parse_number(Some(word))?;
+            let position = match words.next() {
+                Some("straight") => TurnoutPosition::Straight,
+                Some("diverging") => TurnoutPosition::Diverging,
+                _ => return Err("a turnout is `straight` or `diverging`".to_string()),
+            };
+            turnouts.push((turnout, position));
+        }
+        if turnouts.is_empty() {
+            return Err("a route needs a turnout".to_string());
+        }
+        if turnouts.len() > MAX_ROUTE_TURNOUTS {
+            return Err(format!("no more than {} turnouts allowed", MAX_ROUTE_TURNOUTS));
+        }
+        Ok(RouteLayout { id, turnouts })
+    }
+}
+
+impl IntersectionLayout {
+    /// Returns the phase plan and the conflict matrix of the intersection
+    pub fn plan(&self) -> (PhasePlan, ConflictMatrix) {
+        if self.phases.is_empty() {
+            return (PhasePlan::T_JUNCTION, ConflictMatrix::T_JUNCTION);
+        }
+        let mut plan = PhasePlan::new();
+        for (duration, lights) in &self.phases {
+            plan.push(lights, *duration);
+        }
+        let mut conflicts = ConflictMatrix::new();
+        for (first, second) in &self.allowed {
+            conflicts = conflicts.allow(*first, *second);
+        }
+        (plan, conflicts)
+    }
+
+    /// Returns the settings of the night mode if the intersection has one
+    pub fn night_mode(&self) -> Option<NightMode> {
+        let night_mode = self.night?;
+        if self.phases.is_empty() && night_mode.directions.iter().all(Option::is_none) {
+            return Some(NightMode {
+                crossing_time: night_mode.crossing_time,
+                schedule: night_mode.schedule,
+                ..NightMode::T_JUNCTION
+            });
+        }
+        Some(night_mode)
+    }
+
+    /// Checks the number of arms and the plan
+    fn validate(&self) -> Result<(), String> {
+        if self.phases.is_empty() && self.arms.len() != 3 {
+            let error = "an intersection without a plan needs the left, right and upper arm";
+            return Err(error.to_string());
+        }
+        if !(2..=MAX_ARMS).contains(&self.arms.len()) {
+            return Err(format!("an intersection needs 2 to {} arms", MAX_ARMS));
+        }
+        // the plan can only be built once the number of arms is checked
+        if let Some(coordination) = self.coordination {
+            let cycle = self.plan().0.cycle();
+            if cycle != coordination.cycle() {
+                return Err(format!(
+                    "the phases last {} s but the cycle of the coordination {} s",
+                    format_seconds(cycle),
+                    format_seconds(coordination.cycle())
+                ));
+            }
+        }
+        if self.phases.is_empty() {
+            return Ok(());
+        }
+        let (plan, conflicts) = self.plan();
+        if let Some(actuation) = self.actuation {
+            if actuation.min_green > actuation.max_green {
+                return Err("the minimum green is longer than the maximum".to_string());
+            }
+            if let Some(phase) = actuation.rest_phase.filter(|phase| *phase >= plan.len()) {
+                return Err(format!("the plan has no phase {}", phase + 1));
+            }
+        }
+        if let Some(night_mode) = self.night_mode() {
+            let main_road = (0..self.arms.len()).filter_map(|arm| {
+                let direction = night_mode.directions[arm].filter(|_| night_mode.main_road[arm]);
+                direction.map(|direction| Movement::new(arm, direction))
+            });
+            if main_road.clone().next().is_none() {
+                return Err("the night mode needs an arm of the main road".to_string());
+            }
+            for first in main_road.clone() {
+                for second in main_road.clone().filter(|second| second.arm > first.arm) {
+                    if conflicts.conflicts(first, second) {
+                        return Err(format!(
+                            "the main road of the night mode has conflicting movements of arm {} \
+                             and {}",
+                            first.arm, second.arm
+                        ));
+                    }
+                }
+            }
+        }
+        plan.validate(self.arms.len(), &conflicts).map_err(|error| match error {
+            PlanError::Empty | PlanError::TooManyArms => unreachable!(),
+            PlanError::ZeroDuration(phase) => format!("phase {} lasts no time", phase + 1),
+            PlanError::Conflict(phase, first, second) => format!(
+                "phase {} gives green to conflicting movements of arm {} and {}",
+                phase + 1,
+                first,
+                second
+            ),
+            PlanError::Transition(phase, arm) => format!(
+                "arm {} can't switch to its light of phase {}, green has to go over yellow to \
+                 red and red over red-yellow to green",
+                arm,
+                phase + 1
+            ),
+        })
+    }
+
+    fn parse_phase<'a>(&mut self, mut words: impl Iterator<Item = &'a str>) -> Result<(), String> {
+        if self.phases.len() == MAX_PHASES {
+            return Err(format!("no more than {} phases allowed", MAX_PHASES));
+        }
+        let duration = parse_seconds(words.next())?;
+        let lights = words.map(parse_light_word).collect::<Result<Vec<_>, _>>()?;
+        if lights.len() != self.arms.len() {
+            return Err(format!("a phase needs a light for each of the {} arms", self.arms.len()));
+        }
+        self.phases.push((duration, lights));
+        Ok(())
+    }
+
+    fn parse_allow<'a>(&mut self, mut words: impl Iterator<Item = &'a str>) -> Result<(), String> {
+        let mut movement = || -> Result<Movement, String> {
+            let arm: usize = parse_number(words.next())?;
+            if arm >= self.arms.len() {
+                return Err(format!("there is no arm {}", arm));
+            }
+            match parse_light_word(words.next().unwrap_or_default())? {
+                Green(direction) => Ok(Movement::new(arm, direction)),
+                _ => Err("a movement is given by its green `GR` or `GL`".to_string()),
+            }
+        };
+        let first = movement()?;
+        let second = movement()?;
+        if words.next().is_some() {
+            return Err("`allow` takes two movements".to_string());
+        }
+        self.allowed.push((first, second));
+        Ok(())
+    }
+
+    fn parse_approach<'a>(&mut self, mut words: impl Iterator<Item = &'a str>) -> Result<(), String> {
+        let arm: usize = parse_number(words.next())?;
+        if arm >= self.arms.len() {
+            return Err(format!("there is no arm {}", arm));
+        }
+        let mut sensors = words.peekable();
+        if sensors.peek().is_none() {
+            return Err("an approach needs a sensor".to_string());
+        }
+        for sensor in sensors {
+            self.approaches.push((arm, parse_number(Some(sensor))?));
+        }
+        Ok(())
+    }
+
+    fn parse_clearance<'a>(&mut self, mut words: impl Iterator<Item = &'a str>) -> Result<(), String> {
+        self.clearance = parse_seconds(words.next())?;
+        match words.next() {
+            Some("box") => (),
+            Some(word) => return Err(format!("unexpected `{}`", word)),
+            None => return Ok(()),
+        }
+        let mut sensors = words.peekable();
+        if sensors.peek().is_none() {
+            return Err("a box needs a sensor".to_string());
+        }
+        for sensor in sensors {
+            self.box_sensors.push(parse_number(Some(sensor))?);
+        }
+        Ok(())
+    }
+
+    fn parse_servos<'a>(&mut self, mut words: impl Iterator<Item = &'a str>) -> Result<(), String> {
+        while let Some(word) = words.next() {
+            match word {
+                "settle" => self.servo_timing.0 = parse_seconds(words.next())?,
+                "timeout" => self.servo_timing.1 = parse_seconds(words.next())?,
+                word => return Err(format!("unexpected `{}`", word)),
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_crossing<'a>(&mut self, words: impl Iterator<Item = &'a str>) -> Result<(), String> {
+        if self.crossings.len() == MAX_CROSSINGS {
+            return Err(format!("no more than {} crossings allowed", MAX_CROSSINGS));
+        }
+        let (mut arms, mut buttons) = (Vec::new(), Vec::new());
+        // the list the numbers are added to
+        let mut list = None;
+        for word in words {
+            match (word, list) {
+                ("arms", _) => list = Some(false),
+                ("buttons", _) => list = Some(true),
+                (number, Some(false)) => {
+                    let arm: usize = parse_number(Some(number))?;
+                    if arm >= self.arms.len() {
+                        return Err(format!("there is no arm {}", arm));
+                    }
+                    arms.push(arm);
+                }
+                (number, Some(true)) => buttons.push(parse_number(Some(number))?),
+                (word, None) => return Err(format!("unexpected `{}`", word)),
+            }
+        }
+        if arms.is_empty() || buttons.is_empty() {
+            return Err("a crossing needs `arms` and `buttons`".to_string());
+        }
+        self.crossings.push((arms, buttons));
+        Ok(())
+    }
+
+    fn parse_pedestrians<'a>(&mut self, mut words: impl Iterator<Item = &'a str>) -> Result<(), String> {
+        while let Some(word) = words.next() {
+            match word {
+                "walk" => self.pedestrian_timing.walk = parse_seconds(words.next())?,
+                "clearance" => self.pedestrian_timing.clearance = parse_seconds(words.next())?,
+                "exclusive" => self.pedestrian_timing.exclusive = true,
+                word => return Err(format!("unexpected `{}`", word)),
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_coordination<'a>(&mut self, mut words: impl Iterator<Item = &'a str>) -> Result<(), String> {
+        let (mut cycle, mut offset) = (None, 0);
+        while let Some(word) = words.next() {
+            match word {
+                "cycle" => cycle = Some(parse_seconds(words.next())?),
+                "offset" => offset = parse_seconds(words.next())?,
+                word => return Err(format!("unexpected `{}`", word)),
+            }
+        }
+        let cycle = cycle.ok_or("`coordination` needs `cycle`")?;
+        if cycle == 0 {
+            return Err("the cycle can't be 0".to_string());
+        }
+        let coordination = Coordination::new(cycle, offset);
+        self.coordination =
+            Some(coordination.ok_or("the offset has to be shorter than the cycle")?);
+        Ok(())
+    }
+
+    fn parse_preemption<'a>(&mut self, mut words: impl Iterator<Item = &'a str>) -> Result<(), String> {
+        let movement = match words.next() {
+            Some("clear") => None,
+            arm => {
+                let arm: usize = parse_number(arm)?;
+                if arm >= self.arms.len() {
+                    return Err(format!("there is no arm {}", arm));
+                }
+                match parse_light_word(words.next().unwrap_or_default())? {
+                    Green(direction) => Some(Movement::new(arm, direction)),
+                    _ => return Err("a movement is given by its green `GR` or `GL`".to_string()),
+                }
+            }
+        };
+        let mut sensors = words.peekable();
+        if sensors.peek().is_none() {
+            return Err("a preemption needs a sensor".to_string());
+        }
+        for sensor in sensors {
+            let sensor = parse_number(Some(sensor))?;
+            match movement {
+                Some(movement) => self.preemption_calls.push((movement, sensor)),
+                None => self.preemption_clears.push(sensor),
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_night<'a>(&mut self, mut words: impl Iterator<Item = &'a str>) -> Result<(), String> {
+        let mut night_mode = NightMode {
+            directions: [None; MAX_ARMS],
+            main_road: [false; MAX_ARMS],
+            ..NightMode::T_JUNCTION
+        };
+        let (mut from, mut to, mut day) = (None, None, None);
+        // if the arms being listed are the main road
+        let mut main_road = None;
+        while let Some(word) = words.next() {
+            match word {
+                "crossing" => night_mode.crossing_time = parse_seconds(words.next())?,
+                "from" => from = Some(parse_seconds(words.next())? as u64),
+                "to" => to = Some(parse_seconds(words.next())? as u64),
+                "day" => day = Some(parse_seconds(words.next())? as u64),
+                "main" => main_road = Some(true),
+                "yield" => main_road = Some(false),
+                number => {
+                    let main_road = main_road.ok_or(format!("unexpected `{}`", number))?;
+                    let arm: usize = parse_number(Some(number))?;
+                    if arm >= self.arms.len() {
+                        return Err(format!("there is no arm {}", arm));
+                    }
+                    match parse_light_word(words.next().unwrap_or_default())? {
+                        Green(direction) => night_mode.directions[arm] = Some(direction),
+                        _ => return Err("a movement is given by its green `GR` or `GL`".to_string()),
+                    }
+                    night_mode.main_road[arm] = main_road;
+                }
+            }
+        }
+        night_mode.schedule = parse_window(from, to, day)?;
+        self.night = Some(night_mode);
+        Ok(())
+    }
+
+    fn parse_actuated<'a>(&mut self, mut words: impl Iterator<Item = &'a str>) -> Result<(), String> {
+        let (mut min_green, mut max_green, mut gap, mut rest_phase) = (None, None, None, None);
+        while let Some(word) = words.next() {
+            match word {
+                "min" => min_green = Some(parse_seconds(words.next())?),
+                "max" => max_green = Some(parse_seconds(words.next())?),
+                "gap" => gap = Some(parse_seconds(words.next())?),
+                "rest" => match parse_number::<usize>(words.next())? {
+                    0 => return Err("the phases are counted from 1".to_string()),
+                    phase => rest_phase = Some(phase - 1),
+                },
+                word => return Err(format!("unexpected `{}`", word)),
+            }
+        }
+        let (min_green, max_green, gap) = match (min_green, max_green, gap) {
+            (Some(min_green), Some(max_green), Some(gap)) => (min_green, max_green, gap),
+            _ => return Err("`actuated` needs `min`, `max` and `gap`".to_string()),
+        };
+        let actuation = Actuation {
+            min_green,
+            max_green,
+            gap,
+            rest_phase,
+        };
+        self.actuation = Some(actuation);
+        Ok(())
+    }
+}
+
+/// The sections, intersections, level crossings, roundabouts, turnouts and routes of the layout
+#[derive(Default)]
+pub struct Layout {
+    pub sections: Vec<SectionLayout>,
+    pub intersections: Vec<IntersectionLayout>,
+    pub level_crossings: Vec<LevelCrossingLayout>,
+    pub roundabouts: Vec<RoundaboutLayout>,
+    pub turnouts: Vec<TurnoutLayout>,
+    pub routes: Vec<RouteLayout>,
+}
+
+impl Layout {
+    /// Reads the layout file at the given path
+    pub fn load(path: &str) -> Result<Layout, Box<dyn Error>> {
+        let text = fs::read_to_string(path)?;
+        Layout::parse(&text).map_err(|error| format!("{}: {}", path, error).into())
+    }
+
+    /// Parses the contents of a layout file
+    pub fn parse(text: &str) -> Result<Layout, String> {
+        let mut layout = Layout::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            if line.trim().is_empty() {
+                continue;
+            }
+            layout
+                .parse_line(line)
+                .and_then(|_| layout.check_roles())
+                .map_err(|error| format!("line {}: {}", number + 1, error))?;
+        }
+        for intersection in &layout.intersections {
+            intersection
+                .validate()
+                .map_err(|error| format!("intersection {}: {}", intersection.id, error))?;
+        }
+        for roundabout in &layout.roundabouts {
+            if roundabout.entries.is_empty() {
+                return Err(format!("roundabout {}: a roundabout needs an entry", roundabout.id));
+            }
+            for entry in &roundabout.entries {
+                if !layout.sections.iter().any(|section| section.id == entry.section) {
+                    return Err(format!(
+                        "roundabout {}: there is no section {}",
+                        roundabout.id, entry.section
+                    ));
+                }
+            }
+        }
+        for turnout in &layout.turnouts {
+            for id in &turnout.sections {
+                if !layout.sections.iter().any(|section| section.id == *id) {
+                    return Err(format!("turnout {}: there is no section {}", turnout.id, id));
+                }
+            }
+        }
+        for route in &layout.routes {
+            for (id, _) in &route.turnouts {
+                if !layout.turnouts.iter().any(|turnout| turnout.id == *id) {
+                    return Err(format!("route {}: there is no turnout {}", route.id, id));
+                }
+            }
+        }
+        let mut cycles = layout.intersections.iter().filter_map(|intersection| {
+            let coordination = intersection.coordination?;
+            Some(coordination.cycle())
+        });
+        if let Some(cycle) = cycles.next() {
+            if cycles.any(|other| other != cycle) {
+                return Err("the coordinated intersections need a common cycle".to_string());
+            }
+        }
+        // the host locks and releases the stoppers through the remote
+        if layout.stopper_ids().len() > MAX_STOPPERS {
+            return Err(format!("no more than {} stoppers allowed", MAX_STOPPERS));
+        }
+        Ok(layout)
+    }
+
+    /// Returns the position of every element on the map as (row, column)
+    ///
+    /// Sections come first, then intersections, each in the order of the file
+    pub fn positions(&self) -> Vec<(u16, u16)> {
+        let given = self
+            .sections
+            .iter()
+            .map(|section| section.position)
+            .chain(self.intersections.iter().map(|intersection| intersection.position));
+        let next_row = given
+            .clone()
+            .flatten()
+            .map(|(row, _)| row + 1)
+            .max()
+            .unwrap_or(0);
+        let mut unplaced = 0;
+        given
+            .map(|position| {
+                position.unwrap_or_else(|| {
+                    unplaced += 1;
+                    (next_row + (unplaced - 1) / 3, (unplaced - 1) % 3)
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the ids of all stoppers of the sections, intersections, level crossings and
+    /// roundabouts in ascending order
+    pub fn stopper_ids(&self) -> Vec<u8> {
+        let sections = self.sections.iter().flat_map(|section| &section.stoppers);
+        let arms = self.intersections.iter().flat_map(|intersection| &intersection.arms);
+        let roads = self.level_crossings.iter().flat_map(|level| &level.stoppers);
+        let entries = self.roundabouts.iter().flat_map(|roundabout| &roundabout.entries);
+        sorted(sections.chain(arms).chain(roads).chain(entries.map(|entry| &entry.stopper)))
+    }
+
+    /// Returns the ids of all sensors of the sections, approaches, boxes, push buttons,
+    /// preemptions, train lines and roundabout entries in ascending order
+    pub fn sensor_ids(&self) -> Vec<u8> {
+        let sections = self.sections.iter().flat_map(|section| {
+            section.start_sensors.iter().chain(&section.end_sensors)
+        });
+        let approaches = self.intersections.iter().flat_map(|intersection| {
+            let approaches = intersection.approaches.iter().map(|(_, sensor)| sensor);
+            let buttons = intersection.crossings.iter().flat_map(|(_, buttons)| buttons);
+            let calls = intersection.preemption_calls.iter().map(|(_, sensor)| sensor);
+            approaches
+                .chain(&intersection.box_sensors)
+                .chain(buttons)
+                .chain(calls)
+                .chain(&intersection.preemption_clears)
+        });
+        let lines = self.level_crossings.iter().flat_map(|level| {
+            level.approach_sensors.iter().chain(&level.exit_sensors)
+        });
+        let entries = self.roundabouts.iter().flat_map(|roundabout| &roundabout.entries);
+        let entries = entries.flat_map(|entry| &entry.approach_sensors);
+        sorted(sections.chain(approaches).chain(lines).chain(entries))
+    }
+
+    /// Checks that no sensor takes more than two roles of a kind, a sensor only has room for two
+    fn check_roles(&self) -> Result<(), String> {
+        let intersections = || self.intersections.iter();
+        let entries = self.roundabouts.iter().flat_map(|roundabout| &roundabout.entries);
+        let roles: [(&str, Vec<&u8>); 7] = [
+            ("section starts", self.sections.iter().flat_map(|s| &s.start_sensors).collect()),
+            ("section ends", self.sections.iter().flat_map(|s| &s.end_sensors).collect()),
+            (
+                "approaches",
+                intersections()
+                    .flat_map(|intersection| intersection.approaches.iter().map(|(_, id)| id))
+                    .chain(entries.flat_map(|entry| &entry.approach_sensors))
+                    .collect(),
+            ),
+            ("boxes", intersections().flat_map(|i| &i.box_sensors).collect()),
+            (
+                "crossings",
+                intersections()
+                    .flat_map(|intersection| &intersection.crossings)
+                    .flat_map(|(_, buttons)| buttons)
+                    .collect(),
+            ),
+            (
+                "preemptions",
+                intersections()
+                    .flat_map(|intersection| {
+                        let calls = intersection.preemption_calls.iter().map(|(_, id)| id);
+                        calls.chain(&intersection.preemption_clears)
+                    })
+                    .collect(),
+            ),
+            (
+                "train lines",
+                self.level_crossings
+                    .iter()
+                    .flat_map(|level| level.approach_sensors.iter().chain(&level.exit_sensors))
+                    .collect(),
+            ),
+        ];
+        for (kind, mut ids) in roles {
+            ids.sort_unstable();
+            if let Some(ids) = ids.windows(3).find(|ids| ids[0] == ids[2]) {
+                return Err(format!("no more than two {} allowed for sensor {}", kind, ids[0]));
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_line(&mut self, line: &str) -> Result<(), String> {
+        let mut words = line.split_whitespace();
+        let kind = words.next().unwrap_or_default();
+        let id = parse_number(words.next())?;
+        if matches!(
+            kind,
+            "phase"
+                | "allow"
+                | "approach"
+                | "actuated"
+                | "clearance"
+                | "servos"
+                | "night"
+                | "crossing"
+                | "pedestrians"
+                | "coordination"
+                | "preemption"
+        ) {
+            let intersection = self
+                .intersections
+                .iter_mut()
+                .find(|intersection| intersection.id == id)
+                .ok_or(format!("no intersection {} before this line", id))?;
+            return match kind {
+                "phase" => intersection.parse_phase(words),
+                "allow" => intersection.parse_allow(words),
+                "approach" => intersection.parse_approach(words),
+                "actuated" => intersection.parse_actuated(words),
+                "clearance" => intersection.parse_clearance(words),
+                "servos" => intersection.parse_servos(words),
+                "night" => intersection.parse_night(words),
+                "crossing" => intersection.parse_crossing(words),
+                "pedestrians" => intersection.parse_pedestrians(words),
+                "coordination" => intersection.parse_coordination(words),
+                _ => intersection.parse_preemption(words),
+            };
+        }
+        match kind {
+            "level" => {
+                self.level_crossings.push(LevelCrossingLayout::parse(id, words)?);
+                return Ok(());
+            }
+            "roundabout" => {
+                self.roundabouts.push(RoundaboutLayout::parse(id, words)?);
+                return Ok(());
+            }
+            "turnout" => {
+                self.turnouts.push(TurnoutLayout::parse(id, words)?);
+                return Ok(());
+            }
+            "route" => {
+                self.routes.push(RouteLayout::parse(id, words)?);
+                return Ok(());
+            }
+            "entry" => {
+                let roundabout = self
+                    .roundabouts
+                    .iter_mut()
+                    .find(|roundabout| roundabout.id == id)
+                    .ok_or(format!("no roundabout {} before this line", id))?;
+                return roundabout.parse_entry(words);
+            }
+            _ => (),
+        }
+        let mut position = None;
+        let mut lists: Vec<(&str, Vec<u8>)> = Vec::new();
+        while let Some(word) = words.next() {
+            match word {
+                "at" => {
+                    position = Some((parse_number(words.next())?, parse_number(words.next())?));
+                }
+                "stoppers" | "start" | "end" | "arms" => lists.push((word, Vec::new())),
+                number => match lists.last_mut() {
+                    Some((_, list)) => list.push(parse_number(Some(number))?),
+                    None => return Err(format!("unexpected `{}`", number)),
+                },
+            }
+        }
+        let list = |name: &str| {
+            lists
+                .iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, list)| list.clone())
+                .unwrap_or_default()
+        };
+
+        match kind {
+            "section" => {
+                let section = SectionLayout {
+                    id,
+                    position,
+                    stoppers: list("stoppers"),
+                    start_sensors: list("start"),
+                    end_sensors: list("end"),
+                };
+                let lists = [&section.stoppers, &section.start_sensors, &section.end_sensors];
+                if lists.iter().any(|list| list.len() > 2) {
+                    return Err("no more than two stoppers, start and end sensors allowed".into());
+                }
+                self.sections.push(section);
+            }
+            "intersection" => self.intersections.push(IntersectionLayout {
+                id,
+                position,
+                arms: list("arms"),
+                phases: Vec::new(),
+                allowed: Vec::new(),
+                approaches: Vec::new(),
+                actuation: None,
+                clearance: 0,
+                box_sensors: Vec::new(),
+                servo_timing: (0, SERVO_TIMEOUT),
+                night: None,
+                crossings: Vec::new(),
+                pedestrian_timing: PedestrianTiming::default(),
+                coordination: None,
+                preemption_calls: Vec::new(),
+                preemption_clears: Vec::new(),
+            }),
+            kind => return Err(format!("unknown element `{}`", kind)),
+        }
+        Ok(())
+    }
+}
+
+fn parse_light_word(word: &str) -> Result<IntersectionActionLight, String> {
+    parse_light(word).ok_or(format!("`{}` is not a light", word))
+}
+
+fn sorted<'a>(ids: impl Iterator<Item = &'a u8>) -> Vec<u8> {
+    let mut ids: Vec<u8> = ids.copied().collect();
+    ids.sort_unstable();
+    ids.dedup();
+    ids
+}
+
+fn parse_number<T: std::str::FromStr>(word: Option<&str>) -> Result<T, String> {
+    let word = word.ok_or("missing number")?;
+    word.parse().map_err(|_| format!("`{}` is not a number", word))
+}
+
+/// Returns the time of every day given by the milliseconds of `from`, `to` and `day` of a line,
+/// `None` without `from` and `to`
+///
+/// The window starts at the start and ends at the end of the day if one of them is left out, and
+/// wraps around the end of the day if it starts after it ends.
+fn parse_window(
+    from: Option<u64>,
+    to: Option<u64>,
+    day: Option<u64>,
+) -> Result<Option<DailyWindow>, String> {
+    let day = day.unwrap_or(DAY);
+    let (from, until) = match (from, to) {
+        (None, None) => return Ok(None),
+        (from, to) => (from.unwrap_or(0), to.unwrap_or(day)),
+    };
+    if day == 0 {
+        return Err("the `day` can't be 0".to_string());
+    }
+    if from == until {
+        return Err("`from` and `to` can't be the same time".to_string());
+    }
+    match DailyWindow::new(from, until, day) {
+        Some(window) => Ok(Some(window)),
+        None => Err("`from` and `to` have to be within the `day`".to_string()),
+    }
+}
+
+/// Parses seconds with up to three decimals and returns them in milliseconds
+fn parse_seconds(word: Option<&str>) -> Result<u32, String> {
+    let word = word.ok_or("missing number")?;
+    let error = || format!("`{}` is not a number of seconds", word);
+    let (seconds, fraction) = word.split_once('.').unwrap_or((word, ""));
+    if fraction.len() > 3 || !fraction.bytes().all(|byte| byte.is_ascii_digit()) {
+        return Err(error());
+    }
+    let seconds: u32 = seconds.parse().map_err(|_| error())?;
+    let milliseconds: u32 = format!("{:0<3}", fraction).parse().map_err(|_| error())?;
+    seconds
+        .checked_mul(1_000)
+        .and_then(|seconds| seconds.checked_add(milliseconds))
+        .ok_or_else(error)
+}
+
+/// Formats milliseconds as the seconds of a layout file
+fn format_seconds(milliseconds: u32) -> String {
+    let seconds = format!("{}.{:03}", milliseconds / 1_000, milliseconds % 1_000);
+    seconds.trim_end_matches('0').trim_end_matches('.').to_string()
+}
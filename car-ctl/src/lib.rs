@@ -0,0 +1,13 @@
+//! The parts of the host companion, used by the `car-ctl` binary and its tests
+//!
+//! See the binary for the commands.
+
+pub mod check;
+pub mod dashboard;
+pub mod layout;
+pub mod link;
+pub mod model;
+pub mod session;
+pub mod sim;
+pub mod state;
+pub mod trace;
@@ -0,0 +1,111 @@
+//! Connection to the controller over a serial port or pseudo-terminal
+
+use std::io::{self, BufRead, BufReader, ErrorKind};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
+
+use car_system::history;
+use car_system::protocol::{Command, Event};
+use car_system::serial::Serial;
+use serialport::SerialPort;
+
+/// Baud rate the firmware uses for its serial port
+pub const BAUD_RATE: u32 = 57600;
+
+/// A line received from the controller
+pub enum Line {
+    /// A line of the protocol
+    Event(Event),
+    /// An event of the history with the time it was recorded
+    History(u64, Event),
+    /// Any other output, usually log messages
+    Text(String),
+}
+
+impl Line {
+    /// Returns the line as an event if it is one
+    pub fn parse(text: &str) -> Line {
+        if let Some(event) = Event::parse(text) {
+            return Line::Event(event);
+        }
+        match history::parse_entry(text) {
+            Some((time, event)) => Line::History(time, event),
+            None => Line::Text(text.to_string()),
+        }
+    }
+
+    /// Returns the text of the line as the controller sent it
+    pub fn text(&self) -> String {
+        match self {
+            Line::Event(event) => {
+                let mut line = LineWriter(String::new());
+                event.write(&mut line);
+                line.0.trim_end().to_string()
+            }
+            Line::History(time, event) => {
+                format!("HIST {} {}", time, Line::Event(*event).text())
+            }
+            Line::Text(text) => text.clone(),
+        }
+    }
+}
+
+/// Opens the serial port or pseudo-terminal at the given path
+pub fn open(path: &str, baud_rate: u32) -> serialport::Result<Box<dyn SerialPort>> {
+    serialport::new(path, baud_rate)
+        .timeout(Duration::from_millis(100))
+        .open()
+}
+
+/// Reads lines from the port on a separate thread
+///
+/// The returned channel is closed when the port fails
+pub fn spawn_reader(port: Box<dyn SerialPort>) -> Receiver<Line> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut reader = BufReader::new(port);
+        let mut buffer = Vec::new();
+        loop {
+            // a timeout leaves the bytes read so far in the buffer, so the line is continued
+            match reader.read_until(b'\n', &mut buffer) {
+                Ok(0) => return,
+                Ok(_) if buffer.ends_with(b"\n") => {
+                    let line = Line::parse(String::from_utf8_lossy(&buffer).trim());
+                    buffer.clear();
+                    if sender.send(line).is_err() {
+                        return;
+                    }
+                }
+                Ok(_) => (),
+                Err(error) if error.kind() == ErrorKind::TimedOut => (),
+                Err(_) => return,
+            }
+        }
+    });
+    receiver
+}
+
+/// Sends the command to the controller
+pub fn send(port: &mut dyn SerialPort, command: &Command) -> io::Result<()> {
+    let mut line = LineWriter(String::new());
+    command.write(&mut line);
+    port.write_all(line.0.as_bytes())?;
+    port.flush()
+}
+
+/// Parses a command typed by the user
+///
+/// Accepts the wire format in any case, e.g. `lock 3` or `next 1`
+pub fn parse_command(text: &str) -> Option<Command> {
+    Command::parse(&text.to_uppercase())
+}
+
+/// Collects the output of the protocol in a string
+pub struct LineWriter(pub String);
+
+impl Serial for LineWriter {
+    fn write_str(&mut self, s: &str) {
+        self.0.push_str(s);
+    }
+}
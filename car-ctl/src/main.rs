@@ -0,0 +1,238 @@
+//! Host companion for monitoring and commanding the controller
+//!
+//! ```text
+//! car-ctl monitor <port> [baud]          shows the events and sends the commands typed on stdin
+//! car-ctl dashboard <layout> <port>      shows a live map of the layout
+//! car-ctl replay <layout> <session>      replays a recorded session on the map
+//! car-ctl send <port> <command>...       sends a single command
+//! car-ctl capture <port> <trace>         records the sensor edges of the controller to a trace
+//! car-ctl check <layout> <trace> <snapshot> [--bless] [--log <level>]
+//!                                        replays a trace and compares the outputs to a snapshot
+//! car-ctl sim [layout]                   runs the stand-in for the firmware on a pseudo-terminal
+//! ```
+//!
+//! `monitor` and `dashboard` record the session to a file when given `--record <file>`.
+//!
+//! `check` exits with 1 when the outputs differ from the snapshot and fails when the snapshot can't
+//! be read. With `--bless` the snapshot is written from the outputs instead.
+//!
+//! Commands use the wire format in any case: `lock <stopper>`, `release <stopper>`, `estop`,
+//! `resume`, `next <intersection>`, `night <intersection> on|off`, `preempt <intersection> <arm>
+//! gr|gl` or `preempt <intersection> clear` for an emergency vehicle, `turnout <turnout>
+//! straight|diverging`, `route <route>`, `log <module|all> <level>`, `dump`, which lists the recent
+//! events the controller kept, and `crash` or `crash clear`, which show or remove the record of the
+//! last crash.
+
+use std::env;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufRead, Write};
+use std::process;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use car_ctl::check::{self, Outcome};
+use car_ctl::dashboard::{self, Source};
+use car_ctl::layout::Layout;
+use car_ctl::link::{self, Line};
+use car_ctl::session::{self, Recorder};
+use car_ctl::sim;
+use car_ctl::state::{self, State};
+use car_ctl::trace;
+use car_system::log::Level;
+use car_system::protocol::Event;
+
+const USAGE: &str = "usage:
+    car-ctl monitor <port> [baud] [--record <file>]
+    car-ctl dashboard <layout> <port> [--record <file>]
+    car-ctl replay <layout> <session>
+    car-ctl send <port> <command>...
+    car-ctl capture <port> <trace>
+    car-ctl check <layout> <trace> <snapshot> [--bless] [--log <level>]
+    car-ctl sim [layout]";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let record = match args.iter().position(|arg| *arg == "--record") {
+        Some(index) if index + 1 < args.len() => Some(args.drain(index..index + 2).nth(1).unwrap()),
+        Some(_) => usage(),
+        None => None,
+    };
+    let result = match (args.as_slice(), record) {
+        (["monitor", port], record) => monitor(port, link::BAUD_RATE, record),
+        (["monitor", port, baud], record) => match baud.parse() {
+            Ok(baud) => monitor(port, baud, record),
+            Err(_) => usage(),
+        },
+        (["dashboard", layout, port], record) => dashboard(layout, port, record),
+        (["replay", layout, session], None) => replay(layout, session),
+        (["send", port, command @ ..], None) if !command.is_empty() => {
+            send(port, &command.join(" "))
+        }
+        (["capture", port, trace], None) => capture(port, trace),
+        (["check", layout, trace, snapshot, flags @ ..], None) => check(layout, trace, snapshot, flags),
+        (["sim"], None) => Layout::parse(sim::DEFAULT_LAYOUT)
+            .map_err(Into::into)
+            .and_then(|layout| sim::run(&layout)),
+        (["sim", layout], None) => Layout::load(layout).and_then(|layout| sim::run(&layout)),
+        _ => usage(),
+    };
+    if let Err(error) = result {
+        eprintln!("error: {}", error);
+        process::exit(1);
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+/// Prints the events of the controller and sends the commands typed on stdin
+///
+/// `status` prints the state collected so far, `verbose` toggles the debug output of the
+/// controller.
+fn monitor(path: &str, baud_rate: u32, record: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let mut port = link::open(path, baud_rate)?;
+    let mut recorder = record.map(Recorder::create).transpose()?;
+    let lines = link::spawn_reader(port.try_clone()?);
+    let input = spawn_stdin_reader();
+    let mut state = State::default();
+    let mut verbose = false;
+
+    loop {
+        let line = lines.recv_timeout(Duration::from_millis(10));
+        if let (Ok(line), Some(recorder)) = (&line, &mut recorder) {
+            recorder.record(&line.text())?;
+        }
+        match line {
+            Ok(Line::Event(event)) => {
+                state.apply(&event);
+                println!("{}", state::describe(&event));
+            }
+            Ok(Line::History(time, event)) => {
+                println!("history {} ms: {}", time, state::describe(&event));
+            }
+            Ok(Line::Text(text)) => {
+                // crash reports are shown like events, they are rare and important
+                if verbose || text.starts_with("CRASH") {
+                    println!("  | {}", text);
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => return Err("connection lost".into()),
+        }
+
+        while let Ok(text) = input.try_recv() {
+            match text.trim() {
+                "" => (),
+                "status" => println!("{}", state.summary()),
+                "verbose" => verbose = !verbose,
+                text => match link::parse_command(text) {
+                    Some(command) => link::send(port.as_mut(), &command)?,
+                    None => eprintln!("unknown command: {}", text),
+                },
+            }
+        }
+    }
+}
+
+/// Shows the live map of the layout
+fn dashboard(layout: &str, path: &str, record: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let layout = Layout::load(layout)?;
+    let port = link::open(path, link::BAUD_RATE)?;
+    let source = Source::Live {
+        name: path.to_string(),
+        lines: link::spawn_reader(port),
+        recorder: record.map(Recorder::create).transpose()?,
+        start: Instant::now(),
+    };
+    dashboard::run(&layout, source)
+}
+
+/// Replays a recorded session on the map of the layout
+fn replay(layout: &str, session: &str) -> Result<(), Box<dyn Error>> {
+    let layout = Layout::load(layout)?;
+    let lines = session::load(session)?;
+    dashboard::run(&layout, Source::replay(session, lines))
+}
+
+/// Sends a single command
+fn send(path: &str, text: &str) -> Result<(), Box<dyn Error>> {
+    let command = link::parse_command(text).ok_or("unknown command")?;
+    let mut port = link::open(path, link::BAUD_RATE)?;
+    link::send(port.as_mut(), &command)?;
+    Ok(())
+}
+
+/// Writes the sensor edges reported by the controller to a trace until the connection is lost
+fn capture(path: &str, trace: &str) -> Result<(), Box<dyn Error>> {
+    let port = link::open(path, link::BAUD_RATE)?;
+    let lines = link::spawn_reader(port);
+    let mut file = File::create(trace)?;
+    writeln!(file, "# sensor edges captured from {}", path)?;
+
+    for line in lines {
+        if let Line::Event(event @ Event::SensorEdge { id, active, time }) = line {
+            println!("{}", state::describe(&event));
+            let edge = trace::Edge {
+                time,
+                sensor: id,
+                active,
+            };
+            trace::write_edge(&mut file, &edge)?;
+            file.flush()?;
+        }
+    }
+    Err("connection lost".into())
+}
+
+/// Replays the trace into the logic of the layout and compares the outputs to the snapshot
+///
+/// `--bless` writes the snapshot, `--log <level>` prints the log messages of the logic up to the
+/// level.
+fn check(layout: &str, trace: &str, snapshot: &str, flags: &[&str]) -> Result<(), Box<dyn Error>> {
+    let mut bless = false;
+    let mut log_level = Level::Off;
+    let mut flags = flags.iter();
+    while let Some(flag) = flags.next() {
+        match *flag {
+            "--bless" => bless = true,
+            "--log" => {
+                let name = flags.next().unwrap_or_else(|| usage());
+                log_level = Level::parse(&name.to_uppercase()).unwrap_or_else(|| usage());
+            }
+            _ => usage(),
+        }
+    }
+    let layout = Layout::load(layout)?;
+    let edges = trace::load(trace)?;
+    match check::run(&layout, &edges, snapshot, bless, log_level)? {
+        Outcome::Passed => println!("{}: outputs match", trace),
+        Outcome::Blessed => println!("{}: snapshot written", snapshot),
+        Outcome::Failed(difference) => {
+            eprintln!("{}", difference);
+            process::exit(1);
+        }
+    }
+    Ok(())
+}
+
+/// Reads lines from stdin on a separate thread
+fn spawn_stdin_reader() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => return,
+            };
+            if sender.send(line).is_err() {
+                return;
+            }
+        }
+    });
+    receiver
+}
@@ -0,0 +1,800 @@
+//! The control logic of the firmware built from a layout file and running on mock pins
+//!
+//! The model is driven like the main loop of the firmware: [`Model::tick`] polls the sensors every
+//! 5 ms and calls the intersections, level crossings, roundabouts and turnouts every 50 ms of the
+//! mock clock. Errors go through a [`FaultHandler`] like on the board, an escalated error stops the
+//! logic with every stopper locked and every light red.
+
+use std::cell::RefCell;
+
+use car_system::actuation::Approach;
+use car_system::clearance::Occupancy;
+use car_system::crash::{self, CrashKind, CrashRecord, Eeprom};
+use car_system::error::Error;
+use car_system::fault::{Action, FaultHandler};
+use car_system::history::History;
+use car_system::intersection::*;
+use car_system::level_crossing::{LevelCrossing, Track};
+use car_system::lights::{Light, LIGHT_ACTIVE};
+use car_system::log::Logger;
+use car_system::monitor::Movement;
+use car_system::pedestrian::{Crossing, Demand, PedestrianSignal};
+use car_system::plan::PhasePlan;
+use car_system::pin_mockup::Pin;
+use car_system::preemption::Preemption;
+use car_system::protocol::{Command, Event};
+use car_system::remote::Remote;
+use car_system::roundabout::{Roundabout, RoundaboutEntry};
+use car_system::section::Section;
+use car_system::sensor::SensorEnum::*;
+use car_system::sensor::{Sensor, SENSOR_ACTIVE};
+use car_system::sensor_caller::SensorCaller;
+use car_system::serial::{Serial, Tee};
+use car_system::servo::Servo;
+use car_system::stopper::{Stopper, STOPPER_ACTIVE};
+use car_system::time;
+use car_system::turnout::{Route, Turnout};
+use embedded_hal::blocking::i2c;
+
+use crate::layout::Layout;
+
+/// Address of the servo controller on the I2C bus
+const SERVO_ADDRESS: u8 = 4;
+/// Servo angles for the right and left direction
+const SERVO_ANGLES: (u8, u8) = (60, 120);
+/// Number of events kept in the history, the same as on the board
+const HISTORY_LENGTH: usize = 32;
+/// Size of the EEPROM of the board in bytes
+const EEPROM_SIZE: usize = 4096;
+/// First address of the EEPROM area reserved for the crash record, the same as on the board
+const CRASH_RECORD_ADDRESS: u16 = 0;
+
+/// I2C bus which accepts every write and keeps it, standing in for the servo controller
+#[derive(Default)]
+pub struct RecordingI2c {
+    writes: Vec<(u8, Vec<u8>)>,
+}
+
+impl i2c::Write for RecordingI2c {
+    type Error = ();
+
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.writes.push((address, bytes.to_vec()));
+        Ok(())
+    }
+}
+
+/// EEPROM in RAM, erased whenever a model is built
+struct MemoryEeprom(Vec<u8>);
+
+impl Eeprom for MemoryEeprom {
+    fn read_byte(&mut self, address: u16) -> u8 {
+        self.0[address as usize]
+    }
+
+    fn write_byte(&mut self, address: u16, byte: u8) {
+        self.0[address as usize] = byte;
+    }
+}
+
+type MockIntersection<'l> = Intersection<'l, RecordingI2c, PhasePlan, Pin<'l>, Pin<'l>>;
+type MockLevelCrossing<'l> = LevelCrossing<'l, RecordingI2c, Pin<'l>, Pin<'l>>;
+type MockRoundabout<'l> = Roundabout<'l, Pin<'l>, Pin<'l>>;
+type MockTurnout<'l> = Turnout<'l, RecordingI2c, Pin<'l>, Pin<'l>, Pin<'l>>;
+
+/// The logic of one layout, see [`run`]
+pub struct Model<'l> {
+    /// The serial the history is dumped to
+    serial: &'l RefCell<dyn Serial + 'l>,
+    /// The serial the events are written to
+    output: &'l RefCell<dyn Serial + 'l>,
+    history: &'l RefCell<History<HISTORY_LENGTH>>,
+    log: &'l Logger<'l>,
+    eeprom: MemoryEeprom,
+    stopper_ids: Vec<u8>,
+    stopper_levels: &'l [RefCell<bool>],
+    sensor_ids: Vec<u8>,
+    sensor_levels: &'l [RefCell<bool>],
+    sensor_caller: SensorCaller<'l, Pin<'l>, Pin<'l>>,
+    /// Intersections with their id and reported lights
+    intersections: Vec<(u8, MockIntersection<'l>, Option<Vec<IntersectionActionLight>>)>,
+    light_levels: &'l [RefCell<bool>],
+    /// The green and red pin of every pedestrian crossing, numbered through the intersections
+    signal_levels: &'l [RefCell<bool>],
+    level_crossings: Vec<MockLevelCrossing<'l>>,
+    /// The pins of the two lights of every level crossing
+    level_light_levels: &'l [RefCell<bool>],
+    roundabouts: Vec<MockRoundabout<'l>>,
+    turnouts: &'l [RefCell<MockTurnout<'l>>],
+    routes: Vec<Route<'l, RecordingI2c, Pin<'l>, Pin<'l>, Pin<'l>>>,
+    i2c: &'l RefCell<RecordingI2c>,
+    remote: Remote<'l, Pin<'l>>,
+    faults: FaultHandler,
+    /// If an error was escalated and the logic stopped
+    halted: bool,
+    last_5ms: u64,
+    last_50ms: u64,
+}
+
+/// Builds the logic for the layout and passes it to `f`
+///
+/// The logic borrows its pins and parts, so it only lives as long as the call of `f`.
+///
+/// # Arguments
+///
+/// * `layout` - the sections, intersections, level crossings, roundabouts, turnouts and routes to
+///   build
+/// * `serial` - the output of the events
+/// * `log_sink` - the output of the log messages, may be the same as `serial`
+/// * `f` - gets the model to drive
+pub fn run<S, L, T>(
+    layout: &Layout,
+    serial: &RefCell<S>,
+    log_sink: &RefCell<L>,
+    f: impl FnOnce(&mut Model) -> T,
+) -> T
+where
+    S: Serial,
+    L: Serial,
+{
+    // the logic starts at the time 0 like the board after a reset, a model built before may have
+    // moved the mock clock
+    time::set_millis(0);
+    // every event is written to the serial and kept in the history like on the board
+    let history: RefCell<History<HISTORY_LENGTH>> = RefCell::new(History::new());
+    let serial: &RefCell<dyn Serial> = serial;
+    let output = RefCell::new(Tee::new(serial, &history));
+    let output: &RefCell<dyn Serial> = &output;
+    let mut log = Logger::new();
+    log.add_sink(log_sink).unwrap();
+    let stopper_ids = layout.stopper_ids();
+    let sensor_ids = layout.sensor_ids();
+    let arms: usize = layout
+        .intersections
+        .iter()
+        .map(|intersection| intersection.arms.len())
+        .sum();
+    let crossings: usize = layout
+        .intersections
+        .iter()
+        .map(|intersection| intersection.crossings.len())
+        .sum();
+
+    // pins
+    let stopper_levels: Vec<_> = stopper_ids.iter().map(|_| RefCell::new(false)).collect();
+    let sensor_levels: Vec<_> = sensor_ids
+        .iter()
+        .map(|_| RefCell::new(!SENSOR_ACTIVE))
+        .collect();
+    let light_levels: Vec<_> = (0..3 * arms).map(|_| RefCell::new(false)).collect();
+    let signal_levels: Vec<_> = (0..2 * crossings).map(|_| RefCell::new(false)).collect();
+    let demands: Vec<_> = (0..crossings).map(|_| Demand::new()).collect();
+    let servo_levels: Vec<_> = (0..arms).map(|_| RefCell::new(false)).collect();
+    let approaches: Vec<_> = (0..arms).map(|_| Approach::new()).collect();
+    let occupancies: Vec<_> = layout.intersections.iter().map(|_| Occupancy::new()).collect();
+    let preemptions: Vec<_> = layout.intersections.iter().map(|_| Preemption::new()).collect();
+    let level_light_levels: Vec<_> = (0..6 * layout.level_crossings.len())
+        .map(|_| RefCell::new(false))
+        .collect();
+    let barrier_levels: Vec<_> = layout
+        .level_crossings
+        .iter()
+        .flat_map(|level| &level.barriers)
+        .map(|_| RefCell::new(false))
+        .collect();
+    let tracks: Vec<_> = layout.level_crossings.iter().map(|_| Track::new()).collect();
+    let entries: usize = layout
+        .roundabouts
+        .iter()
+        .map(|roundabout| roundabout.entries.len())
+        .sum();
+    let entry_approaches: Vec<_> = (0..entries).map(|_| Approach::new()).collect();
+    let turnout_levels: Vec<_> = layout.turnouts.iter().map(|_| RefCell::new(false)).collect();
+    let i2c = RefCell::new(RecordingI2c::default());
+
+    // stoppers
+    let stoppers: Vec<_> = stopper_ids
+        .iter()
+        .zip(&stopper_levels)
+        .map(|(id, level)| RefCell::new(Stopper::new(Pin::new(level), *id).unwrap()))
+        .collect();
+    let stopper = |id: &u8| &stoppers[stopper_ids.binary_search(id).unwrap()];
+    let stopper_refs: Vec<_> = stoppers.iter().collect();
+
+    // sensors
+    let sensors: Vec<RefCell<Sensor<Pin, Pin>>> = sensor_ids
+        .iter()
+        .zip(&sensor_levels)
+        .map(|(id, level)| RefCell::new(Sensor::new(Pin::new(level), *id)))
+        .collect();
+    // the layout checked that no sensor takes more than two roles of a kind
+    let sensor = |id: &u8| &sensors[sensor_ids.binary_search(id).unwrap()];
+    let sensor_refs: Vec<_> = sensors.iter().collect();
+
+    // sections
+    let sections: Vec<_> = layout
+        .sections
+        .iter()
+        .map(|section| RefCell::new(Section::new(section.id, output)))
+        .collect();
+    for (section, section_layout) in sections.iter().zip(&layout.sections) {
+        let mut section_mut = section.borrow_mut();
+        section_mut.set_self_reference(section);
+        // the layout checked that a section has no more than two of each and no sensor starts or
+        // ends more than two sections
+        for id in &section_layout.stoppers {
+            section_mut.add_stopper(stopper(id)).unwrap();
+        }
+        for id in &section_layout.start_sensors {
+            section_mut.add_sensor(StartSensor(sensor(id))).unwrap();
+        }
+        for id in &section_layout.end_sensors {
+            section_mut.add_sensor(EndSensor(sensor(id))).unwrap();
+        }
+    }
+    let section = |id: &u8| {
+        let index = layout.sections.iter().position(|section| section.id == *id);
+        &sections[index.unwrap()]
+    };
+
+    // intersections, their arms are numbered through and the servo id is the index in the
+    // intersection
+    let servo_ids = layout
+        .intersections
+        .iter()
+        .flat_map(|intersection| 0..intersection.arms.len() as u8);
+    let servos: Vec<_> = servo_levels
+        .iter()
+        .zip(servo_ids)
+        .map(|(level, id)| {
+            let (right_angle, left_angle) = SERVO_ANGLES;
+            let servo = Servo::new(Pin::new(level), right_angle, left_angle, &i2c, id, SERVO_ADDRESS);
+            RefCell::new(servo.unwrap())
+        })
+        .collect();
+    let arm = |index: usize, stopper_id: &u8, approach| IntersectionArm {
+        entry_stopper: stopper(stopper_id),
+        light: Light::new(
+            Pin::new(&light_levels[3 * index]),
+            Pin::new(&light_levels[3 * index + 1]),
+            Pin::new(&light_levels[3 * index + 2]),
+        )
+        .unwrap(),
+        servo: &servos[index],
+        approach,
+    };
+    let mut first_arm = 0;
+    let mut first_crossing = 0;
+    let intersections = layout
+        .intersections
+        .iter()
+        .zip(&occupancies)
+        .zip(&preemptions)
+        .map(|((intersection, occupancy), preemption)| {
+            let first = first_arm;
+            first_arm += intersection.arms.len();
+            for (index, sensor_id) in &intersection.approaches {
+                let approach = &approaches[first + index];
+                sensor(sensor_id).borrow_mut().add_approach(approach).unwrap();
+            }
+            let arms = intersection.arms.iter().enumerate().map(|(index, stopper_id)| {
+                let has_approach = intersection.approaches.iter().any(|(arm, _)| *arm == index);
+                let approach = has_approach.then(|| &approaches[first + index]);
+                arm(first + index, stopper_id, approach)
+            });
+            // the plan was validated with the layout
+            let (plan, conflicts) = intersection.plan();
+            let mut intersection_logic =
+                Intersection::new(intersection.id, arms, plan, conflicts).unwrap();
+            if let Some(actuation) = intersection.actuation {
+                intersection_logic.set_actuation(actuation);
+            }
+            for sensor_id in &intersection.box_sensors {
+                sensor(sensor_id).borrow_mut().add_box(occupancy).unwrap();
+            }
+            let occupancy = match intersection.box_sensors.is_empty() {
+                true => None,
+                false => Some(occupancy),
+            };
+            intersection_logic.set_clearance(intersection.clearance, occupancy);
+            let (settle_time, servo_timeout) = intersection.servo_timing;
+            intersection_logic.set_servo_timing(settle_time, servo_timeout);
+            if let Some(night_mode) = intersection.night_mode() {
+                intersection_logic.set_night_mode(night_mode);
+            }
+            if let Some(coordination) = intersection.coordination {
+                intersection_logic.set_coordination(coordination);
+            }
+            for (movement, sensor_id) in &intersection.preemption_calls {
+                let mut caller = sensor(sensor_id).borrow_mut();
+                caller.add_preemption_call(preemption, *movement).unwrap();
+            }
+            for sensor_id in &intersection.preemption_clears {
+                sensor(sensor_id).borrow_mut().add_preemption_clear(preemption).unwrap();
+            }
+            intersection_logic.set_preemption(preemption);
+            for (crossed_arms, buttons) in &intersection.crossings {
+                let index = first_crossing;
+                first_crossing += 1;
+                for sensor_id in buttons {
+                    sensor(sensor_id).borrow_mut().add_request(&demands[index]).unwrap();
+                }
+                let mut arms = [false; MAX_ARMS];
+                for arm in crossed_arms {
+                    arms[*arm] = true;
+                }
+                let signal = PedestrianSignal::new(
+                    Pin::new(&signal_levels[2 * index]),
+                    Pin::new(&signal_levels[2 * index + 1]),
+                )
+                .unwrap();
+                let crossing = Crossing {
+                    signal,
+                    demand: &demands[index],
+                    arms,
+                };
+                // the layout checked that an intersection has no more than MAX_CROSSINGS crossings
+                intersection_logic.add_crossing(crossing).unwrap();
+            }
+            intersection_logic.set_pedestrian_timing(intersection.pedestrian_timing);
+            (intersection.id, intersection_logic, None)
+        })
+        .collect();
+
+    // level crossings, the servo id of a barrier is given by the layout
+    let barrier_ids = layout.level_crossings.iter().flat_map(|level| &level.barriers);
+    let barriers: Vec<_> = barrier_levels
+        .iter()
+        .zip(barrier_ids)
+        .map(|(level, &id)| {
+            let (right_angle, left_angle) = SERVO_ANGLES;
+            let servo = Servo::new(Pin::new(level), right_angle, left_angle, &i2c, id, SERVO_ADDRESS);
+            RefCell::new(servo.unwrap())
+        })
+        .collect();
+    let mut barriers = barriers.iter();
+    let level_crossings = layout
+        .level_crossings
+        .iter()
+        .zip(&tracks)
+        .enumerate()
+        .map(|(index, (level, track))| {
+            let light = |light: usize| {
+                let pins = &level_light_levels[3 * (2 * index + light)..];
+                Light::new(Pin::new(&pins[0]), Pin::new(&pins[1]), Pin::new(&pins[2])).unwrap()
+            };
+            let lights = [light(0), light(1)];
+            let mut level_crossing = LevelCrossing::new(level.id, lights, track).unwrap();
+            // the layout checked that a crossing has no more than two stoppers and barriers
+            for id in &level.stoppers {
+                level_crossing.add_stopper(stopper(id)).unwrap();
+            }
+            for barrier in barriers.by_ref().take(level.barriers.len()) {
+                level_crossing.add_barrier(barrier).unwrap();
+            }
+            for id in &level.approach_sensors {
+                sensor(id).borrow_mut().add_track_approach(track).unwrap();
+            }
+            for id in &level.exit_sensors {
+                sensor(id).borrow_mut().add_track_exit(track).unwrap();
+            }
+            level_crossing.set_timing(level.timing);
+            level_crossing
+        })
+        .collect();
+
+    // roundabouts, their entries are numbered through
+    let mut entry_approaches = entry_approaches.iter();
+    let roundabouts = layout
+        .roundabouts
+        .iter()
+        .map(|roundabout| {
+            let entries = roundabout.entries.iter().map(|entry| {
+                let approach = entry_approaches.next().unwrap();
+                for id in &entry.approach_sensors {
+                    sensor(id).borrow_mut().add_approach(approach).unwrap();
+                }
+                let approach = match entry.approach_sensors.is_empty() {
+                    true => None,
+                    false => Some(approach),
+                };
+                RoundaboutEntry {
+                    stopper: stopper(&entry.stopper),
+                    circulating: section(&entry.section),
+                    approach,
+                }
+            });
+            // the layout checked that a roundabout has no more than MAX_ENTRIES entries
+            let mut roundabout_logic = Roundabout::new(roundabout.id, entries).unwrap();
+            roundabout_logic.set_timing(roundabout.timing);
+            roundabout_logic
+        })
+        .collect();
+
+    // turnouts and routes, the servo id of a turnout is given by the layout
+    let turnout_servos: Vec<_> = layout
+        .turnouts
+        .iter()
+        .zip(&turnout_levels)
+        .map(|(turnout, level)| {
+            let (right_angle, left_angle) = SERVO_ANGLES;
+            let id = turnout.servo;
+            let servo = Servo::new(Pin::new(level), right_angle, left_angle, &i2c, id, SERVO_ADDRESS);
+            RefCell::new(servo.unwrap())
+        })
+        .collect();
+    let turnouts: Vec<_> = layout
+        .turnouts
+        .iter()
+        .zip(&turnout_servos)
+        .map(|(turnout, servo)| {
+            let mut turnout_logic = Turnout::new(turnout.id, servo);
+            // the layout checked that a turnout has no more than two sections
+            for id in &turnout.sections {
+                turnout_logic.add_section(section(id)).unwrap();
+            }
+            if let Some(schedule) = turnout.schedule {
+                turnout_logic.set_schedule(schedule);
+            }
+            RefCell::new(turnout_logic)
+        })
+        .collect();
+    let routes = layout
+        .routes
+        .iter()
+        .map(|route| {
+            let mut route_logic = Route::new(route.id);
+            // the layout checked that a route has no more than MAX_ROUTE_TURNOUTS turnouts and
+            // that they exist
+            for (id, position) in &route.turnouts {
+                let index = layout.turnouts.iter().position(|turnout| turnout.id == *id);
+                route_logic.add_turnout(&turnouts[index.unwrap()], *position).unwrap();
+            }
+            route_logic
+        })
+        .collect();
+
+    let mut model = Model {
+        serial,
+        output,
+        history: &history,
+        log: &log,
+        eeprom: MemoryEeprom(vec![0xff; EEPROM_SIZE]),
+        stopper_ids: stopper_ids.clone(),
+        stopper_levels: &stopper_levels,
+        sensor_ids: sensor_ids.clone(),
+        sensor_levels: &sensor_levels,
+        sensor_caller: SensorCaller::new(&sensor_refs),
+        intersections,
+        light_levels: &light_levels,
+        signal_levels: &signal_levels,
+        level_crossings,
+        level_light_levels: &level_light_levels,
+        roundabouts,
+        turnouts: &turnouts,
+        routes,
+        i2c: &i2c,
+        // the layout checked that there are no more stoppers than a remote reports
+        remote: Remote::new(&stopper_refs, output).unwrap(),
+        faults: FaultHandler::new(),
+        halted: false,
+        last_5ms: 0,
+        last_50ms: 0,
+    };
+    f(&mut model)
+}
+
+impl<'l> Model<'l> {
+    /// Runs one iteration of the main loop at the given time of the mock clock
+    pub fn tick(&mut self, time: u64) {
+        time::set_millis(time);
+        if self.halted {
+            return;
+        }
+        self.remote.call();
+
+        if self.last_5ms + 5 <= time {
+            if let Err(error) = self.sensor_caller.call(Some(self.output), self.log) {
+                self.handle_error(error);
+            }
+            self.last_5ms = time;
+        }
+
+        if self.last_50ms + 50 <= time {
+            for index in 0..self.intersections.len() {
+                let mut result = self.intersections[index].1.call(self.log);
+                // a retry executes the state again right away
+                while let Err(error) = result {
+                    if self.handle_error(error) != Action::Retry {
+                        break;
+                    }
+                    result = self.intersections[index].1.retry();
+                }
+                if result.is_ok() {
+                    self.faults.succeeded();
+                }
+            }
+            for index in 0..self.level_crossings.len() {
+                // a level crossing sets the outputs which failed again by itself
+                let mut result = self.level_crossings[index].call(self.log);
+                while let Err(error) = result {
+                    if self.handle_error(error) != Action::Retry {
+                        break;
+                    }
+                    result = self.level_crossings[index].call(self.log);
+                }
+                if result.is_ok() {
+                    self.faults.succeeded();
+                }
+            }
+            for index in 0..self.roundabouts.len() {
+                // a roundabout writes its stoppers again by itself
+                let mut result = self.roundabouts[index].call(self.log);
+                while let Err(error) = result {
+                    if self.handle_error(error) != Action::Retry {
+                        break;
+                    }
+                    result = self.roundabouts[index].call(self.log);
+                }
+                if result.is_ok() {
+                    self.faults.succeeded();
+                }
+            }
+            for turnout in self.turnouts {
+                // a turnout commands the position which failed again by itself
+                let mut result = turnout.borrow_mut().call(self.log);
+                while let Err(error) = result {
+                    if self.handle_error(error) != Action::Retry {
+                        break;
+                    }
+                    result = turnout.borrow_mut().call(self.log);
+                }
+                if result.is_ok() {
+                    self.faults.succeeded();
+                }
+            }
+            self.last_50ms = time;
+        }
+        self.report_lights();
+    }
+
+    /// Handles a byte received from the host
+    pub fn receive(&mut self, byte: u8) {
+        if self.halted {
+            return;
+        }
+        match self.remote.receive(byte) {
+            Ok(Some(Command::NextPhase(id))) => {
+                for index in 0..self.intersections.len() {
+                    if self.intersections[index].0 == id {
+                        if let Err(error) = self.intersections[index].1.next_phase() {
+                            self.handle_error(error);
+                        }
+                    }
+                }
+            }
+            Ok(Some(Command::Night(id, night))) => {
+                for index in 0..self.intersections.len() {
+                    if self.intersections[index].0 == id {
+                        if let Err(error) = self.intersections[index].1.set_night(night, self.log) {
+                            self.handle_error(error);
+                        }
+                    }
+                }
+            }
+            Ok(Some(Command::Preempt(id, arm, direction))) => {
+                let movement = Movement::new(arm as usize, direction);
+                for index in 0..self.intersections.len() {
+                    if self.intersections[index].0 == id {
+                        if let Err(error) = self.intersections[index].1.preempt(movement, self.log) {
+                            self.handle_error(error);
+                        }
+                    }
+                }
+            }
+            Ok(Some(Command::ClearPreemption(id))) => {
+                for index in 0..self.intersections.len() {
+                    if self.intersections[index].0 == id {
+                        self.intersections[index].1.clear_preemption();
+                    }
+                }
+            }
+            Ok(Some(Command::Turnout(id, position))) => {
+                for turnout in self.turnouts {
+                    if turnout.borrow().get_id() == id {
+                        let result = turnout.borrow_mut().request(position, self.log);
+                        if let Err(error) = result {
+                            self.handle_error(error);
+                        }
+                    }
+                }
+            }
+            Ok(Some(Command::Route(id))) => {
+                for index in 0..self.routes.len() {
+                    if self.routes[index].get_id() == id {
+                        if let Err(error) = self.routes[index].set(self.log) {
+                            self.handle_error(error);
+                        }
+                    }
+                }
+            }
+            Ok(Some(Command::Log(module, level))) => self.log.set_level(module, level),
+            Ok(Some(Command::Dump)) => self.history.borrow().dump(&mut *self.serial.borrow_mut()),
+            Ok(Some(Command::Crash)) => {
+                crash::report(&mut self.eeprom, CRASH_RECORD_ADDRESS, &mut *self.serial.borrow_mut());
+            }
+            Ok(Some(Command::ClearCrash)) => {
+                CrashRecord::clear(&mut self.eeprom, CRASH_RECORD_ADDRESS);
+                crash::report(&mut self.eeprom, CRASH_RECORD_ADDRESS, &mut *self.serial.borrow_mut());
+            }
+            Ok(_) => (),
+            Err(error) => {
+                self.handle_error(error);
+            }
+        }
+    }
+
+    /// Carries on after the error as the fault handler decides and returns its decision
+    fn handle_error(&mut self, error: Error) -> Action {
+        let action = self.faults.handle(error, self.log, &mut *self.output.borrow_mut());
+        match action {
+            Action::Retry => (),
+            Action::Degrade => {
+                if let Error::SensorPin(id) = error {
+                    if let Err(error) = self.sensor_caller.degrade(id) {
+                        self.handle_error(error);
+                    }
+                }
+            }
+            Action::Escalate => self.escalate(error),
+        }
+        action
+    }
+
+    /// Drives the outputs to their safe levels, records the error and stops the logic like the
+    /// board does until the watchdog resets it
+    fn escalate(&mut self, error: Error) {
+        for level in self.stopper_levels {
+            *level.borrow_mut() = STOPPER_ACTIVE;
+        }
+        let lights = self.light_levels.iter().chain(self.level_light_levels);
+        for (index, level) in lights.enumerate() {
+            // the red light is the last of the three pins of an arm or a level crossing light
+            *level.borrow_mut() = match index % 3 {
+                2 => LIGHT_ACTIVE,
+                _ => !LIGHT_ACTIVE,
+            };
+        }
+        let mut record = CrashRecord::new(
+            CrashKind::Fault(error.code()),
+            error.name(),
+            error.id() as u32,
+            time::millis(),
+        );
+        for (_, event) in self.history.borrow().entries() {
+            record.push_event(*event);
+        }
+        record.store(&mut self.eeprom, CRASH_RECORD_ADDRESS);
+        self.halted = true;
+    }
+
+    /// Returns the logger of the logic
+    pub fn log(&self) -> &Logger<'l> {
+        self.log
+    }
+
+    /// Sets the input of the sensor
+    ///
+    /// Returns false if there is no sensor with the id
+    pub fn set_sensor(&mut self, id: u8, active: bool) -> bool {
+        match self.sensor_ids.binary_search(&id) {
+            Ok(index) => {
+                *self.sensor_levels[index].borrow_mut() = match active {
+                    true => SENSOR_ACTIVE,
+                    false => !SENSOR_ACTIVE,
+                };
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Polls the sensors right away instead of waiting for the next poll of [`Model::tick`]
+    pub fn poll_sensors(&mut self) {
+        if self.halted {
+            return;
+        }
+        if let Err(error) = self.sensor_caller.call(Some(self.output), self.log) {
+            self.handle_error(error);
+        }
+    }
+
+    /// Returns the state of every output read from its pins
+    ///
+    /// Each output is a name like `stopper 3`, `light 1 0` (intersection and arm), `crossing 1 0`
+    /// (intersection and crossing) or `level 1 0` (level crossing and light) and its value
+    pub fn outputs(&self) -> Vec<(String, String)> {
+        let mut outputs = Vec::new();
+        for (id, level) in self.stopper_ids.iter().zip(self.stopper_levels) {
+            let value = match *level.borrow() == STOPPER_ACTIVE {
+                true => "locked",
+                false => "released",
+            };
+            outputs.push((format!("stopper {}", id), value.to_string()));
+        }
+        let mut first_arm = 0;
+        for (id, intersection, _) in &self.intersections {
+            for arm in 0..intersection.arm_count() {
+                let pins = &self.light_levels[3 * (first_arm + arm)..];
+                let lit = |index: usize| *pins[index].borrow() == LIGHT_ACTIVE;
+                let value = match (lit(0), lit(1), lit(2)) {
+                    (true, false, false) => "G",
+                    (false, true, false) => "Y",
+                    (false, false, true) => "R",
+                    (false, true, true) => "RY",
+                    (false, false, false) => "OFF",
+                    _ => "INVALID",
+                };
+                outputs.push((format!("light {} {}", id, arm), value.to_string()));
+            }
+            first_arm += intersection.arm_count();
+        }
+        let mut first_crossing = 0;
+        for (id, intersection, _) in &self.intersections {
+            for crossing in 0..intersection.crossing_count() {
+                let pins = &self.signal_levels[2 * (first_crossing + crossing)..];
+                let lit = |index: usize| *pins[index].borrow() == LIGHT_ACTIVE;
+                let value = match (lit(0), lit(1)) {
+                    (true, false) => "G",
+                    (false, true) => "R",
+                    (false, false) => "OFF",
+                    _ => "INVALID",
+                };
+                outputs.push((format!("crossing {} {}", id, crossing), value.to_string()));
+            }
+            first_crossing += intersection.crossing_count();
+        }
+        for (index, level_crossing) in self.level_crossings.iter().enumerate() {
+            for light in 0..2 {
+                let pins = &self.level_light_levels[3 * (2 * index + light)..];
+                let lit = |index: usize| *pins[index].borrow() == LIGHT_ACTIVE;
+                let value = match (lit(0), lit(1), lit(2)) {
+                    (false, false, true) => "R",
+                    (false, false, false) => "OFF",
+                    _ => "INVALID",
+                };
+                let name = format!("level {} {}", level_crossing.get_id(), light);
+                outputs.push((name, value.to_string()));
+            }
+        }
+        outputs
+    }
+
+    /// Returns the bytes written to the servo controller since the last call
+    pub fn take_servo_writes(&mut self) -> Vec<Vec<u8>> {
+        self.i2c
+            .borrow_mut()
+            .writes
+            .drain(..)
+            .map(|(_, bytes)| bytes)
+            .collect()
+    }
+
+    /// Reports the lights of the intersections which changed
+    fn report_lights(&mut self) {
+        for (id, intersection, reported) in &mut self.intersections {
+            let lights = intersection.current_lights().to_vec();
+            if reported.as_ref() != Some(&lights) {
+                for (arm, light) in lights.iter().enumerate() {
+                    Event::Light {
+                        intersection: *id,
+                        arm: arm as u8,
+                        light: *light,
+                    }
+                    .write(&mut *self.output.borrow_mut());
+                }
+                *reported = Some(lights);
+            }
+        }
+    }
+}
@@ -0,0 +1,49 @@
+//! Recorded sessions of the controller output
+//!
+//! Every line of a session file holds the milliseconds since the start of the recording followed
+//! by the line the controller sent at that time.
+
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::time::Instant;
+
+/// Writes the received lines to a session file
+pub struct Recorder {
+    file: BufWriter<File>,
+    start: Instant,
+}
+
+impl Recorder {
+    /// Creates the session file at the given path, replacing an existing one
+    pub fn create(path: &str) -> io::Result<Recorder> {
+        Ok(Recorder {
+            file: BufWriter::new(File::create(path)?),
+            start: Instant::now(),
+        })
+    }
+
+    /// Appends the line with the time since the start of the recording
+    pub fn record(&mut self, text: &str) -> io::Result<()> {
+        writeln!(self.file, "{} {}", self.start.elapsed().as_millis(), text)?;
+        self.file.flush()
+    }
+}
+
+/// Reads the session file at the given path
+///
+/// Returns the lines with their time in milliseconds in the order they were recorded
+pub fn load(path: &str) -> Result<Vec<(u64, String)>, Box<dyn Error>> {
+    let text = fs::read_to_string(path)?;
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(number, line)| {
+            let (time, text) = line.split_once(' ').unwrap_or((line, ""));
+            match time.parse() {
+                Ok(time) => Ok((time, text.to_string())),
+                Err(_) => Err(format!("{}: line {} has no time", path, number + 1).into()),
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,103 @@
+//! Stand-in for the firmware on a pseudo-terminal
+//!
+//! Runs the control logic of a layout file against mock pins and the mock clock, so the tools can
+//! be used and tested without the layout. Cars are simulated by typing the id of the sensor which
+//! should detect one.
+
+use std::cell::RefCell;
+use std::error::Error;
+use std::io::{self, BufRead, ErrorKind, Read, Write};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use car_system::serial::Serial;
+use serialport::{SerialPort, TTYPort};
+
+use crate::layout::Layout;
+use crate::model;
+
+/// Layout simulated when none is given, the board plus an intersection
+pub const DEFAULT_LAYOUT: &str = include_str!("../layouts/sim.layout");
+
+/// Writes the output of the logic to the pseudo-terminal
+struct PortSerial(TTYPort);
+
+impl Serial for PortSerial {
+    fn write_str(&mut self, s: &str) {
+        // nobody might be connected yet, the output is lost then like on the board
+        let _ = self.0.write_all(s.as_bytes());
+    }
+}
+
+/// Runs the stand-in for the layout until stdin is closed
+pub fn run(layout: &Layout) -> Result<(), Box<dyn Error>> {
+    let (mut master, slave) = TTYPort::pair()?;
+    master.set_timeout(Duration::from_millis(1))?;
+    let serial = RefCell::new(PortSerial(master.try_clone_native()?));
+    println!(
+        "stand-in listening on {}",
+        slave.name().unwrap_or_default()
+    );
+    println!("type the id of a sensor to let it detect a car");
+    let triggers = spawn_stdin_reader();
+
+    model::run(layout, &serial, &serial, |model| {
+        let start = Instant::now();
+        let mut buffer = [0; 64];
+
+        loop {
+            let now = start.elapsed().as_millis() as u64;
+
+            // handle commands from the host
+            let received = match master.read(&mut buffer) {
+                Ok(count) => count,
+                Err(error) if error.kind() == ErrorKind::TimedOut => 0,
+                Err(error) => return Err(error.into()),
+            };
+            for byte in &buffer[..received] {
+                model.receive(*byte);
+            }
+
+            // simulate the cars typed on stdin
+            loop {
+                match triggers.try_recv() {
+                    Ok(id) => {
+                        if model.set_sensor(id, true) {
+                            model.poll_sensors();
+                            model.set_sensor(id, false);
+                        } else {
+                            eprintln!("there is no sensor {}", id);
+                        }
+                    }
+                    Err(mpsc::TryRecvError::Empty) => break,
+                    Err(mpsc::TryRecvError::Disconnected) => return Ok(()),
+                }
+            }
+
+            model.tick(now);
+        }
+    })
+}
+
+/// Reads sensor ids from stdin on a separate thread
+fn spawn_stdin_reader() -> Receiver<u8> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => return,
+            };
+            match line.trim().parse() {
+                Ok(id) if id > 0 => {
+                    if sender.send(id).is_err() {
+                        return;
+                    }
+                }
+                _ => eprintln!("not a sensor id: {}", line.trim()),
+            }
+        }
+    });
+    receiver
+}
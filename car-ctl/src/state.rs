@@ -0,0 +1,105 @@
+//! Last known state of the layout as reported by the controller
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use car_system::intersection::IntersectionActionLight;
+use car_system::protocol::{light_token, Event};
+
+/// State of the layout built up from the events of the controller
+#[derive(Default)]
+pub struct State {
+    /// Number of cars per section
+    pub sections: BTreeMap<u8, i8>,
+    /// If a stopper is locked
+    pub stoppers: BTreeMap<u8, bool>,
+    /// Time in milliseconds a sensor last detected a car
+    pub sensors: BTreeMap<u8, u64>,
+    /// Light per intersection and arm
+    pub lights: BTreeMap<(u8, u8), IntersectionActionLight>,
+    /// If the emergency stop is engaged
+    pub emergency_stop: bool,
+}
+
+impl State {
+    /// Updates the state with the event
+    pub fn apply(&mut self, event: &Event) {
+        match *event {
+            Event::Sensor { id, time } => {
+                self.sensors.insert(id, time);
+            }
+            Event::SensorEdge { .. } => (),
+            Event::Section { id, locks } => {
+                self.sections.insert(id, locks);
+            }
+            Event::Stopper { id, locked } => {
+                self.stoppers.insert(id, locked);
+            }
+            Event::Light {
+                intersection,
+                arm,
+                light,
+            } => {
+                self.lights.insert((intersection, arm), light);
+            }
+            Event::EmergencyStop { engaged } => self.emergency_stop = engaged,
+            Event::Fault { .. } => (),
+        }
+    }
+
+    /// Returns a multi-line overview of the state
+    pub fn summary(&self) -> String {
+        let mut summary = String::new();
+        if self.emergency_stop {
+            summary.push_str("EMERGENCY STOP ENGAGED\n");
+        }
+        summary.push_str("sections:");
+        for (id, locks) in &self.sections {
+            write!(summary, " {}={}", id, locks).unwrap();
+        }
+        summary.push_str("\nstoppers:");
+        for (id, locked) in &self.stoppers {
+            write!(summary, " {}={}", id, if *locked { "locked" } else { "released" }).unwrap();
+        }
+        summary.push_str("\nsensors:");
+        for (id, time) in &self.sensors {
+            write!(summary, " {}@{}ms", id, time).unwrap();
+        }
+        summary.push_str("\nlights:");
+        for ((intersection, arm), light) in &self.lights {
+            write!(summary, " {}/{}={}", intersection, arm, light_token(light)).unwrap();
+        }
+        summary
+    }
+}
+
+/// Returns a human readable description of the event
+pub fn describe(event: &Event) -> String {
+    match *event {
+        Event::Sensor { id, time } => format!("sensor {} detected a car at {} ms", id, time),
+        Event::SensorEdge { id, active, time } => format!(
+            "sensor {} became {} at {} ms",
+            id,
+            if active { "active" } else { "inactive" },
+            time
+        ),
+        Event::Section { id, locks } => format!("section {} holds {} car(s)", id, locks),
+        Event::Stopper { id, locked } => {
+            format!("stopper {} {}", id, if locked { "locked" } else { "released" })
+        }
+        Event::Light {
+            intersection,
+            arm,
+            light,
+        } => format!(
+            "intersection {} arm {} shows {}",
+            intersection,
+            arm,
+            light_token(&light)
+        ),
+        Event::EmergencyStop { engaged } => {
+            format!("emergency stop {}", if engaged { "engaged" } else { "cleared" })
+        }
+        Event::Fault { code, id } => format!("fault {} of part {}", code, id),
+    }
+}
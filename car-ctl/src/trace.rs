@@ -0,0 +1,75 @@
+//! Sensor traces for reproducing what happened on the layout
+//!
+//! Every line of a trace holds the time in milliseconds of the controller, the id of a sensor
+//! and `1` when the sensor became active or `0` when it became inactive, `#` starts a comment:
+//!
+//! ```text
+//! # car passes sensor 3
+//! 1520 3 1
+//! 1580 3 0
+//! ```
+//!
+//! Traces are captured from the `EV EDGE` events of the controller and replayed into the logic
+//! by the harness of `car-ctl check`.
+
+use std::error::Error;
+use std::fs;
+use std::io::Write;
+
+/// A sensor becoming active or inactive
+#[derive(Clone, Copy)]
+pub struct Edge {
+    /// Milliseconds of the controller clock
+    pub time: u64,
+    pub sensor: u8,
+    pub active: bool,
+}
+
+/// Reads the trace file at the given path
+///
+/// Returns the edges ordered by time
+pub fn load(path: &str) -> Result<Vec<Edge>, Box<dyn Error>> {
+    let text = fs::read_to_string(path)?;
+    let mut edges = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default();
+        if line.trim().is_empty() {
+            continue;
+        }
+        match parse_edge(line) {
+            Some(edge) => edges.push(edge),
+            None => return Err(format!("{}: line {} is not an edge", path, number + 1).into()),
+        }
+    }
+    // keeps edges of the same time in the order of the file
+    edges.sort_by_key(|edge| edge.time);
+    Ok(edges)
+}
+
+/// Writes the edge as a line of a trace
+pub fn write_edge(output: &mut dyn Write, edge: &Edge) -> std::io::Result<()> {
+    writeln!(
+        output,
+        "{} {} {}",
+        edge.time,
+        edge.sensor,
+        if edge.active { 1 } else { 0 }
+    )
+}
+
+fn parse_edge(line: &str) -> Option<Edge> {
+    let mut words = line.split_whitespace();
+    let edge = Edge {
+        time: words.next()?.parse().ok()?,
+        sensor: words.next()?.parse().ok()?,
+        active: match words.next()? {
+            "1" => true,
+            "0" => false,
+            _ => return None,
+        },
+    };
+    match words.next() {
+        None => Some(edge),
+        Some(_) => None,
+    }
+}
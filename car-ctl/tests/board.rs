@@ -0,0 +1,125 @@
+//! Runs `layouts/board.layout`, the layout of the firmware, through the model and checks it
+//! against the tables the firmware is wired by
+
+use std::cell::RefCell;
+use std::path::Path;
+
+use car_ctl::layout::Layout;
+use car_ctl::model::{self, Model};
+use car_system::board::{self, ARMS, CLEARANCE_TIME, INTERSECTION_ID, SETTLE_TIME};
+use car_system::intersection::SERVO_TIMEOUT;
+use car_system::serial::Serial;
+
+/// Milliseconds between two iterations of the main loop
+const STEP: u64 = 10;
+/// Milliseconds a phase change takes at most with the clearance and the servos of the board
+const PHASE_CHANGE: u64 = 3_000;
+
+struct Discard;
+
+impl Serial for Discard {
+    fn write_str(&mut self, _: &str) {}
+}
+
+fn board() -> Layout {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("layouts/board.layout");
+    Layout::load(path.to_str().unwrap()).unwrap()
+}
+
+/// Returns the value of the output with the name, see [`Model::outputs`]
+fn output(model: &Model, name: &str) -> String {
+    let mut outputs = model.outputs().into_iter();
+    outputs.find(|(output, _)| output == name).unwrap().1
+}
+
+/// Returns if an arm shows yellow or red-yellow
+fn changing(model: &Model) -> bool {
+    let lights = model.outputs().into_iter().filter(|(output, _)| output.starts_with("light"));
+    lights.map(|(_, state)| state).any(|state| state == "Y" || state == "RY")
+}
+
+/// Lets a car pass the sensor at the time
+fn pass(model: &mut Model, sensor: u8, time: u64) {
+    model.tick(time);
+    model.set_sensor(sensor, true);
+    model.poll_sensors();
+    model.set_sensor(sensor, false);
+    model.poll_sensors();
+}
+
+// the entry stopper of the left arm is the stopper of section 1, a car in the section has to
+// keep it locked through every phase, however often the intersection releases it
+#[test]
+fn occupied_section_keeps_its_entry_stopper_locked_across_phases() {
+    let serial = RefCell::new(Discard);
+    model::run(&board(), &serial, &serial, |model| {
+        let mut time = 1_000;
+        model.tick(time);
+        assert_eq!(output(model, "light 1 0"), "G");
+        assert_eq!(output(model, "stopper 1"), "released");
+
+        // sensor 2 starts section 1
+        pass(model, 2, time);
+        assert_eq!(output(model, "stopper 1"), "locked");
+
+        let mut greens = 0;
+        for _ in 0..8 {
+            for byte in b"NEXT 1\n" {
+                model.receive(*byte);
+            }
+            let end = time + PHASE_CHANGE;
+            while time < end {
+                time += STEP;
+                model.tick(time);
+                assert_eq!(output(model, "stopper 1"), "locked", "at {} ms", time);
+            }
+            if output(model, "light 1 0") == "G" {
+                greens += 1;
+            }
+        }
+        assert!(greens > 1, "the left arm had green in {} phases", greens);
+
+        // the plan moves on by itself as well, so a phase change may still run
+        let end = time + PHASE_CHANGE;
+        while time < end && changing(model) {
+            time += STEP;
+            model.tick(time);
+            assert_eq!(output(model, "stopper 1"), "locked", "at {} ms", time);
+        }
+
+        // sensor 5 ends section 1, the left arm has green again after the full cycle
+        assert_eq!(output(model, "light 1 0"), "G");
+        pass(model, 5, time + STEP);
+        assert_eq!(output(model, "stopper 1"), "released");
+    });
+}
+
+// the tests run the layout, not the firmware, so it has to describe the same board
+#[test]
+fn board_layout_matches_the_firmware() {
+    let layout = board();
+    let ids = |count: usize| (1..=count as u8).collect::<Vec<_>>();
+    assert_eq!(layout.stopper_ids(), ids(board::STOPPERS));
+    assert_eq!(layout.sensor_ids(), ids(board::SENSORS));
+
+    assert_eq!(layout.sections.len(), board::SECTIONS.len());
+    for expected in &board::SECTIONS {
+        let section = layout.sections.iter().find(|section| section.id == expected.id);
+        let section = section.unwrap_or_else(|| panic!("section {} is missing", expected.id));
+        assert_eq!(section.stoppers, expected.stoppers, "stoppers of section {}", expected.id);
+        let id = expected.id;
+        assert_eq!(section.start_sensors, expected.start_sensors, "start of section {}", id);
+        assert_eq!(section.end_sensors, expected.end_sensors, "end of section {}", id);
+    }
+
+    assert_eq!(layout.intersections.len(), 1, "the board has one intersection");
+    let intersection = &layout.intersections[0];
+    assert_eq!(intersection.id, INTERSECTION_ID);
+    assert_eq!(intersection.arms, ARMS);
+    // the firmware runs the T-junction preset without approaches or sensors in the box
+    assert!(intersection.phases.is_empty());
+    assert!(intersection.approaches.is_empty());
+    assert!(intersection.box_sensors.is_empty());
+    assert_eq!(intersection.clearance, CLEARANCE_TIME);
+    assert_eq!(intersection.servo_timing, (SETTLE_TIME, SERVO_TIMEOUT));
+}
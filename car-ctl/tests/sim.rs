@@ -0,0 +1,99 @@
+//! Drives the stand-in of `car-ctl sim` over its pseudo-terminal like the tools drive the board
+
+use std::io::{BufRead, BufReader};
+use std::process::{Child, ChildStdout, Command as Process, Stdio};
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
+
+use car_ctl::link::{self, Line};
+use car_system::intersection::IntersectionActionLight::*;
+use car_system::protocol::{Command, Event};
+
+/// Seconds an expected event may take to arrive
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The stand-in running the default layout, killed when dropped
+struct StandIn {
+    child: Child,
+    /// Kept open, the stand-in fails when it can't print anymore
+    _stdout: BufReader<ChildStdout>,
+}
+
+impl StandIn {
+    /// Starts the stand-in and returns it with the path of its pseudo-terminal
+    fn start() -> (StandIn, String) {
+        // stdin is kept open, the stand-in stops when it is closed
+        let mut child = Process::new(env!("CARGO_BIN_EXE_car-ctl"))
+            .arg("sim")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("the stand-in starts");
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+        let mut line = String::new();
+        stdout
+            .read_line(&mut line)
+            .expect("the stand-in tells its pseudo-terminal");
+        let path = line
+            .trim()
+            .strip_prefix("stand-in listening on ")
+            .expect("the first line names the pseudo-terminal")
+            .to_string();
+        let stand_in = StandIn {
+            child,
+            _stdout: stdout,
+        };
+        (stand_in, path)
+    }
+}
+
+impl Drop for StandIn {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Waits for the event, skipping the other lines
+fn expect(lines: &Receiver<Line>, expected: Event) {
+    let deadline = Instant::now() + TIMEOUT;
+    while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+        match lines.recv_timeout(left) {
+            Ok(Line::Event(event)) if event == expected => return,
+            Ok(_) => (),
+            Err(_) => break,
+        }
+    }
+    panic!("`{}` did not arrive", Line::Event(expected).text());
+}
+
+#[test]
+fn commands_are_answered_with_events() {
+    let (_stand_in, path) = StandIn::start();
+    let mut port = link::open(&path, link::BAUD_RATE).expect("the pseudo-terminal opens");
+    let lines = link::spawn_reader(port.try_clone().unwrap());
+
+    link::send(port.as_mut(), &Command::Lock(4)).unwrap();
+    expect(&lines, Event::Stopper { id: 4, locked: true });
+    link::send(port.as_mut(), &Command::Release(4)).unwrap();
+    expect(&lines, Event::Stopper { id: 4, locked: false });
+
+    link::send(port.as_mut(), &Command::EmergencyStop).unwrap();
+    expect(&lines, Event::EmergencyStop { engaged: true });
+    expect(&lines, Event::Stopper { id: 5, locked: true });
+    link::send(port.as_mut(), &Command::Resume).unwrap();
+    expect(&lines, Event::EmergencyStop { engaged: false });
+    expect(&lines, Event::Stopper { id: 5, locked: false });
+
+    // the first phase lasts far longer than the timeout, only the command ends it that early
+    link::send(port.as_mut(), &Command::NextPhase(1)).unwrap();
+    expect(
+        &lines,
+        Event::Light {
+            intersection: 1,
+            arm: 1,
+            light: Yellow,
+        },
+    );
+}
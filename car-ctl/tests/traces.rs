@@ -0,0 +1,60 @@
+//! Replays every checked in trace of `traces` and compares the outputs to its snapshot
+
+use std::fs;
+use std::path::Path;
+
+use car_ctl::check::{self, Outcome};
+use car_ctl::layout::Layout;
+use car_ctl::trace;
+use car_system::log::Level;
+
+/// The layout a trace runs on by the start of its name, the others run on `sim.layout`
+const LAYOUTS: [(&str, &str); 8] = [
+    ("board-", "board.layout"),
+    ("night-", "night.layout"),
+    ("pedestrian-", "pedestrian.layout"),
+    ("wave-", "wave.layout"),
+    ("preemption-", "preemption.layout"),
+    ("level-", "level.layout"),
+    ("roundabout-", "roundabout.layout"),
+    ("turnout-", "turnout.layout"),
+];
+
+/// Returns the layout file the trace with the name runs on
+fn layout_for(name: &str) -> &'static str {
+    let mut layouts = LAYOUTS.iter();
+    match layouts.find(|(prefix, _)| name.starts_with(prefix)) {
+        Some((_, layout)) => layout,
+        None => "sim.layout",
+    }
+}
+
+// the traces run one after another in a single test, they all set the mock clock of the logic
+#[test]
+fn traces_match_their_snapshots() {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let mut traces: Vec<_> = fs::read_dir(directory.join("traces"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension() == Some("trace".as_ref()))
+        .collect();
+    traces.sort();
+    assert!(!traces.is_empty(), "no traces found");
+
+    let mut failures = Vec::new();
+    for path in &traces {
+        let name = path.file_name().unwrap().to_str().unwrap();
+        let layout = directory.join("layouts").join(layout_for(name));
+        let layout = Layout::load(layout.to_str().unwrap()).unwrap();
+        let edges = trace::load(path.to_str().unwrap()).unwrap();
+        let snapshot = path.with_extension("snapshot");
+        let snapshot = snapshot.to_str().unwrap();
+        match check::run(&layout, &edges, snapshot, false, Level::Off) {
+            Ok(Outcome::Passed) => (),
+            Ok(Outcome::Failed(difference)) => failures.push(difference),
+            Ok(Outcome::Blessed) => unreachable!("the snapshots aren't written"),
+            Err(error) => failures.push(error.to_string()),
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
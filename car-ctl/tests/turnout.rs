@@ -0,0 +1,60 @@
+//! Sets the route of `layouts/turnout.layout` through the model, the traces only replay sensors
+//! and can't send the `ROUTE` command
+
+use std::cell::RefCell;
+use std::path::Path;
+
+use car_ctl::layout::Layout;
+use car_ctl::model::{self, Model};
+use car_system::serial::Serial;
+
+/// Servo angle of a diverging turnout, the left angle of the servos of the model
+const DIVERGING: u8 = 120;
+
+struct Discard;
+
+impl Serial for Discard {
+    fn write_str(&mut self, _: &str) {}
+}
+
+fn turnout_layout() -> Layout {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("layouts/turnout.layout");
+    Layout::load(path.to_str().unwrap()).unwrap()
+}
+
+fn send(model: &mut Model, command: &str) {
+    for byte in command.bytes() {
+        model.receive(byte);
+    }
+}
+
+/// Lets a car pass the sensor at the time
+fn pass(model: &mut Model, sensor: u8, time: u64) {
+    model.tick(time);
+    model.set_sensor(sensor, true);
+    model.poll_sensors();
+    model.set_sensor(sensor, false);
+    model.poll_sensors();
+}
+
+// turnout 1 is covered by section 1 and turnout 2 by section 2, both diverge on route 1, which
+// is set before the schedule of turnout 1 starts at 3 s
+#[test]
+fn route_moves_a_locked_turnout_once_its_section_is_free() {
+    let serial = RefCell::new(Discard);
+    model::run(&turnout_layout(), &serial, &serial, |model| {
+        model.tick(1_000);
+        model.take_servo_writes();
+
+        // sensor 11 starts section 1
+        pass(model, 11, 1_000);
+        send(model, "ROUTE 1\n");
+        model.tick(1_100);
+        assert_eq!(model.take_servo_writes(), [vec![21, DIVERGING]]);
+
+        // sensor 12 ends section 1, the turnout is commanded by the next call
+        pass(model, 12, 2_000);
+        model.tick(2_100);
+        assert_eq!(model.take_servo_writes(), [vec![20, DIVERGING]]);
+    });
+}
@@ -0,0 +1,66 @@
+//! Demand-actuated control of intersections
+//!
+//! Sensors on the approach of an arm report the cars arriving to the [`Approach`] of the arm. An
+//! intersection with [`Actuation`] settings then runs its green phases by demand instead of
+//! their fixed duration:
+//!
+//! * a green phase lasts at least `min_green`
+//! * it is extended while cars keep arriving on its green arms, at most `gap` apart
+//! * it ends when the gap runs out, which skips phases nobody is waiting for after `min_green`
+//! * it ends after `max_green` even if cars keep arriving
+//! * the rest phase is held while no car waits on any other arm
+//!
+//! The times are in milliseconds. Phases without green and phases with yellow or red-yellow lights
+//! keep their duration, so the lights always go through their transitions. Arms without an
+//! approach count as always waiting, an intersection without approaches runs like a fixed-time
+//! one.
+
+use core::cell::Cell;
+
+/// The arrivals on the approach of an arm, shared by its sensors and the intersection
+pub struct Approach {
+    /// The time in milliseconds the last car arrived
+    last_arrival: Cell<Option<u64>>,
+}
+
+impl Approach {
+    /// Returns an approach without arrivals
+    pub const fn new() -> Approach {
+        Approach {
+            last_arrival: Cell::new(None),
+        }
+    }
+
+    /// Records a car arriving
+    ///
+    /// # Arguments
+    ///
+    /// * `time` - the time in milliseconds the car was detected
+    pub fn arrive(&self, time: u64) {
+        self.last_arrival.set(Some(time));
+    }
+
+    /// Returns the time in milliseconds the last car arrived
+    pub fn last_arrival(&self) -> Option<u64> {
+        self.last_arrival.get()
+    }
+}
+
+impl Default for Approach {
+    fn default() -> Self {
+        Approach::new()
+    }
+}
+
+/// Settings of an actuated intersection, see the module documentation
+#[derive(Clone, Copy)]
+pub struct Actuation {
+    /// Minimum milliseconds of a green phase
+    pub min_green: u32,
+    /// Maximum milliseconds of a green phase
+    pub max_green: u32,
+    /// Maximum milliseconds between two cars which extend a green phase
+    pub gap: u32,
+    /// Index of the phase held while nobody is waiting
+    pub rest_phase: Option<usize>,
+}
@@ -0,0 +1,119 @@
+//! Configuration of the firmware
+//!
+//! The firmware in `src/main.rs` wires its sections and its intersection by these tables, and the
+//! host tools check `car-ctl/layouts/board.layout` against them, so the model of the board runs
+//! the configuration the board does. The pins of the parts are only known to the firmware.
+
+use core::cell::RefCell;
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+
+use crate::error::Result;
+use crate::section::Section;
+use crate::sensor::Sensor;
+use crate::sensor::SensorEnum::*;
+use crate::stopper::Stopper;
+
+/// A section of the board, the parts are given by their ids
+pub struct BoardSection {
+    pub id: u8,
+    pub stoppers: &'static [u8],
+    pub start_sensors: &'static [u8],
+    pub end_sensors: &'static [u8],
+}
+
+/// Number of stoppers of the board, their ids start at 1
+pub const STOPPERS: usize = 7;
+/// Number of sensors of the board, their ids start at 1
+pub const SENSORS: usize = 7;
+
+/// The sections of the board
+pub const SECTIONS: [BoardSection; 7] = [
+    BoardSection {
+        id: 1,
+        stoppers: &[1],
+        start_sensors: &[3, 2],
+        end_sensors: &[5, 6],
+    },
+    BoardSection {
+        id: 2,
+        stoppers: &[2],
+        start_sensors: &[3, 1],
+        end_sensors: &[5, 4],
+    },
+    BoardSection {
+        id: 3,
+        stoppers: &[3],
+        start_sensors: &[1, 2],
+        end_sensors: &[4, 6],
+    },
+    BoardSection {
+        id: 4,
+        stoppers: &[4],
+        start_sensors: &[4],
+        end_sensors: &[1, 2],
+    },
+    BoardSection {
+        id: 5,
+        stoppers: &[5],
+        start_sensors: &[5],
+        end_sensors: &[3, 2],
+    },
+    BoardSection {
+        id: 6,
+        stoppers: &[6],
+        start_sensors: &[6],
+        end_sensors: &[7],
+    },
+    BoardSection {
+        id: 7,
+        stoppers: &[7],
+        start_sensors: &[7],
+        end_sensors: &[3, 1],
+    },
+];
+
+/// Id of the intersection reported to the host, it runs the T-junction preset
+pub const INTERSECTION_ID: u8 = 1;
+/// The entry stoppers of the left, right and upper arm of the intersection, they are the stoppers
+/// of the sections 1, 2 and 3
+pub const ARMS: [u8; 3] = [1, 2, 3];
+/// Milliseconds of all-red between conflicting movements of the intersection
+pub const CLEARANCE_TIME: u32 = 1_000;
+/// Milliseconds the servos need to move the guides of the intersection
+pub const SETTLE_TIME: u32 = 500;
+
+impl BoardSection {
+    /// Adds the stoppers and sensors of the table to the section and sets its self reference
+    ///
+    /// Fails with [`Error::Config`](crate::error::Error::Config) when a part takes more than it
+    /// can
+    ///
+    /// # Arguments
+    ///
+    /// * `section` - the section with the id of the table
+    /// * `stoppers` - the stoppers of the board by their id, starting at 1
+    /// * `sensors` - the sensors of the board by their id, starting at 1
+    pub fn wire<'l, W, R>(
+        &self,
+        section: &'l RefCell<Section<'l, W, R>>,
+        stoppers: &[&'l RefCell<Stopper<W>>; STOPPERS],
+        sensors: &[&'l RefCell<Sensor<'l, W, R>>; SENSORS],
+    ) -> Result<()>
+    where
+        W: OutputPin,
+        R: InputPin,
+    {
+        let mut section_mut = section.borrow_mut();
+        section_mut.set_self_reference(section);
+        for id in self.stoppers {
+            section_mut.add_stopper(stoppers[*id as usize - 1])?;
+        }
+        for id in self.start_sensors {
+            section_mut.add_sensor(StartSensor(sensors[*id as usize - 1]))?;
+        }
+        for id in self.end_sensors {
+            section_mut.add_sensor(EndSensor(sensors[*id as usize - 1]))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,46 @@
+//! All-red clearance of intersections
+//!
+//! When the next state gives green to a movement which conflicts with a movement losing its green
+//! or yellow, the cars of the old movement may still be crossing. An intersection with a
+//! clearance time then holds the new greens back: the arms losing their green show red, the arms
+//! getting green keep their light from before and their entry stoppers stay locked. The next state
+//! is applied when the clearance time passed and the [`Occupancy`] of the box, if the intersection
+//! has one, tells that no car is inside anymore.
+
+use core::cell::Cell;
+
+/// Whether cars are inside the box of an intersection, told by the sensors in the box
+pub struct Occupancy {
+    /// The number of sensors in the box detecting a car
+    active_sensors: Cell<u8>,
+}
+
+impl Occupancy {
+    /// Returns an empty box
+    pub const fn new() -> Occupancy {
+        Occupancy {
+            active_sensors: Cell::new(0),
+        }
+    }
+
+    /// Records a sensor in the box starting to detect a car
+    pub fn enter(&self) {
+        self.active_sensors.set(self.active_sensors.get().saturating_add(1));
+    }
+
+    /// Records a sensor in the box no longer detecting a car
+    pub fn leave(&self) {
+        self.active_sensors.set(self.active_sensors.get().saturating_sub(1));
+    }
+
+    /// Returns if any sensor in the box detects a car
+    pub fn occupied(&self) -> bool {
+        self.active_sensors.get() > 0
+    }
+}
+
+impl Default for Occupancy {
+    fn default() -> Self {
+        Occupancy::new()
+    }
+}
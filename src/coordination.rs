@@ -0,0 +1,88 @@
+//! Coordination of intersections to a green wave
+//!
+//! Without coordination every intersection runs its plan from the moment it started, so the greens
+//! of neighbouring intersections drift apart. With a [`Coordination`] all intersections share a
+//! cycle length, which their plans have to last, and each starts its first state when the clock
+//! minus its offset is a multiple of the cycle length. With offsets matching the driving time
+//! between them, a platoon of cars released by one intersection arrives at the next on green.
+//!
+//! An intersection falls into its cycle by correcting its greens: a state with green and without
+//! yellow or red-yellow lights lasts until it was planned to end, but at least half and at most
+//! twice its duration, so it may take a few cycles after the start. Every start of the plan plans
+//! the cycle anew, after a night mode or an exclusive pedestrian phase the intersection finds back
+//! into its cycle the same way. Actuated greens end by demand and aren't corrected.
+
+/// Settings of the coordination of an intersection, see the module documentation
+#[derive(Clone, Copy)]
+pub struct Coordination {
+    /// The milliseconds of the common cycle
+    cycle: u32,
+    /// The milliseconds the cycle of the intersection starts after the common one
+    offset: u32,
+}
+
+impl Coordination {
+    /// Returns the settings, `None` for a cycle without milliseconds or an offset which isn't
+    /// shorter than the cycle
+    ///
+    /// # Arguments
+    ///
+    /// * `cycle` - the milliseconds of the common cycle
+    /// * `offset` - the milliseconds the cycle of the intersection starts after the common one
+    pub const fn new(cycle: u32, offset: u32) -> Option<Coordination> {
+        match offset < cycle {
+            true => Some(Coordination { cycle, offset }),
+            false => None,
+        }
+    }
+
+    /// Returns the milliseconds of the common cycle
+    pub fn cycle(&self) -> u32 {
+        self.cycle
+    }
+
+    /// Returns the milliseconds the cycle of the intersection starts after the common one
+    pub fn offset(&self) -> u32 {
+        self.offset
+    }
+
+    /// Returns the time in milliseconds of the start of the cycle nearest to the given time
+    ///
+    /// # Arguments
+    ///
+    /// * `time` - the time in milliseconds
+    pub fn cycle_start(&self, time: u64) -> u64 {
+        let cycle = self.cycle as u64;
+        // the milliseconds since the last start of the cycle
+        let position = (time + cycle - self.offset as u64 % cycle) % cycle;
+        match position <= cycle / 2 {
+            true => time.saturating_sub(position),
+            false => time + cycle - position,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Coordination;
+
+    #[test]
+    fn cycle_without_milliseconds_or_offset_past_it_is_rejected() {
+        assert!(Coordination::new(0, 0).is_none());
+        assert!(Coordination::new(10_000, 10_000).is_none());
+        assert!(Coordination::new(10_000, 9_999).is_some());
+    }
+
+    #[test]
+    fn cycle_start_is_the_nearest_start_after_the_offset() {
+        let coordination = Coordination::new(10_000, 2_000).unwrap();
+        assert_eq!(coordination.cycle_start(2_000), 2_000);
+        assert_eq!(coordination.cycle_start(7_000), 2_000);
+        assert_eq!(coordination.cycle_start(7_001), 12_000);
+        assert_eq!(coordination.cycle_start(13_500), 12_000);
+        assert_eq!(coordination.cycle_start(0), 2_000);
+        // the nearest start is before the clock started, there is no time before 0
+        let late = Coordination::new(10_000, 8_000).unwrap();
+        assert_eq!(late.cycle_start(1_000), 0);
+    }
+}
@@ -0,0 +1,316 @@
+//! Crash records kept in the EEPROM across resets
+//!
+//! When the firmware panics or a fault can't be handled, a compact record with the location, the
+//! uptime and the last events is written to a reserved area of the EEPROM. It is reported at the
+//! next boot and on the `CRASH` command until it is cleared with `CRASH CLEAR`:
+//!
+//! ```text
+//! CRASH PANIC src/section.rs:42 UPTIME 123456
+//! CRASH EV SECTION 1 2
+//! CRASH EV STOPPER 1 1
+//! ```
+//!
+//! The record is protected by a CRC, so an erased or half written area reads as no record.
+
+use crate::intersection::IntersectionActionDirection::*;
+use crate::intersection::IntersectionActionLight::{self, *};
+use crate::protocol::Event;
+use crate::serial::Serial;
+
+/// Size of a record in the EEPROM in bytes
+pub const RECORD_SIZE: usize = 9 + FILE_LENGTH + RECORD_EVENTS * EVENT_SIZE + 2;
+/// Number of events a record keeps
+pub const RECORD_EVENTS: usize = 4;
+/// Number of bytes of the file name a record keeps, longer names are cut at the front
+const FILE_LENGTH: usize = 24;
+/// Size of an event in a record in bytes
+const EVENT_SIZE: usize = 8;
+/// First byte of a valid record
+const MAGIC: u8 = 0xc7;
+
+/// Byte wise access to the EEPROM
+pub trait Eeprom {
+    /// Reads the byte at the address
+    fn read_byte(&mut self, address: u16) -> u8;
+
+    /// Writes the byte at the address
+    fn write_byte(&mut self, address: u16, byte: u8);
+}
+
+/// What caused the crash
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum CrashKind {
+    /// The firmware panicked
+    Panic,
+    /// A fault with the given code couldn't be handled, the file of the record is the name of the
+    /// error and the line the id of the failed part
+    Fault(u8),
+    /// A task missed its deadline, the file of the record is the name of the task
+    Watchdog,
+}
+
+/// Struct describing a crash, see the module documentation
+#[derive(Clone, Copy)]
+pub struct CrashRecord {
+    pub kind: CrashKind,
+    /// Milliseconds since the boot
+    pub uptime: u32,
+    /// The end of the file name, padded with zeros
+    file: [u8; FILE_LENGTH],
+    pub line: u16,
+    /// The last events from the oldest to the newest
+    events: [Option<Event>; RECORD_EVENTS],
+}
+
+impl CrashRecord {
+    /// Returns a record without events
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - what caused the crash
+    /// * `file` - the file where it happened
+    /// * `line` - the line where it happened
+    /// * `uptime` - the milliseconds since the boot
+    pub fn new(kind: CrashKind, file: &str, line: u32, uptime: u64) -> CrashRecord {
+        let bytes = file.as_bytes();
+        let bytes = &bytes[bytes.len().saturating_sub(FILE_LENGTH)..];
+        let mut file = [0; FILE_LENGTH];
+        file[..bytes.len()].copy_from_slice(bytes);
+        CrashRecord {
+            kind,
+            uptime: uptime.min(u32::MAX as u64) as u32,
+            file,
+            line: line.min(u16::MAX as u32) as u16,
+            events: [None; RECORD_EVENTS],
+        }
+    }
+
+    /// Adds an event, only the last [`RECORD_EVENTS`] events are kept
+    pub fn push_event(&mut self, event: Event) {
+        self.events.rotate_left(1);
+        self.events[RECORD_EVENTS - 1] = Some(event);
+    }
+
+    /// Returns the file where the crash happened
+    pub fn file(&self) -> &str {
+        let length = self.file.iter().position(|byte| *byte == 0).unwrap_or(FILE_LENGTH);
+        core::str::from_utf8(&self.file[..length]).unwrap_or("?")
+    }
+
+    /// Writes the record to the EEPROM
+    ///
+    /// # Arguments
+    ///
+    /// * `eeprom` - the EEPROM to write to
+    /// * `address` - the first address of the reserved area
+    pub fn store(&self, eeprom: &mut dyn Eeprom, address: u16) {
+        for (offset, byte) in self.encode().iter().enumerate() {
+            eeprom.write_byte(address + offset as u16, *byte);
+        }
+    }
+
+    /// Reads the record from the EEPROM
+    ///
+    /// Returns `None` if there is no valid record
+    ///
+    /// # Arguments
+    ///
+    /// * `eeprom` - the EEPROM to read from
+    /// * `address` - the first address of the reserved area
+    pub fn load(eeprom: &mut dyn Eeprom, address: u16) -> Option<CrashRecord> {
+        let mut bytes = [0; RECORD_SIZE];
+        for (offset, byte) in bytes.iter_mut().enumerate() {
+            *byte = eeprom.read_byte(address + offset as u16);
+        }
+        CrashRecord::decode(&bytes)
+    }
+
+    /// Removes the record from the EEPROM
+    ///
+    /// # Arguments
+    ///
+    /// * `eeprom` - the EEPROM to clear the record in
+    /// * `address` - the first address of the reserved area
+    pub fn clear(eeprom: &mut dyn Eeprom, address: u16) {
+        // a wrong magic byte is enough, it saves wearing out the rest of the area
+        eeprom.write_byte(address, !MAGIC);
+    }
+
+    /// Writes the record as lines starting with `CRASH`
+    ///
+    /// # Arguments
+    ///
+    /// * `serial` - the output to write the record to
+    pub fn write(&self, serial: &mut dyn Serial) {
+        let result = match self.kind {
+            CrashKind::Panic => ufmt::uwrite!(serial, "CRASH PANIC {}:{}", self.file(), self.line),
+            CrashKind::Fault(code) => {
+                ufmt::uwrite!(serial, "CRASH FAULT {} {}:{}", code, self.file(), self.line)
+            }
+            CrashKind::Watchdog => ufmt::uwrite!(serial, "CRASH WATCHDOG {}", self.file()),
+        };
+        result.unwrap();
+        ufmt::uwriteln!(serial, " UPTIME {}", self.uptime).unwrap();
+        for event in self.events.iter().flatten() {
+            serial.write_str("CRASH ");
+            event.write(serial);
+        }
+    }
+
+    fn encode(&self) -> [u8; RECORD_SIZE] {
+        let mut bytes = [0; RECORD_SIZE];
+        bytes[0] = MAGIC;
+        (bytes[1], bytes[2]) = match self.kind {
+            CrashKind::Panic => (1, 0),
+            CrashKind::Fault(code) => (2, code),
+            CrashKind::Watchdog => (3, 0),
+        };
+        bytes[3..5].copy_from_slice(&self.line.to_le_bytes());
+        bytes[5..9].copy_from_slice(&self.uptime.to_le_bytes());
+        bytes[9..9 + FILE_LENGTH].copy_from_slice(&self.file);
+        let events = &mut bytes[9 + FILE_LENGTH..RECORD_SIZE - 2];
+        for (event, slot) in self.events.iter().zip(events.chunks_exact_mut(EVENT_SIZE)) {
+            if let Some(event) = event {
+                encode_event(event, slot);
+            }
+        }
+        let crc = crc16(&bytes[..RECORD_SIZE - 2]);
+        bytes[RECORD_SIZE - 2..].copy_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+
+    fn decode(bytes: &[u8; RECORD_SIZE]) -> Option<CrashRecord> {
+        let crc = u16::from_le_bytes([bytes[RECORD_SIZE - 2], bytes[RECORD_SIZE - 1]]);
+        if bytes[0] != MAGIC || crc != crc16(&bytes[..RECORD_SIZE - 2]) {
+            return None;
+        }
+        let mut record = CrashRecord {
+            kind: match bytes[1] {
+                1 => CrashKind::Panic,
+                2 => CrashKind::Fault(bytes[2]),
+                3 => CrashKind::Watchdog,
+                _ => return None,
+            },
+            uptime: u32::from_le_bytes([bytes[5], bytes[6], bytes[7], bytes[8]]),
+            file: [0; FILE_LENGTH],
+            line: u16::from_le_bytes([bytes[3], bytes[4]]),
+            events: [None; RECORD_EVENTS],
+        };
+        record.file.copy_from_slice(&bytes[9..9 + FILE_LENGTH]);
+        let events = &bytes[9 + FILE_LENGTH..RECORD_SIZE - 2];
+        for (event, slot) in record.events.iter_mut().zip(events.chunks_exact(EVENT_SIZE)) {
+            *event = decode_event(slot);
+        }
+        Some(record)
+    }
+}
+
+/// Writes the crash record in the EEPROM or `CRASH NONE` if there is none
+///
+/// # Arguments
+///
+/// * `eeprom` - the EEPROM to read the record from
+/// * `address` - the first address of the reserved area
+/// * `serial` - the output to write the record to
+pub fn report(eeprom: &mut dyn Eeprom, address: u16, serial: &mut dyn Serial) {
+    match CrashRecord::load(eeprom, address) {
+        Some(record) => record.write(serial),
+        None => serial.write_str("CRASH NONE\n"),
+    }
+}
+
+/// Writes the event into a slot of [`EVENT_SIZE`] bytes, times are cut to 32 bits
+fn encode_event(event: &Event, slot: &mut [u8]) {
+    match *event {
+        Event::Sensor { id, time } => {
+            slot[..2].copy_from_slice(&[1, id]);
+            slot[2..6].copy_from_slice(&(time as u32).to_le_bytes());
+        }
+        Event::SensorEdge { id, active, time } => {
+            slot[..3].copy_from_slice(&[2, id, active as u8]);
+            slot[3..7].copy_from_slice(&(time as u32).to_le_bytes());
+        }
+        Event::Section { id, locks } => slot[..3].copy_from_slice(&[3, id, locks as u8]),
+        Event::Stopper { id, locked } => slot[..3].copy_from_slice(&[4, id, locked as u8]),
+        Event::Light {
+            intersection,
+            arm,
+            light,
+        } => slot[..4].copy_from_slice(&[5, intersection, arm, light_code(&light)]),
+        Event::EmergencyStop { engaged } => slot[..2].copy_from_slice(&[6, engaged as u8]),
+        Event::Fault { code, id } => slot[..3].copy_from_slice(&[7, code, id]),
+    }
+}
+
+/// Reads an event written by [`encode_event`], returns `None` for an empty slot
+fn decode_event(slot: &[u8]) -> Option<Event> {
+    let time = |start: usize| {
+        u32::from_le_bytes([slot[start], slot[start + 1], slot[start + 2], slot[start + 3]]) as u64
+    };
+    let event = match slot[0] {
+        1 => Event::Sensor {
+            id: slot[1],
+            time: time(2),
+        },
+        2 => Event::SensorEdge {
+            id: slot[1],
+            active: slot[2] != 0,
+            time: time(3),
+        },
+        3 => Event::Section {
+            id: slot[1],
+            locks: slot[2] as i8,
+        },
+        4 => Event::Stopper {
+            id: slot[1],
+            locked: slot[2] != 0,
+        },
+        5 => Event::Light {
+            intersection: slot[1],
+            arm: slot[2],
+            light: light_from_code(slot[3])?,
+        },
+        6 => Event::EmergencyStop {
+            engaged: slot[1] != 0,
+        },
+        7 => Event::Fault {
+            code: slot[1],
+            id: slot[2],
+        },
+        _ => return None,
+    };
+    Some(event)
+}
+
+fn light_code(light: &IntersectionActionLight) -> u8 {
+    match light {
+        Green(Right) => 0,
+        Green(Left) => 1,
+        Yellow => 2,
+        Red => 3,
+        RedYellow => 4,
+        Off => 5,
+        FlashingYellow => 6,
+    }
+}
+
+fn light_from_code(code: u8) -> Option<IntersectionActionLight> {
+    [Green(Right), Green(Left), Yellow, Red, RedYellow, Off, FlashingYellow]
+        .get(code as usize)
+        .copied()
+}
+
+/// CRC-16/CCITT-FALSE of the bytes
+fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc: u16 = 0xffff;
+    for byte in bytes {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = match crc & 0x8000 {
+                0 => crc << 1,
+                _ => (crc << 1) ^ 0x1021,
+            };
+        }
+    }
+    crc
+}
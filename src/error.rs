@@ -0,0 +1,122 @@
+//! Errors of the control logic
+//!
+//! Every operation on a pin or the I2C bus returns a [`Result`] with an [`Error`] naming the part
+//! which failed. The caller passes it to a [`FaultHandler`](crate::fault::FaultHandler), which
+//! decides by the [`ErrorClass`] how the system carries on. Setting up a part with more than it
+//! can take fails with [`Error::Config`].
+
+use crate::log::Module;
+
+/// Result of the control logic
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// Error of the control logic, the ids are the ones reported to the host
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// Writing the pin of the stopper with the id failed
+    StopperPin(u8),
+    /// Reading the pin of the sensor with the id failed
+    SensorPin(u8),
+    /// Writing a pin of a traffic light failed
+    LightPin,
+    /// Sending the direction to the servo with the id over I2C failed
+    ServoI2c(u8),
+    /// The given servo angle is above 180 degrees
+    InvalidAngle(u8),
+    /// The intersection with the id was to give green to conflicting movements
+    SignalConflict(u8),
+    /// An entry stopper of the intersection with the id was released without green
+    OpenStopper(u8),
+    /// The servo with the id couldn't be positioned in time, its direction is unknown
+    ServoTimeout(u8),
+    /// The part with the id was set up with more than it can take, or with settings it can't run
+    Config(u8),
+}
+
+/// How an error affects the system
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// The operation may succeed when it is tried again
+    Transient,
+    /// The system can carry on without the failed part, which has to be made safe
+    Degradable,
+    /// The outputs can't be trusted anymore, only the fail-safe state is safe
+    Fatal,
+}
+
+impl Error {
+    /// Returns how the error affects the system
+    pub fn class(&self) -> ErrorClass {
+        match self {
+            Error::ServoI2c(_) => ErrorClass::Transient,
+            Error::SensorPin(_) | Error::SignalConflict(_) | Error::OpenStopper(_) => {
+                ErrorClass::Degradable
+            }
+            Error::StopperPin(_)
+            | Error::LightPin
+            | Error::InvalidAngle(_)
+            | Error::ServoTimeout(_)
+            | Error::Config(_) => ErrorClass::Fatal,
+        }
+    }
+
+    /// Returns the code of the error kind kept in a crash record
+    pub fn code(&self) -> u8 {
+        match self {
+            Error::StopperPin(_) => 1,
+            Error::SensorPin(_) => 2,
+            Error::LightPin => 3,
+            Error::ServoI2c(_) => 4,
+            Error::InvalidAngle(_) => 5,
+            Error::SignalConflict(_) => 6,
+            Error::OpenStopper(_) => 7,
+            Error::ServoTimeout(_) => 8,
+            Error::Config(_) => 9,
+        }
+    }
+
+    /// Returns the id of the failed part, the angle for an invalid angle and 0 for a light
+    pub fn id(&self) -> u8 {
+        match *self {
+            Error::StopperPin(id)
+            | Error::SensorPin(id)
+            | Error::ServoI2c(id)
+            | Error::InvalidAngle(id)
+            | Error::SignalConflict(id)
+            | Error::OpenStopper(id)
+            | Error::ServoTimeout(id)
+            | Error::Config(id) => id,
+            Error::LightPin => 0,
+        }
+    }
+
+    /// Returns a short description of the error kind
+    pub fn name(&self) -> &'static str {
+        match self {
+            Error::StopperPin(_) => "stopper pin write failed",
+            Error::SensorPin(_) => "sensor pin read failed",
+            Error::LightPin => "light pin write failed",
+            Error::ServoI2c(_) => "servo i2c write failed",
+            Error::InvalidAngle(_) => "servo angle out of range",
+            Error::SignalConflict(_) => "conflicting greens refused",
+            Error::OpenStopper(_) => "entry stopper open without green",
+            Error::ServoTimeout(_) => "servo positioning timed out",
+            Error::Config(_) => "too many parts configured",
+        }
+    }
+
+    /// Returns the module the error is logged for
+    pub fn module(&self) -> Module {
+        match self {
+            Error::StopperPin(_) => Module::Stopper,
+            Error::SensorPin(_) => Module::Sensor,
+            Error::Config(_) => Module::Main,
+            Error::LightPin
+            | Error::ServoI2c(_)
+            | Error::InvalidAngle(_)
+            | Error::SignalConflict(_)
+            | Error::OpenStopper(_)
+            | Error::ServoTimeout(_) => Module::Intersection,
+        }
+    }
+}
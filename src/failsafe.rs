@@ -0,0 +1,89 @@
+//! Safe levels of the outputs for when the firmware fails
+//!
+//! When the firmware panics the logic can't be trusted anymore, so the panic handler drives the
+//! pins of a static registry directly: every stopper is locked and every light shows red. The
+//! registry lists the pins by port and bit, because the typed pins of the board are owned by the
+//! main loop and can't be reached from the panic handler.
+
+use crate::lights::LIGHT_ACTIVE;
+use crate::stopper::STOPPER_ACTIVE;
+
+/// I/O port of the ATmega2560
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Port {
+    A,
+    B,
+    C,
+    D,
+    E,
+    F,
+    G,
+    H,
+    J,
+    K,
+    L,
+}
+
+/// An output pin with the level it has to be driven to when the firmware fails
+#[derive(Clone, Copy)]
+pub struct SafePin {
+    pub port: Port,
+    pub bit: u8,
+    pub level: bool,
+}
+
+impl SafePin {
+    /// Returns the pin of a stopper which is safe when locked
+    ///
+    /// # Arguments
+    ///
+    /// * `port` - the port of the pin
+    /// * `bit` - the bit of the pin in the port
+    pub const fn stopper(port: Port, bit: u8) -> SafePin {
+        SafePin {
+            port,
+            bit,
+            level: STOPPER_ACTIVE,
+        }
+    }
+
+    /// Returns the pin of the green or yellow light of a traffic light, which is safe when off
+    ///
+    /// # Arguments
+    ///
+    /// * `port` - the port of the pin
+    /// * `bit` - the bit of the pin in the port
+    pub const fn light_off(port: Port, bit: u8) -> SafePin {
+        SafePin {
+            port,
+            bit,
+            level: !LIGHT_ACTIVE,
+        }
+    }
+
+    /// Returns the pin of the red light of a traffic light, which is safe when on
+    ///
+    /// # Arguments
+    ///
+    /// * `port` - the port of the pin
+    /// * `bit` - the bit of the pin in the port
+    pub const fn light_red(port: Port, bit: u8) -> SafePin {
+        SafePin {
+            port,
+            bit,
+            level: LIGHT_ACTIVE,
+        }
+    }
+}
+
+/// Drives every pin of the registry to its safe level
+///
+/// # Arguments
+///
+/// * `pins` - the registry of the safety critical pins
+/// * `write` - sets the pin given by port and bit to an output with the level
+pub fn drive_safe(pins: &[SafePin], mut write: impl FnMut(Port, u8, bool)) {
+    for pin in pins {
+        write(pin.port, pin.bit, pin.level);
+    }
+}
@@ -0,0 +1,85 @@
+//! Central decision how to carry on after an error
+//!
+//! The [`FaultHandler`] logs every error once, reports it as an [`Event::Fault`] so it is kept in
+//! the history and tells the caller what to do with it:
+//!
+//! * transient errors are retried, but escalated after [`MAX_RETRIES`] failures in a row
+//! * degradable errors take the failed part out of service, e.g. the stoppers of the sections of
+//!   a broken sensor are locked
+//! * fatal errors are escalated, the caller drives the fail-safe state and lets the board reset
+
+use crate::error::{Error, ErrorClass};
+use crate::log::Logger;
+use crate::protocol::Event;
+use crate::serial::Serial;
+
+/// Number of times a transient error is retried in a row before it is escalated
+pub const MAX_RETRIES: u8 = 3;
+
+/// What the caller has to do after an error
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Try the failed operation again
+    Retry,
+    /// Take the failed part out of service and carry on
+    Degrade,
+    /// Give up and drive the fail-safe state
+    Escalate,
+}
+
+/// Struct which decides about every error, see the module documentation
+pub struct FaultHandler {
+    /// Number of transient errors in a row
+    retries: u8,
+    /// The error handled last, so a lasting error is only logged once
+    last_error: Option<Error>,
+}
+
+impl FaultHandler {
+    /// Returns a fault handler which hasn't seen an error
+    pub const fn new() -> FaultHandler {
+        FaultHandler {
+            retries: 0,
+            last_error: None,
+        }
+    }
+
+    /// Logs and reports the error and returns what to do about it
+    ///
+    /// # Arguments
+    ///
+    /// * `error` - the error to handle
+    /// * `log` - the logger the error is written to
+    /// * `serial` - the output the event of the error is written to
+    pub fn handle(&mut self, error: Error, log: &Logger, serial: &mut dyn Serial) -> Action {
+        if self.last_error != Some(error) {
+            crate::error!(log, error.module(), "{} (id {}, code {})", error.name(), error.id(), error.code());
+            Event::Fault {
+                code: error.code(),
+                id: error.id(),
+            }
+            .write(serial);
+            self.last_error = Some(error);
+        }
+        match error.class() {
+            ErrorClass::Transient if self.retries < MAX_RETRIES => {
+                self.retries += 1;
+                Action::Retry
+            }
+            ErrorClass::Transient | ErrorClass::Fatal => Action::Escalate,
+            ErrorClass::Degradable => Action::Degrade,
+        }
+    }
+
+    /// Tells the handler that a retried operation succeeded, which ends the series of retries
+    pub fn succeeded(&mut self) {
+        self.retries = 0;
+        self.last_error = None;
+    }
+}
+
+impl Default for FaultHandler {
+    fn default() -> Self {
+        FaultHandler::new()
+    }
+}
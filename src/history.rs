@@ -0,0 +1,108 @@
+//! Recent events kept in RAM for when nobody was connected
+//!
+//! The [`History`] is written to like any other [`Serial`] and keeps the significant events among
+//! the lines, usually behind a [`Tee`](crate::serial::Tee) with the UART. On the `DUMP` command
+//! the events are written from the oldest to the newest with the time they were recorded:
+//!
+//! ```text
+//! HIST 1523 EV SECTION 1 1
+//! HIST 1523 EV STOPPER 1 1
+//! HIST END 2
+//! ```
+
+use crate::protocol::{Event, LineBuffer};
+use crate::serial::Serial;
+use crate::time::millis;
+
+/// Struct which keeps the last `N` significant events with the time they were recorded
+///
+/// Sensor edges are left out, they are too frequent and a detection is reported separately.
+pub struct History<const N: usize> {
+    entries: [Option<(u64, Event)>; N],
+    /// Index the next entry is written to
+    next: usize,
+    /// The line being written
+    line: LineBuffer,
+}
+
+impl<const N: usize> History<N> {
+    /// Returns an empty history
+    pub const fn new() -> History<N> {
+        History {
+            entries: [None; N],
+            next: 0,
+            line: LineBuffer::new(),
+        }
+    }
+
+    /// Adds the event, replacing the oldest one when the history is full
+    ///
+    /// # Arguments
+    ///
+    /// * `time` - the time in milliseconds the event happened
+    /// * `event` - the event to add
+    pub fn record(&mut self, time: u64, event: Event) {
+        if let Event::SensorEdge { .. } = event {
+            return;
+        }
+        self.entries[self.next] = Some((time, event));
+        self.next = (self.next + 1) % N;
+    }
+
+    /// Writes the events from the oldest to the newest, followed by their number
+    ///
+    /// # Arguments
+    ///
+    /// * `serial` - the output to write the events to
+    pub fn dump(&self, serial: &mut dyn Serial) {
+        let mut count: usize = 0;
+        for &(time, event) in self.entries() {
+            ufmt::uwrite!(serial, "HIST {} ", time).unwrap();
+            event.write(serial);
+            count += 1;
+        }
+        ufmt::uwriteln!(serial, "HIST END {}", count).unwrap();
+    }
+
+    /// Returns the events with their time from the oldest to the newest
+    pub fn entries(&self) -> impl Iterator<Item = &(u64, Event)> {
+        let (newer, older) = self.entries.split_at(self.next);
+        older.iter().chain(newer).flatten()
+    }
+
+    /// Removes every event
+    pub fn clear(&mut self) {
+        self.entries = [None; N];
+        self.next = 0;
+    }
+}
+
+impl<const N: usize> Default for History<N> {
+    fn default() -> Self {
+        History::new()
+    }
+}
+
+impl<const N: usize> Serial for History<N> {
+    fn write_str(&mut self, s: &str) {
+        for byte in s.bytes() {
+            let event = self.line.push_line(byte).and_then(Event::parse);
+            if let Some(event) = event {
+                self.record(millis(), event);
+            }
+        }
+    }
+}
+
+/// Parses a line written by [`History::dump`]
+///
+/// Returns the time and event or `None` if the line is no event of a history
+///
+/// # Arguments
+///
+/// * `line` - the line without the line ending
+pub fn parse_entry(line: &str) -> Option<(u64, Event)> {
+    let rest = line.strip_prefix("HIST ")?;
+    let (time, event) = rest.split_once(' ')?;
+    Some((time.parse().ok()?, Event::parse(event)?))
+}
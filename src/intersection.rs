@@ -3,19 +3,36 @@ use embedded_hal::blocking::i2c;
 use embedded_hal::blocking::i2c::SevenBitAddress;
 use embedded_hal::digital::v2::OutputPin;
 
-use crate::intersection::IntersectionActionDirection::*;
+use crate::actuation::{Actuation, Approach};
+use crate::clearance::Occupancy;
+use crate::coordination::Coordination;
 use crate::intersection::IntersectionActionLight::*;
+use crate::error::{Error, Result};
 use crate::lights::*;
+use crate::log::{Logger, Module};
+use crate::monitor::{ConflictMatrix, Movement, SafetyMonitor};
+use crate::night::{Night, NightMode, NightStep};
+use crate::pedestrian::PedestrianAspect::{self, *};
+use crate::pedestrian::{Crossing, ExclusivePhase, ExclusiveStep, PedestrianTiming, MAX_CROSSINGS};
+use crate::plan::MAX_PHASES;
+use crate::preemption::{Preemption, PreemptionRun, PreemptionStep};
+use crate::schedule::Schedule;
 use crate::servo::Servo;
 use crate::stopper::Stopper;
+use crate::time::millis;
 
-const LONG_STATE_TIME: u32 = 18;
-const SHORT_STATE_TIME: u32 = 2;
+/// Maximum number of arms of an intersection
+pub const MAX_ARMS: usize = 8;
 
-pub trait CustomIterator {
-    fn next(&mut self) -> &IntersectionState;
-    fn current(&self) -> &IntersectionState;
-}
+/// Index of the left arm of the T-junction preset
+pub const LEFT_ARM: usize = 0;
+/// Index of the right arm of the T-junction preset
+pub const RIGHT_ARM: usize = 1;
+/// Index of the upper arm of the T-junction preset
+pub const UPPER_ARM: usize = 2;
+
+/// Default milliseconds the servos of new greens may take to be positioned
+pub const SERVO_TIMEOUT: u32 = 2_000;
 
 pub struct IntersectionArm<'l, I2C, W, S>
 where
@@ -26,218 +43,1456 @@ where
     pub entry_stopper: &'l RefCell<Stopper<W>>,
     pub light: Light<W>,
     pub servo: &'l RefCell<Servo<'l, I2C, S>>,
+    /// The cars arriving on the arm, without an approach the arm always counts as waiting
+    pub approach: Option<&'l Approach>,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub enum IntersectionActionDirection {
     Right,
     Left,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub enum IntersectionActionLight {
     Green(IntersectionActionDirection),
     Yellow,
     Red,
     RedYellow,
     Off,
+    FlashingYellow,
 }
 
+/// The lights of every arm of an intersection for a time span
+#[derive(Clone, Copy)]
 pub struct IntersectionState {
-    left_action: IntersectionActionLight,
-    right_action: IntersectionActionLight,
-    upper_action: IntersectionActionLight,
+    /// The light of every arm by its index, arms an intersection doesn't have are ignored
+    actions: [IntersectionActionLight; MAX_ARMS],
+    /// The milliseconds the state lasts
     duration: u32,
 }
 
-pub struct DefaultIntersectionStates {
-    states: [IntersectionState; 8],
-    count: usize,
-}
-
-impl DefaultIntersectionStates {
-    pub fn new() -> DefaultIntersectionStates {
-        let states: [IntersectionState; 8] = [
-            // Phase 1
-            IntersectionState {
-                left_action: Green(Right),
-                right_action: Green(Right),
-                upper_action: Green(Right),
-                duration: LONG_STATE_TIME,
-            },
-            IntersectionState {
-                left_action: Green(Right),
-                right_action: Yellow,
-                upper_action: Green(Right),
-                duration: SHORT_STATE_TIME,
-            },
-            // Phase 2
-            IntersectionState {
-                left_action: Green(Left),
-                right_action: Red,
-                upper_action: Green(Right),
-                duration: LONG_STATE_TIME,
-            },
-            IntersectionState {
-                left_action: Green(Left),
-                right_action: RedYellow,
-                upper_action: Yellow,
-                duration: SHORT_STATE_TIME,
-            },
-            // Phase 3
-            IntersectionState {
-                left_action: Green(Right),
-                right_action: Green(Left),
-                upper_action: Red,
-                duration: LONG_STATE_TIME,
-            },
-            IntersectionState {
-                left_action: Yellow,
-                right_action: Green(Left),
-                upper_action: RedYellow,
-                duration: SHORT_STATE_TIME,
-            },
-            // Phase 4
-            IntersectionState {
-                left_action: Red,
-                right_action: Green(Right),
-                upper_action: Green(Left),
-                duration: LONG_STATE_TIME,
-            },
-            IntersectionState {
-                left_action: RedYellow,
-                right_action: Green(Right),
-                upper_action: Green(Left),
-                duration: SHORT_STATE_TIME,
-            },
-        ];
-        DefaultIntersectionStates { states, count: 7 }
+impl IntersectionState {
+    /// Returns a state, the arms after the given lights are off
+    ///
+    /// # Panic
+    /// Panics when there are more than MAX_ARMS lights
+    ///
+    /// # Arguments
+    ///
+    /// * `actions` - the light of every arm by its index
+    /// * `duration` - the milliseconds the state lasts
+    pub const fn new(actions: &[IntersectionActionLight], duration: u32) -> IntersectionState {
+        if actions.len() > MAX_ARMS {
+            panic!("no more than 8 arms allowed");
+        }
+        let mut state = IntersectionState {
+            actions: [Off; MAX_ARMS],
+            duration,
+        };
+        let mut arm = 0;
+        while arm < actions.len() {
+            state.actions[arm] = actions[arm];
+            arm += 1;
+        }
+        state
     }
-}
 
-impl CustomIterator for DefaultIntersectionStates {
-    fn next(&mut self) -> &IntersectionState {
-        self.count += 1;
-        if self.count == self.states.len() {
-            self.count = 0;
-        }
-        &self.states[self.count]
+    /// Returns the light of the arm with the index
+    pub fn action(&self, arm: usize) -> IntersectionActionLight {
+        self.actions[arm]
     }
 
-    fn current(&self) -> &IntersectionState {
-        &self.states[self.count]
+    /// Returns the milliseconds the state lasts
+    pub fn duration(&self) -> u32 {
+        self.duration
     }
 }
 
+/// The stage of the transition to the current state
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    /// The new greens are held back for the all-red clearance
+    Clearing,
+    /// The servos of the new greens were commanded and settle, their entry stoppers stay locked
+    Positioning,
+    /// The current state is applied
+    Applied,
+}
+
+/// Struct which switches the lights, entry stoppers and servos of the arms of an intersection
+/// through its states but has to be called regularily
+///
+/// An arm getting green goes through stages: after the all-red clearance its servo is commanded,
+/// and only when the servo had its settle time the light turns green and the entry stopper is
+/// released. Pedestrian crossings walk on demand, see [`crate::pedestrian`]. In the night mode
+/// the states are paused, see [`crate::night`]. A coordinated intersection corrects its greens to
+/// keep to a common cycle, see [`crate::coordination`]. Emergency vehicles preempt the states, see
+/// [`crate::preemption`].
 pub struct Intersection<'l, I2C, I, W, S>
 where
     I2C: i2c::Write<SevenBitAddress>,
-    I: CustomIterator,
+    I: Schedule,
     W: OutputPin,
     S: OutputPin,
 {
-    left_arm: IntersectionArm<'l, I2C, W, S>,
-    right_arm: IntersectionArm<'l, I2C, W, S>,
-    upper_arm: IntersectionArm<'l, I2C, W, S>,
+    /// The id reported to the host
+    id: u8,
+    /// The arms by their index, the first `arm_count` are set
+    arms: [Option<IntersectionArm<'l, I2C, W, S>>; MAX_ARMS],
+    arm_count: usize,
     states: I,
-    time_counter: u32,
+    /// The time in milliseconds the current state was executed
+    state_start: u64,
+    /// Checks every state, the arms are numbered by their index
+    monitor: SafetyMonitor,
+    /// Runs the green states by demand if set
+    actuation: Option<Actuation>,
+    /// The time in milliseconds every arm last had green
+    last_green: [u64; MAX_ARMS],
+    /// The lights applied to every arm, differing from the current state during a clearance
+    applied: [IntersectionActionLight; MAX_ARMS],
+    /// The direction every arm last had green in
+    directions: [Option<IntersectionActionDirection>; MAX_ARMS],
+    /// The milliseconds of the all-red clearance, see [`crate::clearance`]
+    clearance: u32,
+    /// Tells if cars are inside the box
+    occupancy: Option<&'l Occupancy>,
+    /// The stage of the transition to the current state
+    stage: Stage,
+    /// The time in milliseconds the stage started
+    stage_start: u64,
+    /// The arms whose servo still has to be commanded
+    pending: [bool; MAX_ARMS],
+    /// The time in milliseconds the last servo was commanded
+    last_command: u64,
+    /// The milliseconds a servo needs to reach its position after it was commanded
+    settle_time: u32,
+    /// The milliseconds the servos may take to be commanded, before the positioning failed
+    servo_timeout: u32,
+    /// The settings of the night mode
+    night_mode: Option<NightMode>,
+    /// The night mode if it is on
+    night: Option<Night>,
+    /// If the schedule of the night mode switched it on
+    scheduled: bool,
+    /// The pedestrian crossings
+    crossings: [Option<Crossing<'l, W>>; MAX_CROSSINGS],
+    /// What every crossing shows and the time in milliseconds it started to
+    walks: [(PedestrianAspect, u64); MAX_CROSSINGS],
+    pedestrian_timing: PedestrianTiming,
+    /// The exclusive pedestrian phase if it runs
+    exclusive: Option<ExclusivePhase>,
+    /// The common cycle the intersection keeps to if set
+    coordination: Option<Coordination>,
+    /// The time in milliseconds the current state should end by the coordination
+    planned_end: u64,
+    /// Tells the calls and clearances of emergency vehicles
+    preemption: Option<&'l Preemption>,
+    /// The preemption if one runs
+    preempting: Option<PreemptionRun>,
 }
 
 impl<'l, I2C, I, W, S> Intersection<'l, I2C, I, W, S>
 where
     I2C: i2c::Write<SevenBitAddress>,
-    I: CustomIterator,
+    I: Schedule,
     W: OutputPin,
     S: OutputPin,
 {
+    /// Returns a new intersection executing the current state of the schedule
+    ///
+    /// Fails with [`Error::Config`] when there are more than MAX_ARMS arms or the schedule isn't
+    /// valid for the arms and conflicts, see [`Schedule::validate`]
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - the id reported to the host
+    /// * `arms` - the arms, their index is the one used in the states and the conflict matrix
+    /// * `states` - the schedule of the states, see [`crate::schedule`]
+    /// * `conflicts` - the movements which mustn't have green together
     pub fn new(
-        left_arm: IntersectionArm<'l, I2C, W, S>,
-        right_arm: IntersectionArm<'l, I2C, W, S>,
-        upper_arm: IntersectionArm<'l, I2C, W, S>,
+        id: u8,
+        arms: impl IntoIterator<Item = IntersectionArm<'l, I2C, W, S>>,
         states: I,
-    ) -> Intersection<'l, I2C, I, W, S> {
+        conflicts: ConflictMatrix,
+    ) -> Result<Intersection<'l, I2C, I, W, S>> {
         let mut intersection = Intersection {
-            left_arm,
-            right_arm,
-            upper_arm,
+            id,
+            arms: Default::default(),
+            arm_count: 0,
             states,
-            time_counter: 0,
+            state_start: 0,
+            monitor: SafetyMonitor::new(conflicts),
+            actuation: None,
+            last_green: [0; MAX_ARMS],
+            applied: [Off; MAX_ARMS],
+            directions: [None; MAX_ARMS],
+            clearance: 0,
+            occupancy: None,
+            stage: Stage::Applied,
+            stage_start: 0,
+            pending: [false; MAX_ARMS],
+            last_command: 0,
+            settle_time: 0,
+            servo_timeout: SERVO_TIMEOUT,
+            night_mode: None,
+            night: None,
+            scheduled: false,
+            crossings: Default::default(),
+            walks: [(Dark, 0); MAX_CROSSINGS],
+            pedestrian_timing: PedestrianTiming::default(),
+            exclusive: None,
+            coordination: None,
+            planned_end: 0,
+            preemption: None,
+            preempting: None,
+        };
+        for arm in arms {
+            if intersection.arm_count == MAX_ARMS {
+                return Err(Error::Config(id));
+            }
+            intersection.arms[intersection.arm_count] = Some(arm);
+            intersection.arm_count += 1;
+        }
+        if intersection.states.validate(intersection.arm_count, &conflicts).is_err() {
+            return Err(Error::Config(id));
+        }
+        intersection.execute_current_state(millis())?;
+        Ok(intersection)
+    }
+
+    /// Runs the green states by the demand on the arms instead of their duration
+    ///
+    /// # Arguments
+    ///
+    /// * `actuation` - the settings, see [`crate::actuation`]
+    pub fn set_actuation(&mut self, actuation: Actuation) {
+        self.actuation = Some(actuation);
+    }
+
+    /// Holds new greens back while conflicting movements clear the intersection
+    ///
+    /// # Arguments
+    ///
+    /// * `clearance` - the milliseconds the new greens wait at least
+    /// * `occupancy` - tells if cars are inside the box, the new greens wait until it is empty
+    pub fn set_clearance(&mut self, clearance: u32, occupancy: Option<&'l Occupancy>) {
+        self.clearance = clearance;
+        self.occupancy = occupancy;
+    }
+
+    /// Sets how long the servos take before an arm gets green
+    ///
+    /// # Arguments
+    ///
+    /// * `settle_time` - the milliseconds a servo needs to reach its position after it was
+    ///   commanded
+    /// * `timeout` - the milliseconds a failing servo is commanded again before the positioning
+    ///   failed with [`Error::ServoTimeout`]
+    pub fn set_servo_timing(&mut self, settle_time: u32, timeout: u32) {
+        self.settle_time = settle_time;
+        self.servo_timeout = timeout;
+    }
+
+    /// Adds a pedestrian crossing, its signal head shows don't walk
+    ///
+    /// Fails with [`Error::Config`] when the intersection already has MAX_CROSSINGS crossings
+    ///
+    /// # Arguments
+    ///
+    /// * `crossing` - the crossing, see [`crate::pedestrian`]
+    pub fn add_crossing(&mut self, mut crossing: Crossing<'l, W>) -> Result<()> {
+        let index = match self.crossings.iter().position(Option::is_none) {
+            Some(index) => index,
+            None => return Err(Error::Config(self.id)),
+        };
+        let aspect = match self.night {
+            Some(_) => Dark,
+            None => DontWalk,
+        };
+        crossing.signal.set_aspect(aspect)?;
+        self.crossings[index] = Some(crossing);
+        self.walks[index] = (aspect, millis());
+        Ok(())
+    }
+
+    /// Sets how the pedestrian crossings walk
+    ///
+    /// # Arguments
+    ///
+    /// * `timing` - the settings, see [`crate::pedestrian`]
+    pub fn set_pedestrian_timing(&mut self, timing: PedestrianTiming) {
+        self.pedestrian_timing = timing;
+    }
+
+    /// Keeps the states to a common cycle with the other intersections
+    ///
+    /// The current state isn't corrected, the intersection falls into the cycle from the next
+    /// start of its plan on
+    ///
+    /// # Arguments
+    ///
+    /// * `coordination` - the settings, see [`crate::coordination`]
+    pub fn set_coordination(&mut self, coordination: Coordination) {
+        self.coordination = Some(coordination);
+        self.planned_end = self.state_start + self.states.current().duration as u64;
+    }
+
+    /// Lets sensors preempt the states for emergency vehicles
+    ///
+    /// # Arguments
+    ///
+    /// * `preemption` - tells the calls and clearances, see [`crate::preemption`]
+    pub fn set_preemption(&mut self, preemption: &'l Preemption) {
+        self.preemption = Some(preemption);
+    }
+
+    /// Ends the current state safely and gives green to the movement of an emergency vehicle
+    ///
+    /// An all-red clearance or a positioning of the servos which runs is finished first. Does
+    /// nothing in the night mode, during another preemption, for an arm the intersection doesn't
+    /// have or after the safety monitor found a violation
+    ///
+    /// # Arguments
+    ///
+    /// * `movement` - the arm the vehicle comes from and its direction
+    /// * `log` - the logger the preemption is logged to
+    pub fn preempt(&mut self, movement: Movement, log: &Logger) -> Result<()> {
+        let busy = self.night.is_some() || self.preempting.is_some();
+        if self.monitor.tripped() || busy || movement.arm >= self.arm_count {
+            let (id, arm) = (self.id, movement.arm);
+            crate::info!(log, Module::Preemption, "intersection {} ignores arm {}", id, arm);
+            return Ok(());
+        }
+        let (id, arm) = (self.id, movement.arm);
+        crate::info!(log, Module::Preemption, "intersection {} preempted for arm {}", id, arm);
+        self.preempting = Some(PreemptionRun::new(movement));
+        match self.stage {
+            Stage::Applied => self.preemption_phase(millis(), log),
+            Stage::Clearing | Stage::Positioning => Ok(()),
+        }
+    }
+
+    /// Tells that the emergency vehicle of the preemption cleared the intersection, so the plan
+    /// continues
+    ///
+    /// Does nothing without a preemption
+    pub fn clear_preemption(&mut self) {
+        if let Some(preempting) = &mut self.preempting {
+            preempting.clear();
+        }
+    }
+
+    /// Returns if an emergency vehicle preempts the states
+    pub fn preempting(&self) -> bool {
+        self.preempting.is_some()
+    }
+
+    /// Sets how the intersection runs in the night mode
+    ///
+    /// # Arguments
+    ///
+    /// * `night_mode` - the settings, see [`crate::night`]
+    pub fn set_night_mode(&mut self, night_mode: NightMode) {
+        self.night_mode = Some(night_mode);
+    }
+
+    /// Switches the night mode on or off
+    ///
+    /// Does nothing without night mode settings or after the safety monitor found a violation
+    ///
+    /// # Arguments
+    ///
+    /// * `night` - if the night mode should be on
+    /// * `log` - the logger the switch is logged to
+    pub fn set_night(&mut self, night: bool, log: &Logger) -> Result<()> {
+        let night_mode = match self.night_mode {
+            Some(night_mode) if !self.monitor.tripped() && self.night.is_some() != night => {
+                night_mode
+            }
+            _ => return Ok(()),
+        };
+        let time = millis();
+        let mode = match night {
+            true => "on",
+            false => "off",
+        };
+        crate::info!(log, Module::Night, "intersection {} night mode {}", self.id, mode);
+        self.stage_start = time;
+        self.pending = [false; MAX_ARMS];
+        self.exclusive = None;
+        self.preempting = None;
+        if !night {
+            // the cars let in by the night mode clear the box before the next state
+            self.night = None;
+            self.next_steady_state(time);
+            self.execute_after_all_red(time)?;
+            return self.set_walks(DontWalk, time);
+        }
+        for arm in 0..self.arm_count {
+            self.pending[arm] = night_mode.main_road[arm] && night_mode.directions[arm].is_some();
+        }
+        self.night = Some(Night::new(time));
+        self.execute_night()
+    }
+
+    /// Returns if the night mode is on
+    pub fn night(&self) -> bool {
+        self.night.is_some()
+    }
+
+    /// Executes the next state when the current one is over
+    ///
+    /// The states are timed by the clock from the moment they were executed, so they last their
+    /// duration however often this is called, a late call only makes a state last longer. Does
+    /// nothing after the safety monitor found a violation
+    ///
+    /// # Arguments
+    ///
+    /// * `log` - the logger the changes of the night mode, preemption, walks and cycle are logged
+    ///   to
+    pub fn call(&mut self, log: &Logger) -> Result<()> {
+        if self.monitor.tripped() {
+            return Ok(());
+        }
+        let time = millis();
+        if let Some(schedule) = self.night_mode.and_then(|night_mode| night_mode.schedule) {
+            let scheduled = schedule.contains(time);
+            if scheduled != self.scheduled {
+                self.scheduled = scheduled;
+                self.set_night(scheduled, log)?;
+            }
+        }
+        if let Some(preemption) = self.preemption {
+            if let Some(movement) = preemption.take_call() {
+                self.preempt(movement, log)?;
+            }
+            if preemption.take_cleared() {
+                self.clear_preemption();
+            }
+        }
+        if self.night.is_some() {
+            return self.call_night(time, log);
+        }
+        self.update_walks(time)?;
+        let elapsed = time.saturating_sub(self.state_start);
+        for (arm, last_green) in self.last_green[..self.arm_count].iter_mut().enumerate() {
+            if let Green(_) = self.applied[arm] {
+                *last_green = time;
+            }
+        }
+        match self.stage {
+            Stage::Clearing => {
+                let occupied = matches!(self.occupancy, Some(occupancy) if occupancy.occupied());
+                let cleared = time.saturating_sub(self.stage_start) >= self.clearance as u64;
+                // only a preemption ends a state while pedestrians still cross
+                if cleared && !occupied && !self.walking() {
+                    let next = self.states.current().actions;
+                    return self.start_positioning(self.applied, next, time);
+                }
+                return Ok(());
+            }
+            Stage::Positioning => return self.position(time),
+            Stage::Applied => (),
+        }
+        if self.preempting.is_some() {
+            return self.preemption_phase(time, log);
+        }
+        if self.exclusive.is_some() {
+            return self.exclusive_phase(time, log);
+        }
+        let state = *self.states.current();
+        let over = match self.actuation {
+            Some(actuation) => self.actuated_state_over(&actuation, &state, time, elapsed),
+            None => elapsed >= self.duration(&state) as u64,
+        };
+        if !over && !self.pedestrian_timing.exclusive {
+            self.start_concurrent_walks(time, log)?;
+        }
+        // a state lasts until its crossings are red again
+        if !over || self.walking() {
+            return Ok(());
+        }
+        let requested = self.crossings.iter().flatten().any(|crossing| crossing.demand.requested());
+        if self.pedestrian_timing.exclusive && requested {
+            return self.start_exclusive_phase(time);
+        }
+        self.execute_next_state()?;
+        if let (Some(coordination), 0) = (self.coordination, self.states.index()) {
+            let start = coordination.cycle_start(time);
+            let id = self.id;
+            crate::debug!(log, Module::Coordination, "intersection {} cycle at {}", id, start);
+        }
+        Ok(())
+    }
+
+    /// Ends the current state and executes the next one immediately
+    ///
+    /// Does nothing in the night mode, during a preemption and while pedestrians walk
+    pub fn next_phase(&mut self) -> Result<()> {
+        let preempted = self.preempting.is_some() || self.exclusive.is_some();
+        let paused = self.night.is_some() || preempted || self.walking();
+        if self.monitor.tripped() || paused {
+            return Ok(());
+        }
+        self.execute_next_state()
+    }
+
+    /// Sets the stoppers, lights and servos to the lights last applied again
+    ///
+    /// Used to retry a state which failed to be executed
+    pub fn retry(&mut self) -> Result<()> {
+        if self.monitor.tripped() {
+            return self.force_red();
+        }
+        if self.night.is_some() {
+            return self.execute_night();
+        }
+        self.execute_state(self.applied)
+    }
+
+    /// Returns the number of arms
+    pub fn arm_count(&self) -> usize {
+        self.arm_count
+    }
+
+    /// Returns the number of pedestrian crossings
+    pub fn crossing_count(&self) -> usize {
+        self.crossings.iter().flatten().count()
+    }
+
+    /// Returns the milliseconds left of the current state by its duration in the schedule
+    ///
+    /// A coordinated or actuated state may end at another time, and a state lasts until its
+    /// crossings are red again
+    pub fn remaining(&self) -> u32 {
+        let elapsed = millis().saturating_sub(self.state_start);
+        self.states.remaining(elapsed)
+    }
+
+    /// Returns the lights applied to every arm by its index
+    ///
+    /// Every light is red after the safety monitor found a violation
+    pub fn current_lights(&self) -> &[IntersectionActionLight] {
+        const ALL_RED: [IntersectionActionLight; MAX_ARMS] = [Red; MAX_ARMS];
+        if self.monitor.tripped() {
+            return &ALL_RED[..self.arm_count];
+        }
+        &self.applied[..self.arm_count]
+    }
+
+    /// Returns if the current state is over by the demand on the arms
+    ///
+    /// # Arguments
+    ///
+    /// * `time` - the current time in milliseconds
+    /// * `elapsed` - the milliseconds since the state was executed
+    fn actuated_state_over(
+        &self,
+        actuation: &Actuation,
+        state: &IntersectionState,
+        time: u64,
+        elapsed: u64,
+    ) -> bool {
+        let lights = &state.actions[..self.arm_count];
+        let green = lights.iter().any(|light| matches!(light, Green(_)));
+        let changing = lights.iter().any(|light| matches!(light, Yellow | RedYellow));
+        if !green || changing {
+            return elapsed >= state.duration as u64;
+        }
+
+        // if cars arrive on a green arm, if a car waits on another arm and if a green arm has no
+        // approach to tell
+        let mut arriving = false;
+        let mut waiting = false;
+        let mut fixed = false;
+        let arms = self.arms.iter().flatten().zip(lights).zip(self.last_green);
+        for ((arm, light), last_green) in arms {
+            let last_arrival = arm.approach.map(Approach::last_arrival);
+            match (last_arrival, light) {
+                (None, Green(_)) => fixed = true,
+                (None, _) => waiting = true,
+                (Some(Some(arrival)), Green(_)) => {
+                    arriving |= time.saturating_sub(arrival) <= actuation.gap as u64;
+                }
+                (Some(Some(arrival)), _) => waiting |= arrival > last_green,
+                (Some(None), _) => (),
+            }
+        }
+        // pedestrians count as waiting if they can't walk in this state
+        let mut crossings = self.crossings.iter().flatten();
+        waiting |= crossings.any(|crossing| {
+            crossing.demand.requested() && !self.may_walk(crossing)
+        });
+
+        let rest = actuation.rest_phase == Some(self.states.index()) && !self.states.preempted();
+        if !waiting && rest {
+            return false;
+        }
+        if fixed {
+            return elapsed >= self.duration(state) as u64;
+        }
+        if elapsed >= actuation.max_green as u64 {
+            return true;
+        }
+        elapsed >= actuation.min_green as u64 && !arriving
+    }
+
+    /// Returns the milliseconds the state lasts, corrected to end when the coordination planned it
+    /// if it is a green without yellow or red-yellow lights of the plan
+    fn duration(&self, state: &IntersectionState) -> u32 {
+        let lights = &state.actions[..self.arm_count];
+        let green = lights.iter().any(|light| matches!(light, Green(_)));
+        let changing = lights.iter().any(|light| matches!(light, Yellow | RedYellow));
+        match self.coordination {
+            Some(_) if green && !changing && !self.states.preempted() => {
+                let planned = self.planned_end.saturating_sub(self.state_start);
+                let duration = state.duration as u64;
+                planned.clamp(duration / 2, duration * 2) as u32
+            }
+            _ => state.duration,
+        }
+    }
+
+    /// Returns if a crossing has walk or its clearance
+    fn walking(&self) -> bool {
+        let mut aspects = self.walks.iter().map(|(aspect, _)| aspect);
+        aspects.any(|aspect| matches!(aspect, Walk | Clearance))
+    }
+
+    /// Blinks the signal heads of the crossings and switches them from walk to the clearance and
+    /// to don't walk when their time is over
+    fn update_walks(&mut self, time: u64) -> Result<()> {
+        let timing = self.pedestrian_timing;
+        for (crossing, (aspect, start)) in self.crossings.iter_mut().zip(&mut self.walks) {
+            let crossing = match crossing {
+                Some(crossing) => crossing,
+                None => continue,
+            };
+            crossing.signal.blink()?;
+            let elapsed = time.saturating_sub(*start);
+            let next = match aspect {
+                Walk if elapsed >= timing.walk as u64 => Clearance,
+                Clearance if elapsed >= timing.clearance as u64 => DontWalk,
+                _ => continue,
+            };
+            crossing.signal.set_aspect(next)?;
+            *aspect = next;
+            *start = time;
+        }
+        Ok(())
+    }
+
+    /// Sets the signal head of every crossing
+    fn set_walks(&mut self, aspect: PedestrianAspect, time: u64) -> Result<()> {
+        for (crossing, walk) in self.crossings.iter_mut().zip(&mut self.walks) {
+            if let Some(crossing) = crossing {
+                crossing.signal.set_aspect(aspect)?;
+                *walk = (aspect, time);
+            }
+        }
+        Ok(())
+    }
+
+    /// Gives walk to the crossing with the index after the safety monitor checked the lights
+    fn start_walk(&mut self, index: usize, time: u64) -> Result<()> {
+        if self.crossings[index].is_none() {
+            return Ok(());
+        }
+        self.walks[index] = (Walk, time);
+        self.check_crossings(self.applied)?;
+        if let Some(crossing) = &mut self.crossings[index] {
+            crossing.demand.serve();
+            crossing.signal.set_aspect(Walk)?;
+        }
+        Ok(())
+    }
+
+    /// Returns if every arm passing the crossing is red, so it may walk concurrently
+    fn may_walk(&self, crossing: &Crossing<'l, W>) -> bool {
+        let mut arms = self.applied[..self.arm_count].iter().zip(crossing.arms);
+        arms.all(|(light, crossed)| !crossed || *light == Red)
+    }
+
+    /// Gives walk to the crossings with demand whose arms are red
+    fn start_concurrent_walks(&mut self, time: u64, log: &Logger) -> Result<()> {
+        for index in 0..MAX_CROSSINGS {
+            let ready = match &self.crossings[index] {
+                Some(crossing) => {
+                    crossing.demand.requested()
+                        && self.walks[index].0 == DontWalk
+                        && self.may_walk(crossing)
+                }
+                None => false,
+            };
+            if ready {
+                let id = self.id;
+                crate::debug!(log, Module::Pedestrian, "intersection {} walk {}", id, index);
+                self.start_walk(index, time)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the lights turning the arms which have green or yellow yellow and the other arms red
+    fn yellow_lights(&self) -> [IntersectionActionLight; MAX_ARMS] {
+        let mut lights = [Red; MAX_ARMS];
+        for (light, applied) in lights.iter_mut().zip(&self.applied) {
+            if matches!(applied, Green(_) | Yellow) {
+                *light = Yellow;
+            }
+        }
+        lights
+    }
+
+    /// Turns the arms which had green yellow for an exclusive pedestrian phase
+    fn start_exclusive_phase(&mut self, time: u64) -> Result<()> {
+        let lights = self.yellow_lights();
+        self.exclusive = Some(ExclusivePhase::new(lights.contains(&Yellow), time));
+        self.execute_state(lights)
+    }
+
+    /// Runs the exclusive pedestrian phase and executes the next state after it
+    fn exclusive_phase(&mut self, time: u64, log: &Logger) -> Result<()> {
+        let occupied = matches!(self.occupancy, Some(occupancy) if occupancy.occupied());
+        let (clearance, walking) = (self.clearance, self.walking());
+        let step = match &mut self.exclusive {
+            Some(exclusive) => exclusive.step(time, clearance, occupied, walking),
+            None => return Ok(()),
         };
-        intersection.execute_next_state();
-        intersection
+        match step {
+            Some(ExclusiveStep::AllRed) => self.execute_state([Red; MAX_ARMS]),
+            Some(ExclusiveStep::Walk) => {
+                crate::debug!(log, Module::Pedestrian, "intersection {} exclusive walk", self.id);
+                for index in 0..MAX_CROSSINGS {
+                    self.start_walk(index, time)?;
+                }
+                Ok(())
+            }
+            Some(ExclusiveStep::Continue) => {
+                self.exclusive = None;
+                let next = self.next_steady_state(time);
+                self.state_start = time;
+                self.start_positioning(self.applied, next, time)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Runs the preemption and continues the plan after it
+    fn preemption_phase(&mut self, time: u64, log: &Logger) -> Result<()> {
+        let yellow = self.applied.contains(&Yellow);
+        let step = match &mut self.preempting {
+            Some(preempting) => preempting.step(time, yellow),
+            None => return Ok(()),
+        };
+        match step {
+            Some(PreemptionStep::Yellow) => {
+                self.exclusive = None;
+                for (crossing, walk) in self.crossings.iter_mut().zip(&mut self.walks) {
+                    if let (Some(crossing), (Walk, _)) = (crossing, *walk) {
+                        crossing.signal.set_aspect(Clearance)?;
+                        *walk = (Clearance, time);
+                    }
+                }
+                self.execute_state(self.yellow_lights())
+            }
+            Some(PreemptionStep::Green(movement)) => {
+                let (id, arm) = (self.id, movement.arm);
+                crate::debug!(log, Module::Preemption, "intersection {} green to arm {}", id, arm);
+                // the movement keeps its green until the vehicle cleared the intersection, it gets
+                // it after the all-red clearance and the clearance of the crossings
+                let mut lights = [Red; MAX_ARMS];
+                lights[movement.arm] = Green(movement.direction);
+                let state = IntersectionState::new(&lights[..self.arm_count], u32::MAX);
+                self.states.preempt(state);
+                self.next_state(time);
+                self.execute_after_all_red(time)
+            }
+            Some(PreemptionStep::Leave) => self.execute_state(self.yellow_lights()),
+            Some(PreemptionStep::Continue) => {
+                crate::info!(log, Module::Preemption, "intersection {} preemption over", self.id);
+                self.preempting = None;
+                self.next_steady_state(time);
+                self.execute_after_all_red(time)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Checks with the safety monitor that every arm passing a crossing with walk is red
+    fn check_crossings(&mut self, lights: [IntersectionActionLight; MAX_ARMS]) -> Result<()> {
+        let mut violation = false;
+        for (crossing, (aspect, _)) in self.crossings.iter().zip(&self.walks) {
+            if let (Some(crossing), Walk | Clearance) = (crossing, aspect) {
+                let lights = &lights[..self.arm_count];
+                violation |= self.monitor.check_crossing(lights, &crossing.arms).is_err();
+            }
+        }
+        if violation {
+            self.force_red()?;
+            return Err(Error::SignalConflict(self.id));
+        }
+        Ok(())
+    }
+
+    /// Moves on to the next state which isn't a transition of the lights and returns its lights,
+    /// to continue with it after every arm was red
+    fn next_steady_state(&mut self, time: u64) -> [IntersectionActionLight; MAX_ARMS] {
+        for _ in 0..MAX_PHASES {
+            let lights = &self.next_state(time).actions[..self.arm_count];
+            if !lights.iter().any(|light| matches!(light, Yellow | RedYellow)) {
+                break;
+            }
+        }
+        self.states.current().actions
+    }
+
+    /// Moves on to the next state and plans when it should end by the coordination
+    ///
+    /// # Arguments
+    ///
+    /// * `time` - the current time in milliseconds
+    fn next_state(&mut self, time: u64) -> IntersectionState {
+        let state = *self.states.next();
+        // an inserted state takes the time of the one it interrupted
+        if let (Some(coordination), false) = (self.coordination, self.states.preempted()) {
+            let start = match self.states.index() {
+                0 => coordination.cycle_start(time),
+                _ => self.planned_end,
+            };
+            self.planned_end = start + state.duration as u64;
+        }
+        state
+    }
+
+    /// Turns every arm red and executes the current state of the schedule after the all-red
+    /// clearance
+    ///
+    /// # Arguments
+    ///
+    /// * `time` - the current time in milliseconds
+    fn execute_after_all_red(&mut self, time: u64) -> Result<()> {
+        self.state_start = time;
+        self.stage = Stage::Clearing;
+        self.stage_start = time;
+        self.pending = [false; MAX_ARMS];
+        self.execute_state([Red; MAX_ARMS])
+    }
+
+    fn execute_next_state(&mut self) -> Result<()> {
+        let time = millis();
+        self.next_state(time);
+        self.execute_current_state(time)
+    }
+
+    /// Executes the current state of the schedule, after the all-red clearance if needed
+    ///
+    /// # Arguments
+    ///
+    /// * `time` - the current time in milliseconds
+    fn execute_current_state(&mut self, time: u64) -> Result<()> {
+        let previous = self.applied;
+        let next = self.states.current().actions;
+        self.state_start = time;
+        match self.clearance_lights(&previous, &next) {
+            Some(lights) => {
+                self.stage = Stage::Clearing;
+                self.stage_start = time;
+                self.pending = [false; MAX_ARMS];
+                self.execute_state(lights)
+            }
+            None => self.start_positioning(previous, next, time),
+        }
+    }
+
+    /// Holds the new greens back with their entry stoppers locked and commands their servos
+    ///
+    /// # Arguments
+    ///
+    /// * `previous` - the lights applied before
+    /// * `next` - the lights of the current state
+    /// * `time` - the current time in milliseconds
+    fn start_positioning(
+        &mut self,
+        previous: [IntersectionActionLight; MAX_ARMS],
+        next: [IntersectionActionLight; MAX_ARMS],
+        time: u64,
+    ) -> Result<()> {
+        let mut lights = next;
+        for arm in 0..self.arm_count {
+            self.pending[arm] = matches!(next[arm], Green(_)) && previous[arm] != next[arm];
+            if self.pending[arm] {
+                lights[arm] = previous[arm];
+            }
+        }
+        if !self.pending.contains(&true) {
+            self.stage = Stage::Applied;
+            return self.execute_state(next);
+        }
+        self.stage = Stage::Positioning;
+        self.stage_start = time;
+        self.execute_state(lights)?;
+        self.position(time)
+    }
+
+    /// Commands the servos still pending and applies the current state once all of them settled
+    fn position(&mut self, time: u64) -> Result<()> {
+        let next = self.states.current().actions;
+        let mut directions = [None; MAX_ARMS];
+        for (direction, light) in directions.iter_mut().zip(&next) {
+            if let Green(green_direction) = light {
+                *direction = Some(*green_direction);
+            }
+        }
+        if self.command_servos(&directions, self.stage_start, time)? {
+            self.stage = Stage::Applied;
+            self.state_start = time;
+            self.execute_state(next)?;
+        }
+        Ok(())
     }
 
-    pub fn call(&mut self) {
-        self.time_counter += 1;
-        if self.time_counter >= self.states.current().duration {
-            self.time_counter = 0;
-            self.execute_next_state();
+    /// Commands the servos of the pending arms to their direction
+    ///
+    /// Returns if every servo was commanded and had its settle time. A servo which can't be
+    /// commanded is tried again with the next call until the timeout, counted from the start of
+    /// the stage.
+    ///
+    /// # Arguments
+    ///
+    /// * `directions` - the direction of every arm by its index
+    /// * `stage_start` - the time in milliseconds the stage started
+    /// * `time` - the current time in milliseconds
+    fn command_servos(
+        &mut self,
+        directions: &[Option<IntersectionActionDirection>; MAX_ARMS],
+        stage_start: u64,
+        time: u64,
+    ) -> Result<bool> {
+        let arms = self.arms.iter().flatten().zip(directions).zip(&mut self.pending);
+        for ((arm, direction), pending) in arms {
+            if let (true, Some(direction)) = (*pending, direction) {
+                if arm.servo.borrow_mut().set_direction(direction).is_ok() {
+                    *pending = false;
+                    self.last_command = time;
+                }
+            }
+        }
+
+        let failed = self.arms.iter().flatten().zip(&self.pending).find(|(_, pending)| **pending);
+        if let Some((arm, _)) = failed {
+            if time.saturating_sub(stage_start) >= self.servo_timeout as u64 {
+                let id = arm.servo.borrow().get_id();
+                self.force_red()?;
+                return Err(Error::ServoTimeout(id));
+            }
+            return Ok(false);
         }
+        Ok(time.saturating_sub(self.last_command) >= self.settle_time as u64)
     }
 
-    fn execute_next_state(&mut self) {
-        let state = self.states.next();
+    /// Blinks the lights and lets the cars in by the priority rule of the night mode
+    fn call_night(&mut self, time: u64, log: &Logger) -> Result<()> {
+        for arm in self.arms.iter_mut().flatten() {
+            arm.light.blink()?;
+        }
+        let (night_mode, mut night) = match (self.night_mode, self.night) {
+            (Some(night_mode), Some(night)) => (night_mode, night),
+            _ => return Ok(()),
+        };
+        if !self.command_servos(&night_mode.directions, night.start(), time)? {
+            return Ok(());
+        }
+        let occupied = matches!(self.occupancy, Some(occupancy) if occupancy.occupied());
+        let next_yielding = || self.next_yielding_arm(&night_mode, time);
+        match night.step(&night_mode, time, self.clearance, occupied, next_yielding) {
+            Some(NightStep::Yield(arm)) => {
+                crate::debug!(log, Module::Night, "intersection {} lets arm {} in", self.id, arm);
+                self.pending[arm] = true;
+            }
+            Some(NightStep::Crossed(arm)) => self.last_green[arm] = time,
+            Some(NightStep::MainRoad | NightStep::Release) => (),
+            None => return Ok(()),
+        }
+        self.night = Some(night);
+        self.execute_night()
+    }
 
-        // take action for right intersection arm stoppers
-        match state.right_action {
-            Green(_) => {
-                self.right_arm
-                    .entry_stopper
-                    .borrow_mut()
-                    .intersection_release();
+    /// Returns the yielding arm whose car waits the longest if it may be let in
+    ///
+    /// # Arguments
+    ///
+    /// * `night_mode` - the settings of the night mode
+    /// * `time` - the current time in milliseconds
+    fn next_yielding_arm(&self, night_mode: &NightMode, time: u64) -> Option<usize> {
+        let mut next: Option<(usize, u64)> = None;
+        for (index, arm) in self.arms.iter().flatten().enumerate() {
+            if night_mode.main_road[index] || night_mode.directions[index].is_none() {
+                continue;
             }
-            Yellow => {
-                self.right_arm
-                    .entry_stopper
-                    .borrow_mut()
-                    .intersection_lock();
+            // the last green of a yielding arm is the time its last car was let in
+            let last_green = self.last_green[index];
+            let waiting_since = match arm.approach.map(Approach::last_arrival) {
+                None => last_green,
+                Some(Some(arrival)) if arrival > last_green => arrival,
+                Some(_) => continue,
+            };
+            match next {
+                Some((_, since)) if since <= waiting_since => (),
+                _ => next = Some((index, waiting_since)),
+            }
+        }
+
+        // a car which arrived on a conflicting arm of the main road may still be crossing
+        let (yielding, _) = next?;
+        let mut arms = self.arms.iter().flatten().enumerate();
+        let crossing = arms.any(|(index, arm)| {
+            let last_arrival = arm.approach.and_then(Approach::last_arrival);
+            self.night_conflict(night_mode, yielding, index)
+                && matches!(last_arrival, Some(arrival)
+                    if time.saturating_sub(arrival) < night_mode.crossing_time as u64)
+        });
+        match crossing {
+            true => None,
+            false => Some(yielding),
+        }
+    }
+
+    /// Returns if the movement of the yielding arm conflicts with the movement of the arm of the
+    /// main road in the night mode
+    fn night_conflict(&self, night_mode: &NightMode, yielding: usize, arm: usize) -> bool {
+        match (night_mode.directions[yielding], night_mode.directions[arm]) {
+            (Some(yielding_direction), Some(direction)) if night_mode.main_road[arm] => {
+                let yielding_movement = Movement::new(yielding, yielding_direction);
+                let movement = Movement::new(arm, direction);
+                self.monitor.matrix().conflicts(yielding_movement, movement)
             }
-            _ => (),
+            _ => false,
         }
+    }
 
-        // take action for left intersection arm stoppers
-        match state.left_action {
-            Green(_) => {
-                self.left_arm
-                    .entry_stopper
-                    .borrow_mut()
-                    .intersection_release();
+    /// Applies the flashing lights and the entry stoppers of the night stage and checks them with
+    /// the safety monitor
+    ///
+    /// The monitor sees every released arm as green for the movement it lets in.
+    fn execute_night(&mut self) -> Result<()> {
+        let night_mode = match self.night_mode {
+            Some(night_mode) => night_mode,
+            None => return Ok(()),
+        };
+        let mut movements = [Red; MAX_ARMS];
+        for (arm, movement) in movements[..self.arm_count].iter_mut().enumerate() {
+            let conflicts = |yielding, arm| self.night_conflict(&night_mode, yielding, arm);
+            let released = match self.night {
+                Some(night) => night.released(&night_mode, arm, conflicts),
+                None => false,
+            };
+            if let (true, false, Some(direction)) =
+                (released, self.pending[arm], night_mode.directions[arm])
+            {
+                *movement = Green(direction);
             }
-            Yellow => {
-                self.left_arm.entry_stopper.borrow_mut().intersection_lock();
+        }
+        if self.monitor.check_lights(&movements[..self.arm_count]).is_err() {
+            self.force_red()?;
+            return Err(Error::SignalConflict(self.id));
+        }
+
+        self.applied = [FlashingYellow; MAX_ARMS];
+        for (arm, movement) in self.arms.iter().flatten().zip(&movements) {
+            match movement {
+                Green(_) => arm.entry_stopper.borrow_mut().intersection_release()?,
+                _ => arm.entry_stopper.borrow_mut().intersection_lock()?,
             }
-            _ => (),
         }
+        for arm in self.arms.iter_mut().flatten() {
+            arm.light.set_state(&FlashingYellow)?;
+        }
+        self.set_walks(Dark, millis())?;
+        self.check_stoppers(movements)
+    }
 
-        // take action for upper intersection arm stoppers
-        match state.upper_action {
-            Green(_) => {
-                self.upper_arm
-                    .entry_stopper
-                    .borrow_mut()
-                    .intersection_release();
+    /// Returns the lights to apply during the clearance if the next lights need one
+    ///
+    /// A clearance is needed when a new green conflicts with a movement which loses its green or
+    /// yellow. The arms getting green keep their previous light, the others show the next one.
+    fn clearance_lights(
+        &self,
+        previous: &[IntersectionActionLight; MAX_ARMS],
+        next: &[IntersectionActionLight; MAX_ARMS],
+    ) -> Option<[IntersectionActionLight; MAX_ARMS]> {
+        if self.clearance == 0 && self.occupancy.is_none() {
+            return None;
+        }
+        let mut lights = *next;
+        let mut conflict = false;
+        for arm in 0..self.arm_count {
+            let direction = match next[arm] {
+                Green(direction) if previous[arm] != next[arm] => direction,
+                _ => continue,
+            };
+            lights[arm] = previous[arm];
+            let movement = Movement::new(arm, direction);
+            for other in (0..self.arm_count).filter(|other| *other != arm) {
+                let leaving = match previous[other] {
+                    Green(direction) if next[other] != Green(direction) => Some(direction),
+                    Yellow => self.directions[other],
+                    _ => None,
+                };
+                if let Some(direction) = leaving {
+                    let other_movement = Movement::new(other, direction);
+                    conflict |= self.monitor.matrix().conflicts(movement, other_movement);
+                }
             }
-            Yellow => {
-                self.upper_arm
-                    .entry_stopper
-                    .borrow_mut()
-                    .intersection_lock();
+        }
+        match conflict {
+            true => Some(lights),
+            false => None,
+        }
+    }
+
+    /// Applies the lights and entry stoppers to the arms and checks them with the safety monitor
+    ///
+    /// The servos are commanded before, see [`Intersection::position`]
+    fn execute_state(&mut self, actions: [IntersectionActionLight; MAX_ARMS]) -> Result<()> {
+        self.applied = actions;
+        let lights = &actions[..self.arm_count];
+        if self.monitor.check_lights(lights).is_err() {
+            self.force_red()?;
+            return Err(Error::SignalConflict(self.id));
+        }
+        self.check_crossings(actions)?;
+
+        // take action for intersection arm stoppers, they are only released on green and when
+        // the servo isn't moving
+        for ((arm, light), pending) in self.arms.iter().flatten().zip(lights).zip(self.pending) {
+            match (light, pending) {
+                (Green(_), false) => arm.entry_stopper.borrow_mut().intersection_release()?,
+                _ => arm.entry_stopper.borrow_mut().intersection_lock()?,
             }
-            _ => (),
         }
 
         // take action for intersection lights
-        self.right_arm.light.set_state(&state.right_action);
-        self.left_arm.light.set_state(&state.left_action);
-        self.upper_arm.light.set_state(&state.upper_action);
+        for (arm, light) in self.arms.iter_mut().flatten().zip(lights) {
+            arm.light.set_state(light)?;
+        }
+
+        for (arm, light) in lights.iter().enumerate() {
+            if let Green(direction) = light {
+                self.directions[arm] = Some(*direction);
+            }
+        }
+        self.check_stoppers(actions)
+    }
+
+    /// Checks the entry stoppers against the lights with the safety monitor
+    fn check_stoppers(&mut self, lights: [IntersectionActionLight; MAX_ARMS]) -> Result<()> {
+        let arms = &self.arms;
+        let locked = |arm: usize| match &arms[arm] {
+            Some(arm) => arm.entry_stopper.borrow().get_state(),
+            None => true,
+        };
+        if self.monitor.check_stoppers(&lights[..self.arm_count], locked).is_err() {
+            self.force_red()?;
+            return Err(Error::OpenStopper(self.id));
+        }
+        Ok(())
+    }
+
+    /// Locks the entry stoppers and turns every light and pedestrian signal red
+    fn force_red(&mut self) -> Result<()> {
+        for arm in self.arms.iter_mut().flatten() {
+            arm.entry_stopper.borrow_mut().intersection_lock()?;
+            arm.light.set_state(&Red)?;
+        }
+        self.set_walks(DontWalk, millis())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IntersectionActionDirection::*;
+    use super::*;
+    use crate::pedestrian::{Demand, PedestrianSignal, YELLOW_TIME};
+    use crate::pin_mockup::Pin;
+    use crate::plan::PhasePlan;
+    use crate::time::{set_millis, ClockGuard};
 
-        // take action for intersection servos
-        if let IntersectionActionLight::Green(direction) = &state.right_action {
-            self.right_arm.servo.borrow_mut().set_direction(direction);
+    /// Milliseconds of the all-red clearance of the test intersection
+    const CLEARANCE: u32 = 1_000;
+    /// Milliseconds the servos of the test intersection settle
+    const SETTLE_TIME: u32 = 500;
+    /// Milliseconds of the walk signal of the test crossing
+    const WALK: u32 = 2_000;
+    /// Milliseconds of the flashing green of the test crossing
+    const WALK_CLEARANCE: u32 = 5_000;
+
+    /// The mock clock for one test, released when dropped
+    struct Clock(ClockGuard);
+
+    impl Clock {
+        fn take() -> Clock {
+            Clock(ClockGuard::take())
         }
-        if let IntersectionActionLight::Green(direction) = &state.left_action {
-            self.left_arm.servo.borrow_mut().set_direction(direction);
+
+        /// Sets the time and lets the intersection run
+        fn call(&self, intersection: &mut TestIntersection, time: u64) -> Result<()> {
+            set_millis(time);
+            intersection.call(&Logger::new())
         }
-        if let IntersectionActionLight::Green(direction) = &state.upper_action {
-            self.upper_arm.servo.borrow_mut().set_direction(direction);
+    }
+
+    /// Servo controller whose writes fail if told so
+    #[derive(Default)]
+    struct TestI2c {
+        failing: bool,
+    }
+
+    impl i2c::Write for TestI2c {
+        type Error = ();
+
+        fn write(&mut self, _address: u8, _bytes: &[u8]) -> core::result::Result<(), ()> {
+            match self.failing {
+                true => Err(()),
+                false => Ok(()),
+            }
         }
     }
+
+    type TestIntersection<'l> = Intersection<'l, TestI2c, PhasePlan, Pin<'l>, Pin<'l>>;
+
+    /// Builds the T-junction preset with a clearance and settle time on mock pins and passes it
+    /// with its entry stoppers, servo controller and the demand of its crossing to `f`
+    ///
+    /// The crossing passes the right arm.
+    fn t_junction(
+        f: impl FnOnce(&mut TestIntersection, &[RefCell<Stopper<Pin>>], &RefCell<TestI2c>, &Demand),
+    ) {
+        let stopper_levels: [RefCell<bool>; 3] = Default::default();
+        let light_levels: [RefCell<bool>; 9] = Default::default();
+        let servo_levels: [RefCell<bool>; 3] = Default::default();
+        let signal_levels: [RefCell<bool>; 2] = Default::default();
+        let demand = Demand::new();
+        let i2c = RefCell::new(TestI2c::default());
+        let stoppers: Vec<_> = (0..3)
+            .map(|arm| Stopper::new(Pin::new(&stopper_levels[arm]), arm as u8).unwrap())
+            .map(RefCell::new)
+            .collect();
+        let servos: Vec<_> = (0..3)
+            .map(|arm| {
+                let servo = Servo::new(Pin::new(&servo_levels[arm]), 60, 120, &i2c, arm as u8, 4);
+                RefCell::new(servo.unwrap())
+            })
+            .collect();
+        let arms = (0..3).map(|arm| IntersectionArm {
+            entry_stopper: &stoppers[arm],
+            light: Light::new(
+                Pin::new(&light_levels[3 * arm]),
+                Pin::new(&light_levels[3 * arm + 1]),
+                Pin::new(&light_levels[3 * arm + 2]),
+            )
+            .unwrap(),
+            servo: &servos[arm],
+            approach: None,
+        });
+        let mut intersection =
+            Intersection::new(1, arms, PhasePlan::T_JUNCTION, ConflictMatrix::T_JUNCTION).unwrap();
+        intersection.set_clearance(CLEARANCE, None);
+        intersection.set_servo_timing(SETTLE_TIME, SERVO_TIMEOUT);
+        let signal =
+            PedestrianSignal::new(Pin::new(&signal_levels[0]), Pin::new(&signal_levels[1]));
+        let mut arms = [false; MAX_ARMS];
+        arms[RIGHT_ARM] = true;
+        let crossing = Crossing {
+            signal: signal.unwrap(),
+            demand: &demand,
+            arms,
+        };
+        intersection.add_crossing(crossing).unwrap();
+        intersection.set_pedestrian_timing(PedestrianTiming {
+            walk: WALK,
+            clearance: WALK_CLEARANCE,
+            exclusive: false,
+        });
+        f(&mut intersection, &stoppers, &i2c, &demand);
+    }
+
+    /// Runs the plan of the T-junction into its second phase, in which the left arm turns left
+    /// and the right arm is red
+    fn second_phase(clock: &Clock, intersection: &mut TestIntersection) {
+        intersection.next_phase().unwrap();
+        set_millis(1_000);
+        intersection.next_phase().unwrap();
+        clock.call(intersection, 2_000).unwrap();
+        clock.call(intersection, 2_000 + u64::from(SETTLE_TIME)).unwrap();
+        assert!(intersection.current_lights() == [Green(Left), Red, Green(Right)]);
+    }
+
+    /// Returns which entry stoppers are locked
+    fn locked(stoppers: &[RefCell<Stopper<Pin>>]) -> Vec<bool> {
+        stoppers.iter().map(|stopper| stopper.borrow().get_state()).collect()
+    }
+
+    #[test]
+    fn plan_without_phases_is_a_config_error() {
+        let intersection: Result<TestIntersection> =
+            Intersection::new(1, [], PhasePlan::new(), ConflictMatrix::T_JUNCTION);
+        assert_eq!(intersection.err(), Some(Error::Config(1)));
+    }
+
+    #[test]
+    fn crossing_past_the_maximum_is_a_config_error() {
+        let _clock = Clock::take();
+        t_junction(|intersection, _, _, _| {
+            // the crossings have to outlive the intersection, which the helper owns
+            let levels: Vec<RefCell<bool>> = (0..2 * MAX_CROSSINGS).map(|_| false.into()).collect();
+            let levels = Vec::leak(levels);
+            let demand = Box::leak(Box::new(Demand::new()));
+            let crossing = |index: usize| {
+                let signal = PedestrianSignal::new(
+                    Pin::new(&levels[2 * index]),
+                    Pin::new(&levels[2 * index + 1]),
+                );
+                Crossing {
+                    signal: signal.unwrap(),
+                    demand: &*demand,
+                    arms: [false; MAX_ARMS],
+                }
+            };
+            for index in 1..MAX_CROSSINGS {
+                assert!(intersection.add_crossing(crossing(index)).is_ok());
+            }
+            assert_eq!(intersection.add_crossing(crossing(0)).err(), Some(Error::Config(1)));
+        });
+    }
+
+    #[test]
+    fn conflicting_green_waits_for_the_clearance_and_the_servo() {
+        let clock = Clock::take();
+        t_junction(|intersection, stoppers, _, _| {
+            // the first state was applied before the intersection got its timing
+            assert!(intersection.stage == Stage::Applied);
+            assert_eq!(locked(stoppers), [false, false, false]);
+            // the right arm turns yellow without a new green
+            intersection.next_phase().unwrap();
+            assert!(intersection.stage == Stage::Applied);
+            assert!(intersection.current_lights() == [Green(Right), Yellow, Green(Right)]);
+
+            // turning left the left arm conflicts with the right arm leaving
+            let start = 1_000;
+            set_millis(start);
+            intersection.next_phase().unwrap();
+            assert!(intersection.stage == Stage::Clearing);
+            assert!(intersection.current_lights() == [Green(Right), Red, Green(Right)]);
+
+            clock.call(intersection, start + u64::from(CLEARANCE) - 1).unwrap();
+            assert!(intersection.stage == Stage::Clearing);
+
+            let positioning = start + u64::from(CLEARANCE);
+            clock.call(intersection, positioning).unwrap();
+            assert!(intersection.stage == Stage::Positioning);
+            assert_eq!(locked(stoppers), [true, true, false]);
+
+            clock.call(intersection, positioning + u64::from(SETTLE_TIME)).unwrap();
+            assert!(intersection.stage == Stage::Applied);
+            assert!(intersection.current_lights() == [Green(Left), Red, Green(Right)]);
+            assert_eq!(locked(stoppers), [false, true, false]);
+        });
+    }
+
+    #[test]
+    fn failing_servo_turns_every_arm_red_after_the_timeout() {
+        let clock = Clock::take();
+        t_junction(|intersection, stoppers, i2c, _| {
+            intersection.next_phase().unwrap();
+            i2c.borrow_mut().failing = true;
+            intersection.next_phase().unwrap();
+
+            let positioning = u64::from(CLEARANCE);
+            clock.call(intersection, positioning).unwrap();
+            clock.call(intersection, positioning + u64::from(SERVO_TIMEOUT) - 1).unwrap();
+            assert!(intersection.stage == Stage::Positioning);
+
+            let result = clock.call(intersection, positioning + u64::from(SERVO_TIMEOUT));
+            assert!(result == Err(Error::ServoTimeout(LEFT_ARM as u8)));
+            assert_eq!(locked(stoppers), [true, true, true]);
+        });
+    }
+
+    #[test]
+    fn preemption_gives_green_after_the_clearance_of_walking_pedestrians() {
+        let clock = Clock::take();
+        t_junction(|intersection, _, _, demand| {
+            second_phase(&clock, intersection);
+            demand.request();
+            clock.call(intersection, 2_600).unwrap();
+            assert!(intersection.walks[0].0 == Walk);
+
+            // the right arm passes the crossing
+            let start = 3_000;
+            set_millis(start);
+            intersection.preempt(Movement::new(RIGHT_ARM, Left), &Logger::new()).unwrap();
+            assert!(intersection.walks[0].0 == Clearance);
+            assert!(intersection.current_lights() == [Yellow, Red, Yellow]);
+
+            let all_red = start + u64::from(YELLOW_TIME);
+            clock.call(intersection, all_red).unwrap();
+            assert!(intersection.current_lights() == [Red, Red, Red]);
+
+            // the all-red clearance is over before the pedestrians cleared the crossing
+            let walked = start + u64::from(WALK_CLEARANCE);
+            clock.call(intersection, all_red + u64::from(CLEARANCE)).unwrap();
+            clock.call(intersection, walked - 1).unwrap();
+            assert!(intersection.stage == Stage::Clearing);
+            assert!(intersection.walks[0].0 == Clearance);
+
+            clock.call(intersection, walked).unwrap();
+            assert!(intersection.walks[0].0 == DontWalk);
+            assert!(intersection.stage == Stage::Positioning);
+            clock.call(intersection, walked + u64::from(SETTLE_TIME)).unwrap();
+            assert!(intersection.current_lights() == [Red, Green(Left), Red]);
+        });
+    }
+
+    #[test]
+    fn preemption_during_a_clearance_starts_after_the_new_state() {
+        let clock = Clock::take();
+        t_junction(|intersection, _, _, _| {
+            intersection.next_phase().unwrap();
+            set_millis(1_000);
+            intersection.next_phase().unwrap();
+
+            set_millis(1_200);
+            intersection.preempt(Movement::new(LEFT_ARM, Right), &Logger::new()).unwrap();
+            assert!(intersection.preempting());
+            assert!(intersection.stage == Stage::Clearing);
+            assert!(intersection.current_lights() == [Green(Right), Red, Green(Right)]);
+
+            // the left arm is positioned and gets its green of the plan first
+            clock.call(intersection, 2_000).unwrap();
+            assert!(intersection.stage == Stage::Positioning);
+            let applied = 2_000 + u64::from(SETTLE_TIME);
+            clock.call(intersection, applied).unwrap();
+            assert!(intersection.current_lights() == [Green(Left), Red, Green(Right)]);
+
+            let start = applied + 10;
+            clock.call(intersection, start).unwrap();
+            assert!(intersection.current_lights() == [Yellow, Red, Yellow]);
+            let all_red = start + u64::from(YELLOW_TIME);
+            clock.call(intersection, all_red).unwrap();
+            clock.call(intersection, all_red + u64::from(CLEARANCE)).unwrap();
+            let positioned = all_red + u64::from(CLEARANCE + SETTLE_TIME);
+            clock.call(intersection, positioned).unwrap();
+            assert!(intersection.current_lights() == [Green(Right), Red, Red]);
+
+            // the plan continues once the vehicle cleared the intersection
+            intersection.clear_preemption();
+            clock.call(intersection, positioned + 10).unwrap();
+            assert!(intersection.current_lights() == [Yellow, Red, Red]);
+            clock.call(intersection, positioned + 10 + u64::from(YELLOW_TIME)).unwrap();
+            assert!(!intersection.preempting());
+        });
+    }
 }
@@ -0,0 +1,443 @@
+//! Level crossings of the car road with a train line
+//!
+//! Sensors on the train line report to the [`Track`] of a [`LevelCrossing`] when a train
+//! approaches and when it left the crossing. The crossing closes for the train in stages:
+//!
+//! * on the approach the two red lights start flashing alternately and the road stoppers lock
+//! * after the `warning` of the [`LevelCrossingTiming`] the barriers lower
+//! * once the exit sensor fired and the `open_time` passed since it last did, the barriers rise
+//!   while the lights keep flashing, and after the `rise` time the lights turn dark and the
+//!   stoppers release
+//!
+//! A train approaching while the barriers rise lowers them again. The track is occupied from the
+//! approach until the exit, so only one train may be between the sensors at a time and it has to
+//! pass them in their direction. The barriers are up in the right direction of their servos, which
+//! is the one a new servo is set to, and down in the left direction.
+
+use core::cell::{Cell, RefCell};
+use embedded_hal::blocking::i2c;
+use embedded_hal::blocking::i2c::SevenBitAddress;
+use embedded_hal::digital::v2::OutputPin;
+
+use crate::error::Result;
+use crate::intersection::IntersectionActionDirection;
+use crate::intersection::IntersectionActionLight::{self, *};
+use crate::lights::{blink_level, Light, LIGHT_ACTIVE};
+use crate::log::{Logger, Module};
+use crate::servo::Servo;
+use crate::slots::Slots;
+use crate::stopper::Stopper;
+use crate::time::millis;
+
+/// The trains on the line at a level crossing, shared by its sensors and the crossing
+pub struct Track {
+    /// The time in milliseconds an approach sensor last detected a train
+    approached: Cell<Option<u64>>,
+    /// The time in milliseconds an exit sensor last detected a train
+    exited: Cell<Option<u64>>,
+}
+
+impl Track {
+    /// Returns a track without trains
+    pub const fn new() -> Track {
+        Track {
+            approached: Cell::new(None),
+            exited: Cell::new(None),
+        }
+    }
+
+    /// Records a train approaching the crossing
+    ///
+    /// # Arguments
+    ///
+    /// * `time` - the time in milliseconds of the detection
+    pub fn approach(&self, time: u64) {
+        self.approached.set(Some(time));
+    }
+
+    /// Records a train leaving the crossing
+    ///
+    /// # Arguments
+    ///
+    /// * `time` - the time in milliseconds of the detection
+    pub fn exit(&self, time: u64) {
+        self.exited.set(Some(time));
+    }
+
+    /// Returns if a train is between the sensors or left less than `open_time` ago
+    ///
+    /// # Arguments
+    ///
+    /// * `time` - the current time in milliseconds
+    /// * `open_time` - the milliseconds after the last exit the track still counts as occupied
+    pub fn occupied(&self, time: u64, open_time: u32) -> bool {
+        match (self.approached.get(), self.exited.get()) {
+            (None, _) => false,
+            (Some(_), None) => true,
+            (Some(approached), Some(exited)) => {
+                approached > exited || time < exited + open_time as u64
+            }
+        }
+    }
+}
+
+impl Default for Track {
+    fn default() -> Self {
+        Track::new()
+    }
+}
+
+/// Settings of a level crossing, see the module documentation
+#[derive(Clone, Copy)]
+pub struct LevelCrossingTiming {
+    /// Milliseconds the lights flash before the barriers lower
+    pub warning: u32,
+    /// Milliseconds the barriers take to rise
+    pub rise: u32,
+    /// Milliseconds after the exit sensor last fired before the barriers rise
+    pub open_time: u32,
+}
+
+impl Default for LevelCrossingTiming {
+    fn default() -> Self {
+        LevelCrossingTiming {
+            warning: 3_000,
+            rise: 2_000,
+            open_time: 2_000,
+        }
+    }
+}
+
+/// The stages of a level crossing
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    /// No train, the lights are dark and the road is free
+    Open,
+    /// The lights flash and the stoppers are locked, the barriers are still up
+    Warning,
+    /// The barriers are down
+    Closed,
+    /// The barriers rise, the lights still flash
+    Opening,
+}
+
+/// Structure to represent a level crossing, see the module documentation
+pub struct LevelCrossing<'l, I2C, W, S>
+where
+    I2C: i2c::Write<SevenBitAddress>,
+    W: OutputPin,
+    S: OutputPin,
+{
+    /// The id of the level crossing
+    id: u8,
+    /// The red lights flashing alternately
+    lights: [Light<W>; 2],
+    /// The stoppers of the road in front of the crossing
+    stoppers: Slots<&'l RefCell<Stopper<W>>, 2>,
+    /// The servos of the barriers
+    barriers: Slots<&'l RefCell<Servo<'l, I2C, S>>, 2>,
+    /// Tells if a train is at the crossing
+    track: &'l Track,
+    timing: LevelCrossingTiming,
+    stage: Stage,
+    /// The time in milliseconds the stage started
+    stage_start: u64,
+    /// What the lights were last set to, None while setting them failed
+    applied: Option<[IntersectionActionLight; 2]>,
+    /// If the stoppers are locked by the crossing
+    locked: bool,
+    /// If the barriers were last commanded down, None while a command failed
+    lowered: Option<bool>,
+}
+
+impl<'l, I2C, W, S> LevelCrossing<'l, I2C, W, S>
+where
+    I2C: i2c::Write<SevenBitAddress>,
+    W: OutputPin,
+    S: OutputPin,
+{
+    /// Returns an open level crossing with dark lights
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - the id of the level crossing
+    /// * `lights` - the two lights, only their red light is used
+    /// * `track` - the track its sensors report the trains to
+    pub fn new(id: u8, mut lights: [Light<W>; 2], track: &'l Track) -> Result<Self> {
+        for light in &mut lights {
+            light.set_state(&Off)?;
+        }
+        Ok(LevelCrossing {
+            id,
+            lights,
+            stoppers: Default::default(),
+            barriers: Default::default(),
+            track,
+            timing: LevelCrossingTiming::default(),
+            stage: Stage::Open,
+            stage_start: 0,
+            applied: Some([Off, Off]),
+            locked: false,
+            lowered: Some(false),
+        })
+    }
+
+    /// Adds a stopper of the road, which is locked while the crossing isn't open
+    ///
+    /// Fails with [`Error::Config`](crate::error::Error::Config) when the crossing already has
+    /// two stoppers
+    pub fn add_stopper(&mut self, stopper: &'l RefCell<Stopper<W>>) -> Result<()> {
+        self.stoppers.push(stopper, self.id)
+    }
+
+    /// Adds the servo of a barrier, which has to be set to the right direction
+    ///
+    /// Fails with [`Error::Config`](crate::error::Error::Config) when the crossing already has
+    /// two barriers
+    pub fn add_barrier(&mut self, servo: &'l RefCell<Servo<'l, I2C, S>>) -> Result<()> {
+        self.barriers.push(servo, self.id)
+    }
+
+    /// Sets how long the stages of the crossing last
+    ///
+    /// # Arguments
+    ///
+    /// * `timing` - the settings, see the module documentation
+    pub fn set_timing(&mut self, timing: LevelCrossingTiming) {
+        self.timing = timing;
+    }
+
+    /// Returns the id of the level crossing
+    pub fn get_id(&self) -> u8 {
+        self.id
+    }
+
+    /// Returns if the crossing is open for the cars
+    pub fn open(&self) -> bool {
+        self.stage == Stage::Open
+    }
+
+    /// Moves on to the next stage when it is due and sets the lights, stoppers and barriers
+    ///
+    /// Has to be called regularily for the lights to flash. Outputs which failed to be set are
+    /// set again by the next call
+    ///
+    /// # Arguments
+    ///
+    /// * `log` - the logger the stages are logged to
+    pub fn call(&mut self, log: &Logger) -> Result<()> {
+        let time = millis();
+        let occupied = self.track.occupied(time, self.timing.open_time);
+        let elapsed = time.saturating_sub(self.stage_start);
+        let next = match self.stage {
+            Stage::Open if occupied => Some(Stage::Warning),
+            Stage::Warning if elapsed >= self.timing.warning as u64 => Some(Stage::Closed),
+            Stage::Closed if !occupied => Some(Stage::Opening),
+            Stage::Opening if occupied => Some(Stage::Closed),
+            Stage::Opening if elapsed >= self.timing.rise as u64 => Some(Stage::Open),
+            _ => None,
+        };
+        if let Some(next) = next {
+            let stage = match next {
+                Stage::Open => "open",
+                Stage::Warning => "warning",
+                Stage::Closed => "closed",
+                Stage::Opening => "opening",
+            };
+            crate::info!(log, Module::LevelCrossing, "level crossing {} {}", self.id, stage);
+            self.stage = next;
+            self.stage_start = time;
+        }
+        self.lock_road(self.stage != Stage::Open)?;
+        self.lower_barriers(self.stage == Stage::Closed)?;
+        let lights = match (self.stage, blink_level() == LIGHT_ACTIVE) {
+            (Stage::Open, _) => [Off, Off],
+            (_, true) => [Red, Off],
+            (_, false) => [Off, Red],
+        };
+        self.set_lights(lights)
+    }
+
+    /// Locks or releases the stoppers unless the crossing already did
+    fn lock_road(&mut self, locked: bool) -> Result<()> {
+        if self.locked == locked {
+            return Ok(());
+        }
+        // the locks are counted by the stoppers, so they are only taken and given back once
+        self.locked = locked;
+        for stopper in self.stoppers.iter() {
+            match locked {
+                true => stopper.borrow_mut().lock()?,
+                false => stopper.borrow_mut().release()?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Commands the barriers down or up unless they already were
+    fn lower_barriers(&mut self, lowered: bool) -> Result<()> {
+        if self.lowered == Some(lowered) {
+            return Ok(());
+        }
+        let direction = match lowered {
+            true => IntersectionActionDirection::Left,
+            false => IntersectionActionDirection::Right,
+        };
+        self.lowered = None;
+        for servo in self.barriers.iter() {
+            servo.borrow_mut().set_direction(&direction)?;
+        }
+        self.lowered = Some(lowered);
+        Ok(())
+    }
+
+    /// Sets the lights unless they already are
+    fn set_lights(&mut self, states: [IntersectionActionLight; 2]) -> Result<()> {
+        if self.applied == Some(states) {
+            return Ok(());
+        }
+        self.applied = None;
+        let first_result = self.lights[0].set_state(&states[0]);
+        let second_result = self.lights[1].set_state(&states[1]);
+        first_result.and(second_result)?;
+        self.applied = Some(states);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use crate::pin_mockup::Pin;
+    use crate::time::{set_millis, ClockGuard};
+
+    const RIGHT_ANGLE: u8 = 60;
+    const LEFT_ANGLE: u8 = 120;
+    const TIMING: LevelCrossingTiming = LevelCrossingTiming {
+        warning: 3_000,
+        rise: 2_000,
+        open_time: 1_000,
+    };
+
+    /// Servo controller remembering the last angle written
+    #[derive(Default)]
+    struct TestI2c {
+        angle: Option<u8>,
+    }
+
+    impl i2c::Write for TestI2c {
+        type Error = ();
+
+        fn write(&mut self, _address: u8, bytes: &[u8]) -> core::result::Result<(), ()> {
+            self.angle = Some(bytes[1]);
+            Ok(())
+        }
+    }
+
+    type TestLevelCrossing<'l> = LevelCrossing<'l, TestI2c, Pin<'l>, Pin<'l>>;
+
+    /// Builds a level crossing with one stopper and one barrier on mock pins and passes it with
+    /// its track, stopper and servo controller to `f`
+    fn level_crossing(
+        f: impl for<'l> FnOnce(
+            &mut TestLevelCrossing<'l>,
+            &'l Track,
+            &'l RefCell<Stopper<Pin<'l>>>,
+            &'l RefCell<TestI2c>,
+        ),
+    ) {
+        let light_levels: [RefCell<bool>; 6] = Default::default();
+        let (stopper_level, servo_level) = (RefCell::new(false), RefCell::new(false));
+        let track = Track::new();
+        let i2c = RefCell::new(TestI2c::default());
+        let stopper = RefCell::new(Stopper::new(Pin::new(&stopper_level), 1).unwrap());
+        let servo = Servo::new(Pin::new(&servo_level), RIGHT_ANGLE, LEFT_ANGLE, &i2c, 1, 4);
+        let servo = RefCell::new(servo.unwrap());
+        let light = |index: usize| {
+            let levels = &light_levels[3 * index..];
+            Light::new(Pin::new(&levels[0]), Pin::new(&levels[1]), Pin::new(&levels[2])).unwrap()
+        };
+        let mut level_crossing = LevelCrossing::new(1, [light(0), light(1)], &track).unwrap();
+        level_crossing.add_stopper(&stopper).unwrap();
+        level_crossing.add_barrier(&servo).unwrap();
+        level_crossing.set_timing(TIMING);
+        f(&mut level_crossing, &track, &stopper, &i2c);
+    }
+
+    fn call(level_crossing: &mut TestLevelCrossing, time: u64) {
+        set_millis(time);
+        level_crossing.call(&Logger::new()).unwrap();
+    }
+
+    #[test]
+    fn track_is_occupied_from_the_approach_until_the_open_time_after_the_exit() {
+        let track = Track::new();
+        assert!(!track.occupied(0, 1_000));
+        track.approach(100);
+        assert!(track.occupied(5_000, 1_000));
+        track.exit(2_000);
+        assert!(track.occupied(2_999, 1_000));
+        assert!(!track.occupied(3_000, 1_000));
+        // the next train approaches after the last one left
+        track.approach(4_000);
+        assert!(track.occupied(4_000, 1_000));
+    }
+
+    #[test]
+    fn barriers_lower_after_the_warning_and_rise_after_the_open_time() {
+        let _clock = ClockGuard::take();
+        level_crossing(|level_crossing, track, stopper, i2c| {
+            let angle = || i2c.borrow().angle;
+            call(level_crossing, 1_000);
+            assert!(level_crossing.open() && !stopper.borrow().get_state());
+
+            // the road locks at once, the barriers wait for the warning
+            track.approach(1_000);
+            call(level_crossing, 1_010);
+            assert!(!level_crossing.open() && stopper.borrow().get_state());
+            assert_eq!(angle(), Some(RIGHT_ANGLE));
+            call(level_crossing, 1_010 + TIMING.warning as u64 - 1);
+            assert_eq!(angle(), Some(RIGHT_ANGLE));
+            call(level_crossing, 1_010 + TIMING.warning as u64);
+            assert_eq!(angle(), Some(LEFT_ANGLE));
+
+            track.exit(6_000);
+            call(level_crossing, 6_000 + TIMING.open_time as u64 - 1);
+            assert_eq!(angle(), Some(LEFT_ANGLE));
+            let opening = 6_000 + TIMING.open_time as u64;
+            call(level_crossing, opening);
+            assert_eq!(angle(), Some(RIGHT_ANGLE));
+            // the road stays locked while the barriers rise
+            call(level_crossing, opening + TIMING.rise as u64 - 1);
+            assert!(!level_crossing.open() && stopper.borrow().get_state());
+            call(level_crossing, opening + TIMING.rise as u64);
+            assert!(level_crossing.open() && !stopper.borrow().get_state());
+        });
+    }
+
+    #[test]
+    fn train_approaching_while_the_barriers_rise_lowers_them_again() {
+        let _clock = ClockGuard::take();
+        level_crossing(|level_crossing, track, _, i2c| {
+            track.approach(0);
+            call(level_crossing, 0);
+            call(level_crossing, TIMING.warning as u64);
+            track.exit(4_000);
+            call(level_crossing, 5_000);
+            assert_eq!(i2c.borrow().angle, Some(RIGHT_ANGLE));
+
+            track.approach(5_500);
+            call(level_crossing, 5_500);
+            assert_eq!(i2c.borrow().angle, Some(LEFT_ANGLE));
+        });
+    }
+
+    #[test]
+    fn third_stopper_is_a_config_error() {
+        level_crossing(|level_crossing, _, stopper, _| {
+            assert!(level_crossing.add_stopper(stopper).is_ok());
+            assert_eq!(level_crossing.add_stopper(stopper), Err(Error::Config(1)));
+        });
+    }
+}
@@ -0,0 +1,41 @@
+//! Control logic of the car system
+//!
+//! Apart from the clock in [`time`] everything in here only depends on `embedded-hal`, so it runs
+//! on the board as well as in the host tools, which use it to stand in for the firmware.
+
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(target_arch = "avr", feature(abi_avr_interrupt))]
+
+pub mod actuation;
+pub mod board;
+pub mod clearance;
+pub mod coordination;
+pub mod crash;
+pub mod error;
+pub mod failsafe;
+pub mod fault;
+pub mod history;
+pub mod intersection;
+pub mod level_crossing;
+pub mod lights;
+pub mod log;
+pub mod monitor;
+pub mod night;
+pub mod pedestrian;
+pub mod pin_mockup;
+pub mod plan;
+pub mod preemption;
+pub mod protocol;
+pub mod remote;
+pub mod roundabout;
+pub mod schedule;
+pub mod section;
+pub mod sensor;
+pub mod sensor_caller;
+pub mod serial;
+pub mod servo;
+pub mod slots;
+pub mod stopper;
+pub mod time;
+pub mod turnout;
+pub mod watchdog;
@@ -1,10 +1,15 @@
 use embedded_hal::digital::v2::{OutputPin, PinState};
 
+use crate::error::{Error, Result};
 use crate::intersection::IntersectionActionLight;
 use crate::intersection::IntersectionActionLight::*;
+use crate::time::millis;
 
 pub const LIGHT_ACTIVE: bool = true;
 
+/// Milliseconds of one cycle of a flashing light, it is lit for the first half
+pub const BLINK_PERIOD: u64 = 1_000;
+
 /// Structure to represent a traffic light
 pub struct Light<W>
 where
@@ -16,6 +21,8 @@ where
     yellow_light: W,
     /// the output pin representing the red light
     red_light: W,
+    /// if the yellow light flashes
+    flashing: bool,
 }
 
 impl<W> Light<W>
@@ -29,38 +36,65 @@ where
     /// * `green_light` - the output pin which represents the green light
     /// * `yellow_light` - the output pin which represents the yellow light
     /// * `red_light` - the output pin which represents the red light
-    pub fn new(green_light: W, yellow_light: W, red_light: W) -> Light<W> {
+    pub fn new(green_light: W, yellow_light: W, red_light: W) -> Result<Light<W>> {
         let mut light = Light {
             green_light,
             yellow_light,
             red_light,
+            flashing: false,
         };
-        light.set_state(&Off);
-        light
+        light.set_state(&Off)?;
+        Ok(light)
     }
 
     /// Sets the state for the traffic light
     ///
+    /// Every pin is written even if an earlier one failed, so as much as possible of the state is
+    /// shown. A flashing light starts with the yellow light of the current cycle, see
+    /// [`Light::blink`]
+    ///
     /// # Arguments
     ///
     /// * `state` - reference to the state which should be set
-    pub fn set_state(&mut self, state: &IntersectionActionLight) {
+    pub fn set_state(&mut self, state: &IntersectionActionLight) -> Result<()> {
         let light_states = match state {
             Green(_) => (LIGHT_ACTIVE, !LIGHT_ACTIVE, !LIGHT_ACTIVE),
             Yellow => (!LIGHT_ACTIVE, LIGHT_ACTIVE, !LIGHT_ACTIVE),
+            FlashingYellow => (!LIGHT_ACTIVE, blink_level(), !LIGHT_ACTIVE),
             Red => (!LIGHT_ACTIVE, !LIGHT_ACTIVE, LIGHT_ACTIVE),
             RedYellow => (!LIGHT_ACTIVE, LIGHT_ACTIVE, LIGHT_ACTIVE),
             Off => (!LIGHT_ACTIVE, !LIGHT_ACTIVE, !LIGHT_ACTIVE),
         };
 
+        self.flashing = *state == FlashingYellow;
+
         let green_result = self.green_light.set_state(PinState::from(light_states.0));
         let yellow_result = self.yellow_light.set_state(PinState::from(light_states.1));
         let red_result = self.red_light.set_state(PinState::from(light_states.2));
 
-        for result in [green_result, yellow_result, red_result] {
-            if result.is_err() {
-                panic!("write pin failed");
-            }
+        match (green_result, yellow_result, red_result) {
+            (Ok(()), Ok(()), Ok(())) => Ok(()),
+            _ => Err(Error::LightPin),
+        }
+    }
+
+    /// Switches the yellow light of a flashing light on or off by the clock
+    ///
+    /// Does nothing if the light doesn't flash, has to be called regularily
+    pub fn blink(&mut self) -> Result<()> {
+        if !self.flashing {
+            return Ok(());
         }
+        self.yellow_light
+            .set_state(PinState::from(blink_level()))
+            .map_err(|_| Error::LightPin)
+    }
+}
+
+/// Returns the level of a flashing light at the current time
+pub fn blink_level() -> bool {
+    match millis() % BLINK_PERIOD < BLINK_PERIOD / 2 {
+        true => LIGHT_ACTIVE,
+        false => !LIGHT_ACTIVE,
     }
 }
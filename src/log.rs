@@ -0,0 +1,380 @@
+//! Leveled logging with a filter per module
+//!
+//! Messages are written with the macros [`error!`](crate::error), [`warn!`](crate::warn),
+//! [`info!`](crate::info), [`debug!`](crate::debug) and [`trace!`](crate::trace) to the sinks of a
+//! [`Logger`]. Every line starts with the level, the time in milliseconds and the module:
+//!
+//! ```text
+//! D 1520 sensor: sensor 3 detected
+//! ```
+//!
+//! Messages above [`MAX_LEVEL`] are removed at compile time. It is selected with one of the
+//! `max-level-off`, `max-level-error`, `max-level-warn`, `max-level-info`, `max-level-debug` or
+//! `max-level-trace` features, the lowest one wins. Without a feature debug builds keep every
+//! message and release builds keep up to [`Level::Info`]. Below that the level of each module is
+//! changed at runtime with the `LOG <module|ALL> <level>` command.
+
+use core::cell::{Cell, RefCell};
+
+use crate::error::Result;
+use crate::serial::Serial;
+use crate::slots::Slots;
+use crate::time::millis;
+
+/// Highest level which is compiled in
+pub const MAX_LEVEL: Level = if cfg!(feature = "max-level-off") {
+    Level::Off
+} else if cfg!(feature = "max-level-error") {
+    Level::Error
+} else if cfg!(feature = "max-level-warn") {
+    Level::Warn
+} else if cfg!(feature = "max-level-info") {
+    Level::Info
+} else if cfg!(feature = "max-level-debug") {
+    Level::Debug
+} else if cfg!(any(feature = "max-level-trace", debug_assertions)) {
+    Level::Trace
+} else {
+    Level::Info
+};
+
+/// Level a module starts with
+pub const DEFAULT_LEVEL: Level = Level::Info;
+
+/// Importance of a message, a filter lets every message up to its level pass
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    /// Only used as filter, lets no message pass
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    /// Returns the name used in commands
+    pub fn name(&self) -> &'static str {
+        match self {
+            Level::Off => "OFF",
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+
+    /// Parses the name used in commands
+    pub fn parse(name: &str) -> Option<Level> {
+        [
+            Level::Off,
+            Level::Error,
+            Level::Warn,
+            Level::Info,
+            Level::Debug,
+            Level::Trace,
+        ]
+        .into_iter()
+        .find(|level| level.name() == name)
+    }
+
+    /// Returns the letter a line of the level starts with
+    fn letter(&self) -> &'static str {
+        &self.name()[..1]
+    }
+}
+
+/// Number of modules with their own filter
+pub const MODULES: usize = 13;
+
+/// Part of the logic a message comes from
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Module {
+    Main,
+    Sensor,
+    Section,
+    Stopper,
+    Remote,
+    Intersection,
+    Night,
+    Pedestrian,
+    Coordination,
+    Preemption,
+    LevelCrossing,
+    Roundabout,
+    Turnout,
+}
+
+impl Module {
+    /// Every module in the order of their filters
+    pub const ALL: [Module; MODULES] = [
+        Module::Main,
+        Module::Sensor,
+        Module::Section,
+        Module::Stopper,
+        Module::Remote,
+        Module::Intersection,
+        Module::Night,
+        Module::Pedestrian,
+        Module::Coordination,
+        Module::Preemption,
+        Module::LevelCrossing,
+        Module::Roundabout,
+        Module::Turnout,
+    ];
+
+    /// Returns the name used in commands
+    pub fn name(&self) -> &'static str {
+        match self {
+            Module::Main => "MAIN",
+            Module::Sensor => "SENSOR",
+            Module::Section => "SECTION",
+            Module::Stopper => "STOPPER",
+            Module::Remote => "REMOTE",
+            Module::Intersection => "INTERSECTION",
+            Module::Night => "NIGHT",
+            Module::Pedestrian => "PEDESTRIAN",
+            Module::Coordination => "COORDINATION",
+            Module::Preemption => "PREEMPTION",
+            Module::LevelCrossing => "LEVEL",
+            Module::Roundabout => "ROUNDABOUT",
+            Module::Turnout => "TURNOUT",
+        }
+    }
+
+    /// Parses the name used in commands
+    pub fn parse(name: &str) -> Option<Module> {
+        Module::ALL.into_iter().find(|module| module.name() == name)
+    }
+
+    /// Returns the name a line of the module contains
+    fn label(&self) -> &'static str {
+        match self {
+            Module::Main => "main",
+            Module::Sensor => "sensor",
+            Module::Section => "section",
+            Module::Stopper => "stopper",
+            Module::Remote => "remote",
+            Module::Intersection => "intersection",
+            Module::Night => "night",
+            Module::Pedestrian => "pedestrian",
+            Module::Coordination => "coordination",
+            Module::Preemption => "preemption",
+            Module::LevelCrossing => "level crossing",
+            Module::Roundabout => "roundabout",
+            Module::Turnout => "turnout",
+        }
+    }
+}
+
+/// Struct which filters messages and writes them to its sinks
+///
+/// A sink is any [`Serial`], like the UART, a [`RingBuffer`] or the stdout of the host tools.
+pub struct Logger<'l> {
+    sinks: Slots<&'l RefCell<dyn Serial + 'l>, 2>,
+    levels: [Cell<Level>; MODULES],
+}
+
+impl<'l> Logger<'l> {
+    /// Returns a logger without sinks, every module starts with [`DEFAULT_LEVEL`]
+    pub fn new() -> Logger<'l> {
+        Logger {
+            sinks: Slots::new(),
+            levels: Default::default(),
+        }
+    }
+
+    /// Adds a sink the messages are written to
+    ///
+    /// Fails with [`Error::Config`](crate::error::Error::Config) of id 0 when the logger already
+    /// has two sinks
+    ///
+    /// # Arguments
+    ///
+    /// * `sink` - the output to write the messages to
+    pub fn add_sink(&mut self, sink: &'l RefCell<dyn Serial + 'l>) -> Result<()> {
+        self.sinks.push(sink, 0)
+    }
+
+    /// Sets the level up to which messages of the module pass
+    ///
+    /// # Arguments
+    ///
+    /// * `module` - the module or `None` for every module
+    /// * `level` - the highest level which passes
+    pub fn set_level(&self, module: Option<Module>, level: Level) {
+        for (index, filter) in self.levels.iter().enumerate() {
+            if module.map_or(true, |module| module as usize == index) {
+                filter.set(level);
+            }
+        }
+    }
+
+    /// Returns the level up to which messages of the module pass
+    pub fn level(&self, module: Module) -> Level {
+        self.levels[module as usize].get()
+    }
+
+    /// Returns if a message of the module and level would be written
+    pub fn enabled(&self, module: Module, level: Level) -> bool {
+        level <= MAX_LEVEL && level <= self.level(module)
+    }
+
+    /// Writes one line to every sink, the macros should be used instead
+    ///
+    /// # Arguments
+    ///
+    /// * `module` - the module the message comes from
+    /// * `level` - the level of the message
+    /// * `message` - writes the message without the line ending
+    pub fn write(&self, module: Module, level: Level, message: impl Fn(&mut dyn Serial)) {
+        let time = millis();
+        for sink in self.sinks.iter() {
+            let mut sink = sink.borrow_mut();
+            let sink: &mut dyn Serial = &mut *sink;
+            ufmt::uwrite!(sink, "{} {} {}: ", level.letter(), time, module.label()).unwrap();
+            message(sink);
+            sink.write_str("\n");
+        }
+    }
+}
+
+impl Default for Logger<'_> {
+    fn default() -> Self {
+        Logger::new()
+    }
+}
+
+impl Default for Level {
+    fn default() -> Self {
+        DEFAULT_LEVEL
+    }
+}
+
+/// Sink keeping the last `N` bytes of output in RAM
+///
+/// Older output is overwritten, so the buffer always holds the most recent lines.
+pub struct RingBuffer<const N: usize> {
+    buffer: [u8; N],
+    /// Index the next byte is written to
+    next: usize,
+    /// If the buffer was filled once
+    wrapped: bool,
+}
+
+impl<const N: usize> RingBuffer<N> {
+    /// Returns an empty ring buffer
+    pub fn new() -> RingBuffer<N> {
+        RingBuffer {
+            buffer: [0; N],
+            next: 0,
+            wrapped: false,
+        }
+    }
+
+    /// Writes the complete lines of the buffer from the oldest to the newest
+    ///
+    /// # Arguments
+    ///
+    /// * `serial` - the output to write the lines to
+    pub fn dump(&self, serial: &mut dyn Serial) {
+        let (older, newer) = match self.wrapped {
+            true => (&self.buffer[self.next..], &self.buffer[..self.next]),
+            false => (&self.buffer[..0], &self.buffer[..self.next]),
+        };
+        // the oldest line was partly overwritten
+        let older = match self.wrapped {
+            true => match older.iter().position(|byte| *byte == b'\n') {
+                Some(index) => &older[index + 1..],
+                None => &older[older.len()..],
+            },
+            false => older,
+        };
+        for part in [older, newer] {
+            serial.write_str(core::str::from_utf8(part).unwrap_or("?\n"));
+        }
+    }
+
+    /// Removes every line
+    pub fn clear(&mut self) {
+        self.next = 0;
+        self.wrapped = false;
+    }
+}
+
+impl<const N: usize> Default for RingBuffer<N> {
+    fn default() -> Self {
+        RingBuffer::new()
+    }
+}
+
+impl<const N: usize> Serial for RingBuffer<N> {
+    fn write_str(&mut self, s: &str) {
+        for byte in s.bytes() {
+            self.buffer[self.next] = byte;
+            self.next += 1;
+            if self.next == N {
+                self.next = 0;
+                self.wrapped = true;
+            }
+        }
+    }
+}
+
+/// Writes a message of the given level if the logger lets it pass
+///
+/// `log!(logger, Level::Debug, Module::Sensor, "sensor {} detected", id)`
+#[macro_export]
+macro_rules! log {
+    ($logger:expr, $level:expr, $module:expr, $($arg:tt)+) => {{
+        let logger: &$crate::log::Logger = $logger;
+        let (level, module) = ($level, $module);
+        if logger.enabled(module, level) {
+            logger.write(module, level, |serial: &mut dyn $crate::serial::Serial| {
+                ufmt::uwrite!(serial, $($arg)+).unwrap();
+            });
+        }
+    }};
+}
+
+/// Writes a message of level [`Level::Error`], see [`log!`](crate::log!)
+#[macro_export]
+macro_rules! error {
+    ($logger:expr, $module:expr, $($arg:tt)+) => {
+        $crate::log!($logger, $crate::log::Level::Error, $module, $($arg)+)
+    };
+}
+
+/// Writes a message of level [`Level::Warn`], see [`log!`](crate::log!)
+#[macro_export]
+macro_rules! warn {
+    ($logger:expr, $module:expr, $($arg:tt)+) => {
+        $crate::log!($logger, $crate::log::Level::Warn, $module, $($arg)+)
+    };
+}
+
+/// Writes a message of level [`Level::Info`], see [`log!`](crate::log!)
+#[macro_export]
+macro_rules! info {
+    ($logger:expr, $module:expr, $($arg:tt)+) => {
+        $crate::log!($logger, $crate::log::Level::Info, $module, $($arg)+)
+    };
+}
+
+/// Writes a message of level [`Level::Debug`], see [`log!`](crate::log!)
+#[macro_export]
+macro_rules! debug {
+    ($logger:expr, $module:expr, $($arg:tt)+) => {
+        $crate::log!($logger, $crate::log::Level::Debug, $module, $($arg)+)
+    };
+}
+
+/// Writes a message of level [`Level::Trace`], see [`log!`](crate::log!)
+#[macro_export]
+macro_rules! trace {
+    ($logger:expr, $module:expr, $($arg:tt)+) => {
+        $crate::log!($logger, $crate::log::Level::Trace, $module, $($arg)+)
+    };
+}
@@ -2,31 +2,109 @@
 #![no_main]
 #![feature(abi_avr_interrupt)]
 
-use sensor_caller::SensorCaller;
-use time::millis;
+use car_system::board::{self, CLEARANCE_TIME, INTERSECTION_ID, SETTLE_TIME};
+use car_system::crash::{self, CrashKind, CrashRecord, Eeprom};
+use car_system::error::{Error, Result};
+use car_system::failsafe::{self, Port, SafePin};
+use car_system::fault::{Action, FaultHandler};
+use car_system::history::History;
+use car_system::intersection::*;
+use car_system::lights::Light;
+use car_system::log::{Logger, Module};
+use car_system::monitor::{ConflictMatrix, Movement};
+use car_system::night::NightMode;
+use car_system::plan::PhasePlan;
+use car_system::protocol::{Command, Event};
+use car_system::remote::Remote;
+use car_system::sensor_caller::SensorCaller;
+use car_system::serial::{Serial, Tee};
+use car_system::servo::Servo;
+use car_system::time::millis;
+use car_system::watchdog::{ResetCause, Supervisor, Task};
 
-mod section;
-mod stopper;
-mod sensor;
-mod time;
-mod sensor_caller;
-
-use crate::section::*;
-use crate::stopper::*;
-use crate::sensor::*;
-use crate::sensor::SensorEnum::*;
+use car_system::section::*;
+use car_system::stopper::*;
+use car_system::sensor::*;
 
 use arduino_hal::port::{Pin, mode::{Output, Input}};
-use core::cell::RefCell;
+use avr_device::interrupt::Mutex;
+use core::cell::{Cell, RefCell};
+use embedded_hal::serial::Read;
+
+/// Number of events kept in the history
+const HISTORY_LENGTH: usize = 32;
+/// First address of the EEPROM area reserved for the crash record
+const CRASH_RECORD_ADDRESS: u16 = 0;
+/// Address of the servo controller on the I2C bus
+const SERVO_ADDRESS: u8 = 4;
+/// Servo angles for the right and left direction
+const SERVO_ANGLES: (u8, u8) = (60, 120);
+
+/// Index of the sensor polling in `TASKS`
+const SENSOR_TASK: usize = 0;
+/// Index of the intersection tick in `TASKS`
+const INTERSECTION_TASK: usize = 1;
+
+/// Tasks of the main loop, the watchdog is only fed while all of them keep their deadline
+static TASKS: [Task; 2] = [
+    Task {
+        name: "sensors",
+        deadline: 50,
+    },
+    // the servo updates are part of the intersection tick
+    Task {
+        name: "intersection",
+        deadline: 500,
+    },
+];
+
+/// The task which is running, so the watchdog interrupt can tell which one hangs
+static RUNNING_TASK: Mutex<Cell<Option<usize>>> = Mutex::new(Cell::new(None));
+/// If a crash record was already stored for the coming watchdog reset
+static WATCHDOG_RECORDED: Mutex<Cell<bool>> = Mutex::new(Cell::new(false));
+
+/// Event history, static so the panic handler can put the last events into the crash record
+static mut HISTORY: RefCell<History<HISTORY_LENGTH>> = RefCell::new(History::new());
+
+/// Outputs the panic handler drives to their safe level, has to match the pins set up in `main`
+static SAFE_PINS: [SafePin; 16] = [
+    // stoppers 1 to 7 on d41, d43, d45, d47, d49, d51, d53
+    SafePin::stopper(Port::G, 0),
+    SafePin::stopper(Port::L, 6),
+    SafePin::stopper(Port::L, 4),
+    SafePin::stopper(Port::L, 2),
+    SafePin::stopper(Port::L, 0),
+    SafePin::stopper(Port::B, 2),
+    SafePin::stopper(Port::B, 0),
+    // left light on d22, d24, d26
+    SafePin::light_off(Port::A, 0),
+    SafePin::light_off(Port::A, 2),
+    SafePin::light_red(Port::A, 4),
+    // right light on d28, d30, d32
+    SafePin::light_off(Port::A, 6),
+    SafePin::light_off(Port::C, 7),
+    SafePin::light_red(Port::C, 5),
+    // upper light on d34, d36, d38
+    SafePin::light_off(Port::C, 3),
+    SafePin::light_off(Port::C, 1),
+    SafePin::light_red(Port::D, 7),
+];
 
 #[arduino_hal::entry]
 fn main() -> ! {
     // setup of peripherals
     let dp = arduino_hal::Peripherals::take().unwrap();
+
+    // the watchdog stays enabled with its shortest timeout after a watchdog reset, so it is set up
+    // before anything else
+    let reset_cause = ResetCause::from_flags(dp.CPU.mcusr.read().bits());
+    dp.CPU.mcusr.write(|w| unsafe { w.bits(0) });
+    watchdog_start(&dp.WDT);
+
     let pins = arduino_hal::pins!(dp);
 
     // setup i2c
-    let mut i2c = RefCell::new(arduino_hal::I2c::new(
+    let i2c = RefCell::new(arduino_hal::I2c::new(
         dp.TWI,
         pins.d20.into_pull_up_input(),
         pins.d21.into_pull_up_input(),
@@ -36,8 +114,33 @@ fn main() -> ! {
     // setup serial
     let adc = arduino_hal::Adc::new(dp.ADC, Default::default());
     let serial = RefCell::new(arduino_hal::default_serial!(dp, pins, 57600));
-    ufmt::uwriteln!(&mut serial.borrow_mut(), "serial start").unwrap();
 
+    // setup logging
+    let mut log = Logger::new();
+    let sink = log.add_sink(&serial);
+    car_system::info!(&log, Module::Main, "serial start");
+    match reset_cause {
+        ResetCause::Watchdog | ResetCause::Brownout => {
+            car_system::warn!(&log, Module::Main, "reset by {}", reset_cause.name());
+        }
+        _ => car_system::info!(&log, Module::Main, "reset by {}", reset_cause.name()),
+    }
+
+    // report the crash before the last reset
+    let mut eeprom = BoardEeprom(&dp.EEPROM);
+    if let Some(record) = CrashRecord::load(&mut eeprom, CRASH_RECORD_ADDRESS) {
+        record.write(&mut *serial.borrow_mut());
+    }
+
+    // setup event history, every event is written to the serial and kept in the history
+    // UNSAFE: the history is only accessed from here on and by the panic handler, which checks
+    // that it isn't borrowed
+    let history: &RefCell<History<HISTORY_LENGTH>> = unsafe { &HISTORY };
+    let output = RefCell::new(Tee::new(&serial, history));
+
+    // a part which can't be set up goes through the fault handler like any later error
+    let mut faults = FaultHandler::new();
+    set_up(sink, &mut faults, &log, &output, &mut eeprom);
 
     // setup integrated led
     let mut led = pins.d13.into_output();
@@ -46,31 +149,45 @@ fn main() -> ! {
 
     // stopper 1 setup
     let stopper_1_pin = pins.d41.into_output().downgrade();
-    let stopper_1: RefCell<Stopper<Pin<Output>>> = RefCell::new(Stopper::new(stopper_1_pin));
+    let stopper_1 = Stopper::new(stopper_1_pin, 1);
+    let stopper_1: RefCell<Stopper<Pin<Output>>> =
+        RefCell::new(set_up(stopper_1, &mut faults, &log, &output, &mut eeprom));
     
     // stopper 2 setup
     let stopper_2_pin = pins.d43.into_output().downgrade();
-    let stopper_2: RefCell<Stopper<Pin<Output>>> = RefCell::new(Stopper::new(stopper_2_pin));
+    let stopper_2 = Stopper::new(stopper_2_pin, 2);
+    let stopper_2: RefCell<Stopper<Pin<Output>>> =
+        RefCell::new(set_up(stopper_2, &mut faults, &log, &output, &mut eeprom));
     
     // stopper 3 setup
     let stopper_3_pin = pins.d45.into_output().downgrade();
-    let stopper_3: RefCell<Stopper<Pin<Output>>> = RefCell::new(Stopper::new(stopper_3_pin));
+    let stopper_3 = Stopper::new(stopper_3_pin, 3);
+    let stopper_3: RefCell<Stopper<Pin<Output>>> =
+        RefCell::new(set_up(stopper_3, &mut faults, &log, &output, &mut eeprom));
     
     // stopper 4 setup
     let stopper_4_pin = pins.d47.into_output().downgrade();
-    let stopper_4: RefCell<Stopper<Pin<Output>>> = RefCell::new(Stopper::new(stopper_4_pin));
+    let stopper_4 = Stopper::new(stopper_4_pin, 4);
+    let stopper_4: RefCell<Stopper<Pin<Output>>> =
+        RefCell::new(set_up(stopper_4, &mut faults, &log, &output, &mut eeprom));
     
     // stopper 5 setup
     let stopper_5_pin = pins.d49.into_output().downgrade();
-    let stopper_5: RefCell<Stopper<Pin<Output>>> = RefCell::new(Stopper::new(stopper_5_pin));
+    let stopper_5 = Stopper::new(stopper_5_pin, 5);
+    let stopper_5: RefCell<Stopper<Pin<Output>>> =
+        RefCell::new(set_up(stopper_5, &mut faults, &log, &output, &mut eeprom));
     
     // stopper 6 setup
     let stopper_6_pin = pins.d51.into_output().downgrade();
-    let stopper_6: RefCell<Stopper<Pin<Output>>> = RefCell::new(Stopper::new(stopper_6_pin));
+    let stopper_6 = Stopper::new(stopper_6_pin, 6);
+    let stopper_6: RefCell<Stopper<Pin<Output>>> =
+        RefCell::new(set_up(stopper_6, &mut faults, &log, &output, &mut eeprom));
     
     // stopper 7 setup
     let stopper_7_pin = pins.d53.into_output().downgrade();
-    let stopper_7: RefCell<Stopper<Pin<Output>>> = RefCell::new(Stopper::new(stopper_7_pin));
+    let stopper_7 = Stopper::new(stopper_7_pin, 7);
+    let stopper_7: RefCell<Stopper<Pin<Output>>> =
+        RefCell::new(set_up(stopper_7, &mut faults, &log, &output, &mut eeprom));
 
     // sensor: a7, a6, a5, a4, a3, a2, a1
     // sensor 1 setup
@@ -101,90 +218,219 @@ fn main() -> ! {
     let sensor_7_pin = pins.a1.into_pull_up_input().forget_imode().downgrade();
     let sensor_7: RefCell<Sensor<Pin<Output>, Pin<Input>>> = RefCell::new(Sensor::new(sensor_7_pin, 7));
     
-    // section 1 setup
-    let section_1: RefCell<Section<Pin<Output>, Pin<Input>>> = RefCell::new(Section::new(1, &serial));
-    section_1.borrow_mut().set_self_reference(&section_1);
-    section_1.borrow_mut().add_stopper(&stopper_1);
-    section_1.borrow_mut().add_sensor(StartSensor(&sensor_3));
-    section_1.borrow_mut().add_sensor(StartSensor(&sensor_2));
-    section_1.borrow_mut().add_sensor(EndSensor(&sensor_5));
-    section_1.borrow_mut().add_sensor(EndSensor(&sensor_6));
-    
-    // section 2 setup
-    let section_2: RefCell<Section<Pin<Output>, Pin<Input>>> = RefCell::new(Section::new(2, &serial));
-    section_2.borrow_mut().set_self_reference(&section_2);
-    section_2.borrow_mut().add_stopper(&stopper_2);
-    section_2.borrow_mut().add_sensor(StartSensor(&sensor_3));
-    section_2.borrow_mut().add_sensor(StartSensor(&sensor_1));
-    section_2.borrow_mut().add_sensor(EndSensor(&sensor_5));
-    section_2.borrow_mut().add_sensor(EndSensor(&sensor_4));
-    
-    // section 3 setup
-    let section_3: RefCell<Section<Pin<Output>, Pin<Input>>> = RefCell::new(Section::new(3, &serial));
-    section_3.borrow_mut().set_self_reference(&section_3);
-    section_3.borrow_mut().add_stopper(&stopper_3);
-    section_3.borrow_mut().add_sensor(StartSensor(&sensor_1));
-    section_3.borrow_mut().add_sensor(StartSensor(&sensor_2));
-    section_3.borrow_mut().add_sensor(EndSensor(&sensor_4));
-    section_3.borrow_mut().add_sensor(EndSensor(&sensor_6));
-    
-    // section 4 setup
-    let section_4: RefCell<Section<Pin<Output>, Pin<Input>>> = RefCell::new(Section::new(4, &serial));
-    section_4.borrow_mut().set_self_reference(&section_4);
-    section_4.borrow_mut().add_stopper(&stopper_4);
-    section_4.borrow_mut().add_sensor(StartSensor(&sensor_4));
-    section_4.borrow_mut().add_sensor(EndSensor(&sensor_1));
-    section_4.borrow_mut().add_sensor(EndSensor(&sensor_2));
-    
-    // section 5 setup
-    let section_5: RefCell<Section<Pin<Output>, Pin<Input>>> = RefCell::new(Section::new(5, &serial));
-    section_5.borrow_mut().set_self_reference(&section_5);
-    section_5.borrow_mut().add_stopper(&stopper_5);
-    section_5.borrow_mut().add_sensor(StartSensor(&sensor_5));
-    section_5.borrow_mut().add_sensor(EndSensor(&sensor_3));
-    section_5.borrow_mut().add_sensor(EndSensor(&sensor_2));
-    
-    // section 6 setup
-    let section_6: RefCell<Section<Pin<Output>, Pin<Input>>> = RefCell::new(Section::new(6, &serial));
-    section_6.borrow_mut().set_self_reference(&section_6);
-    section_6.borrow_mut().add_stopper(&stopper_6);
-    section_6.borrow_mut().add_sensor(StartSensor(&sensor_6));
-    section_6.borrow_mut().add_sensor(EndSensor(&sensor_7));
-    
-    // section 7 setup
-    let section_7: RefCell<Section<Pin<Output>, Pin<Input>>> = RefCell::new(Section::new(7, &serial));
-    section_7.borrow_mut().set_self_reference(&section_7);
-    section_7.borrow_mut().add_stopper(&stopper_7);
-    section_7.borrow_mut().add_sensor(StartSensor(&sensor_7));
-    section_7.borrow_mut().add_sensor(EndSensor(&sensor_3));
-    section_7.borrow_mut().add_sensor(EndSensor(&sensor_1));
+    // the stoppers and sensors by their id, the sections and the intersection are wired by them
+    let stoppers = [&stopper_1, &stopper_2, &stopper_3, &stopper_4, &stopper_5, &stopper_6, &stopper_7];
+    let sensors = [&sensor_1, &sensor_2, &sensor_3, &sensor_4, &sensor_5, &sensor_6, &sensor_7];
+
+    // sections setup, the tables in `car_system::board` are shared with the board layout of the
+    // host tools
+    let sections = board::SECTIONS.map(|section| RefCell::new(Section::new(section.id, &output)));
+    for (section, board_section) in sections.iter().zip(board::SECTIONS.iter()) {
+        let wiring = board_section.wire(section, &stoppers, &sensors);
+        set_up(wiring, &mut faults, &log, &output, &mut eeprom);
+    }
     
+    // servo: a8, a9, a10, the servo controller on the I2C bus moves them
+    let (right_angle, left_angle) = SERVO_ANGLES;
+
+    // left servo setup
+    let left_servo_pin = pins.a8.into_output().downgrade();
+    let left_servo =
+        Servo::new(left_servo_pin, right_angle, left_angle, &i2c, LEFT_ARM as u8, SERVO_ADDRESS);
+    let left_servo = RefCell::new(set_up(left_servo, &mut faults, &log, &output, &mut eeprom));
+
+    // right servo setup
+    let right_servo_pin = pins.a9.into_output().downgrade();
+    let right_servo =
+        Servo::new(right_servo_pin, right_angle, left_angle, &i2c, RIGHT_ARM as u8, SERVO_ADDRESS);
+    let right_servo = RefCell::new(set_up(right_servo, &mut faults, &log, &output, &mut eeprom));
+
+    // upper servo setup
+    let upper_servo_pin = pins.a10.into_output().downgrade();
+    let upper_servo =
+        Servo::new(upper_servo_pin, right_angle, left_angle, &i2c, UPPER_ARM as u8, SERVO_ADDRESS);
+    let upper_servo = RefCell::new(set_up(upper_servo, &mut faults, &log, &output, &mut eeprom));
+
+    // light: green, yellow and red on 22 to 38
+    // left light setup
+    let left_light_green_pin = pins.d22.into_output().downgrade();
+    let left_light_yellow_pin = pins.d24.into_output().downgrade();
+    let left_light_red_pin = pins.d26.into_output().downgrade();
+    let left_light = Light::new(left_light_green_pin, left_light_yellow_pin, left_light_red_pin);
+    let left_light = set_up(left_light, &mut faults, &log, &output, &mut eeprom);
+
+    // right light setup
+    let right_light_green_pin = pins.d28.into_output().downgrade();
+    let right_light_yellow_pin = pins.d30.into_output().downgrade();
+    let right_light_red_pin = pins.d32.into_output().downgrade();
+    let right_light = Light::new(right_light_green_pin, right_light_yellow_pin, right_light_red_pin);
+    let right_light = set_up(right_light, &mut faults, &log, &output, &mut eeprom);
+
+    // upper light setup
+    let upper_light_green_pin = pins.d34.into_output().downgrade();
+    let upper_light_yellow_pin = pins.d36.into_output().downgrade();
+    let upper_light_red_pin = pins.d38.into_output().downgrade();
+    let upper_light = Light::new(upper_light_green_pin, upper_light_yellow_pin, upper_light_red_pin);
+    let upper_light = set_up(upper_light, &mut faults, &log, &output, &mut eeprom);
+
+    // intersection setup, the entry stoppers are the stoppers of the sections 1, 2 and 3, the
+    // intersection lock overwrites the locks of the sections
+    let [left_stopper, right_stopper, upper_stopper] = board::ARMS.map(|id| stoppers[id as usize - 1]);
+    let arms = [
+        IntersectionArm {
+            entry_stopper: left_stopper,
+            light: left_light,
+            servo: &left_servo,
+            approach: None,
+        },
+        IntersectionArm {
+            entry_stopper: right_stopper,
+            light: right_light,
+            servo: &right_servo,
+            approach: None,
+        },
+        IntersectionArm {
+            entry_stopper: upper_stopper,
+            light: upper_light,
+            servo: &upper_servo,
+            approach: None,
+        },
+    ];
+    // the plan is validated against the conflicts, an invalid one is a config error
+    let intersection =
+        Intersection::new(INTERSECTION_ID, arms, PhasePlan::T_JUNCTION, ConflictMatrix::T_JUNCTION);
+    let mut intersection = set_up(intersection, &mut faults, &log, &output, &mut eeprom);
+    // there are no sensors inside the intersection, the clearance time has to do
+    intersection.set_clearance(CLEARANCE_TIME, None);
+    intersection.set_servo_timing(SETTLE_TIME, SERVO_TIMEOUT);
+    // the board has no clock of the day, the night mode is switched by the host
+    intersection.set_night_mode(NightMode::T_JUNCTION);
+
     // sensor caller setup
-    let sensors = [&sensor_1, &sensor_2, &sensor_3, &sensor_4, &sensor_5, &sensor_6, &sensor_7];
     let sensor_caller = SensorCaller::new(&sensors);
+
+    // remote setup
+    let remote = Remote::new(&stoppers, &output);
+    let mut remote = set_up(remote, &mut faults, &log, &output, &mut eeprom);
     
     // initiate micros
-    crate::time::millis_init(dp.TC0);
+    car_system::time::millis_init(dp.TC0);
     // enable interrupts globally
     unsafe { avr_device::interrupt::enable() };
 
     let mut last_1000ms: u64 = 0;
+    let mut last_50ms: u64 = 0;
     let mut last_5ms: u64 = 0;
+    let supervisor = Supervisor::new(&TASKS, millis());
+    let mut supervisor = set_up(supervisor, &mut faults, &log, &output, &mut eeprom);
+    let mut reported_lights: Option<[IntersectionActionLight; 3]> = None;
 
     loop {
+        // handle commands from the host
+        let received = serial.borrow_mut().read();
+        if let Ok(byte) = received {
+            match remote.receive(byte) {
+                Ok(Some(Command::Log(module, level))) => log.set_level(module, level),
+                Ok(Some(Command::Dump)) => history.borrow().dump(&mut *serial.borrow_mut()),
+                Ok(Some(Command::Crash)) => {
+                    crash::report(&mut eeprom, CRASH_RECORD_ADDRESS, &mut *serial.borrow_mut());
+                }
+                Ok(Some(Command::ClearCrash)) => {
+                    CrashRecord::clear(&mut eeprom, CRASH_RECORD_ADDRESS);
+                    crash::report(&mut eeprom, CRASH_RECORD_ADDRESS, &mut *serial.borrow_mut());
+                }
+                Ok(Some(Command::NextPhase(INTERSECTION_ID))) => {
+                    if let Err(error) = intersection.next_phase() {
+                        handle_error(error, &mut faults, &log, &output, &sensor_caller, &mut eeprom);
+                    }
+                }
+                Ok(Some(Command::Night(INTERSECTION_ID, night))) => {
+                    if let Err(error) = intersection.set_night(night, &log) {
+                        handle_error(error, &mut faults, &log, &output, &sensor_caller, &mut eeprom);
+                    }
+                }
+                Ok(Some(Command::Preempt(INTERSECTION_ID, arm, direction))) => {
+                    let movement = Movement::new(arm as usize, direction);
+                    if let Err(error) = intersection.preempt(movement, &log) {
+                        handle_error(error, &mut faults, &log, &output, &sensor_caller, &mut eeprom);
+                    }
+                }
+                Ok(Some(Command::ClearPreemption(INTERSECTION_ID))) => intersection.clear_preemption(),
+                Ok(_) => (),
+                Err(error) => {
+                    handle_error(error, &mut faults, &log, &output, &sensor_caller, &mut eeprom);
+                }
+            }
+        }
+        remote.call();
+
         // call the sensor caller
         let current = millis();
-        if last_5ms + 5 < current {
-            sensor_caller.call(Some(&serial));
+        if last_5ms + 5 <= current {
+            set_running_task(Some(SENSOR_TASK));
+            let result = sensor_caller.call(Some(&output), &log);
+            set_running_task(None);
+            if let Err(error) = result {
+                handle_error(error, &mut faults, &log, &output, &sensor_caller, &mut eeprom);
+            }
+            supervisor.ran(SENSOR_TASK, millis());
             last_5ms = current;
         }
 
+        // call the intersection often enough for states shorter than a second, it times them by
+        // the clock
         let current = millis();
-        if last_1000ms + 1_000 < current {
+        if last_50ms + 50 <= current {
+            set_running_task(Some(INTERSECTION_TASK));
+            let mut result = intersection.call(&log);
+            // a retry executes the state again right away
+            while let Err(error) = result {
+                if handle_error(error, &mut faults, &log, &output, &sensor_caller, &mut eeprom) != Action::Retry {
+                    break;
+                }
+                result = intersection.retry();
+            }
+            if result.is_ok() {
+                faults.succeeded();
+            }
+            set_running_task(None);
+            supervisor.ran(INTERSECTION_TASK, millis());
+            last_50ms = current;
+        }
+
+        let current = millis();
+        if last_1000ms + 1_000 <= current {
             led.toggle();
-            // call the intersection
             last_1000ms = current;
         }
+
+        // report the lights when they changed
+        let lights = intersection.current_lights();
+        if reported_lights.as_ref().map(|reported| &reported[..]) != Some(lights) {
+            for (arm, light) in lights.iter().enumerate() {
+                Event::Light {
+                    intersection: INTERSECTION_ID,
+                    arm: arm as u8,
+                    light: *light,
+                }
+                .write(&mut *output.borrow_mut());
+            }
+            reported_lights = lights.try_into().ok();
+        }
+
+        // feed the watchdog only while every task keeps its deadline, otherwise the board resets
+        match supervisor.overdue(millis()) {
+            None => avr_device::asm::wdr(),
+            Some(task) => {
+                let recorded =
+                    avr_device::interrupt::free(|cs| WATCHDOG_RECORDED.borrow(cs).replace(true));
+                if !recorded {
+                    let name = supervisor.task(task).name;
+                    car_system::error!(&log, Module::Main, "task {} missed its deadline", name);
+                    store_crash(&mut eeprom, CrashKind::Watchdog, name, 0);
+                }
+            }
+        }
     }
 }
 
@@ -197,6 +443,17 @@ fn panic(info: &core::panic::PanicInfo) -> ! {
     // get the peripherals so we can access serial and the LED
     // UNSAFE: because main already has references to the peripherals this is an unsafe operation
     let dp = unsafe { arduino_hal::Peripherals::steal() };
+
+    // lock the stoppers and turn the lights red before anything else can fail
+    failsafe::drive_safe(&SAFE_PINS, |port, bit, level| write_pin(&dp, port, bit, level));
+
+    // keep the location and the last events for the next boot
+    let (file, line) = match info.location() {
+        Some(location) => (location.file(), location.line()),
+        None => ("?", 0),
+    };
+    store_crash(&mut BoardEeprom(&dp.EEPROM), CrashKind::Panic, file, line);
+
     let pins = arduino_hal::pins!(dp);
 
     #[cfg(debug_assertions)]
@@ -216,9 +473,176 @@ fn panic(info: &core::panic::PanicInfo) -> ! {
         }
     }
 
+    // the board stays halted in the fail-safe state with the LED blinking until it is reset by
+    // hand, so the watchdog is fed instead of running the logic which panicked again
     let mut led = pins.d13.into_output();
     loop {
+        avr_device::asm::wdr();
         led.toggle();
         arduino_hal::delay_ms(100);
     }
-}
\ No newline at end of file
+}
+/// Records a hang of the main loop just before the watchdog resets the board
+///
+/// The watchdog raises this interrupt on its first timeout and resets on the second one.
+#[avr_device::interrupt(atmega2560)]
+fn WDT() {
+    // UNSAFE: main is stuck, this interrupt is the last code running before the reset
+    let dp = unsafe { arduino_hal::Peripherals::steal() };
+    failsafe::drive_safe(&SAFE_PINS, |port, bit, level| write_pin(&dp, port, bit, level));
+
+    avr_device::interrupt::free(|cs| {
+        if !WATCHDOG_RECORDED.borrow(cs).replace(true) {
+            let name = match RUNNING_TASK.borrow(cs).get() {
+                Some(task) => TASKS[task].name,
+                None => "main loop",
+            };
+            store_crash(&mut BoardEeprom(&dp.EEPROM), CrashKind::Watchdog, name, 0);
+        }
+    });
+
+    // wait for the reset, the outputs must not be changed by main anymore
+    loop {}
+}
+
+/// Enables the watchdog with a timeout of two seconds, first raising its interrupt and then
+/// resetting the board
+fn watchdog_start(wdt: &arduino_hal::pac::WDT) {
+    // WDIE, WDE and WDP2..0 for two seconds
+    const CONFIGURATION: u8 = 0b0100_1111;
+    // WDCE and WDE allow changing the configuration within the next four cycles
+    const CHANGE_ENABLE: u8 = 0b0001_1000;
+    avr_device::interrupt::free(|_| {
+        avr_device::asm::wdr();
+        wdt.wdtcsr.write(|w| unsafe { w.bits(CHANGE_ENABLE) });
+        wdt.wdtcsr.write(|w| unsafe { w.bits(CONFIGURATION) });
+    });
+}
+
+/// Carries on after the error as the fault handler decides and returns its decision
+fn handle_error(
+    error: Error,
+    faults: &mut FaultHandler,
+    log: &Logger,
+    output: &RefCell<dyn Serial + '_>,
+    sensor_caller: &SensorCaller<Pin<Output>, Pin<Input>>,
+    eeprom: &mut BoardEeprom,
+) -> Action {
+    let action = faults.handle(error, log, &mut *output.borrow_mut());
+    match action {
+        // the caller runs the failed operation again right away or with the next call of its task
+        Action::Retry => (),
+        Action::Degrade => {
+            if let Error::SensorPin(id) = error {
+                if let Err(error) = sensor_caller.degrade(id) {
+                    handle_error(error, faults, log, output, sensor_caller, eeprom);
+                }
+            }
+        }
+        Action::Escalate => escalate(eeprom, error),
+    }
+    action
+}
+
+/// Returns the part or escalates the error it couldn't be set up with
+///
+/// Nothing can be retried or taken out of service while the parts are set up, the fault handler
+/// only logs and reports the error.
+fn set_up<T>(
+    result: Result<T>,
+    faults: &mut FaultHandler,
+    log: &Logger,
+    output: &RefCell<dyn Serial + '_>,
+    eeprom: &mut BoardEeprom,
+) -> T {
+    match result {
+        Ok(part) => part,
+        Err(error) => {
+            faults.handle(error, log, &mut *output.borrow_mut());
+            escalate(eeprom, error)
+        }
+    }
+}
+
+/// Drives the fail-safe state, records the error and waits for the watchdog to reset the board
+fn escalate(eeprom: &mut BoardEeprom, error: Error) -> ! {
+    // UNSAFE: main gives up its pins, nothing but this function and the watchdog interrupt runs
+    // from here on
+    let dp = unsafe { arduino_hal::Peripherals::steal() };
+    failsafe::drive_safe(&SAFE_PINS, |port, bit, level| write_pin(&dp, port, bit, level));
+
+    // the watchdog interrupt mustn't overwrite the record of the error
+    avr_device::interrupt::free(|cs| WATCHDOG_RECORDED.borrow(cs).set(true));
+    store_crash(eeprom, CrashKind::Fault(error.code()), error.name(), error.id() as u32);
+
+    // the watchdog isn't fed anymore and resets the board
+    loop {}
+}
+
+fn set_running_task(task: Option<usize>) {
+    avr_device::interrupt::free(|cs| RUNNING_TASK.borrow(cs).set(task));
+}
+
+/// Writes a crash record with the last events of the history to the EEPROM
+fn store_crash(eeprom: &mut BoardEeprom, kind: CrashKind, file: &str, line: u32) {
+    let mut record = CrashRecord::new(kind, file, line, millis());
+    // UNSAFE: only main and the handlers of fatal errors access the history, a borrow of the
+    // interrupted code is detected by `try_borrow`
+    if let Ok(history) = unsafe { HISTORY.try_borrow() } {
+        for (_, event) in history.entries() {
+            record.push_event(*event);
+        }
+    }
+    record.store(eeprom, CRASH_RECORD_ADDRESS);
+}
+
+/// The EEPROM of the board accessed through its registers, so the panic handler can use it too
+struct BoardEeprom<'l>(&'l arduino_hal::pac::EEPROM);
+
+impl Eeprom for BoardEeprom<'_> {
+    fn read_byte(&mut self, address: u16) -> u8 {
+        // wait for the previous write to finish
+        while self.0.eecr.read().eepe().bit_is_set() {}
+        self.0.eear.write(|w| unsafe { w.bits(address) });
+        self.0.eecr.write(|w| w.eere().set_bit());
+        self.0.eedr.read().bits()
+    }
+
+    fn write_byte(&mut self, address: u16, byte: u8) {
+        while self.0.eecr.read().eepe().bit_is_set() {}
+        self.0.eear.write(|w| unsafe { w.bits(address) });
+        self.0.eedr.write(|w| unsafe { w.bits(byte) });
+        // the write has to start within four cycles after enabling it
+        avr_device::interrupt::free(|_| {
+            self.0.eecr.write(|w| w.eempe().set_bit());
+            self.0.eecr.write(|w| w.eepe().set_bit());
+        });
+    }
+}
+
+/// Sets the pin to an output with the given level by writing the port registers directly
+fn write_pin(dp: &arduino_hal::Peripherals, port: Port, bit: u8, level: bool) {
+    let mask = 1 << bit;
+    macro_rules! write_port {
+        ($port:ident, $output:ident, $direction:ident) => {{
+            // set the level before the direction, so the pin never drives the wrong level
+            dp.$port.$output.modify(|r, w| unsafe {
+                w.bits(if level { r.bits() | mask } else { r.bits() & !mask })
+            });
+            dp.$port.$direction.modify(|r, w| unsafe { w.bits(r.bits() | mask) });
+        }};
+    }
+    match port {
+        Port::A => write_port!(PORTA, porta, ddra),
+        Port::B => write_port!(PORTB, portb, ddrb),
+        Port::C => write_port!(PORTC, portc, ddrc),
+        Port::D => write_port!(PORTD, portd, ddrd),
+        Port::E => write_port!(PORTE, porte, ddre),
+        Port::F => write_port!(PORTF, portf, ddrf),
+        Port::G => write_port!(PORTG, portg, ddrg),
+        Port::H => write_port!(PORTH, porth, ddrh),
+        Port::J => write_port!(PORTJ, portj, ddrj),
+        Port::K => write_port!(PORTK, portk, ddrk),
+        Port::L => write_port!(PORTL, portl, ddrl),
+    }
+}
@@ -0,0 +1,195 @@
+//! Independent check of the states an intersection applies
+//!
+//! A wrong phase table mustn't let cars of conflicting movements into an intersection. Every
+//! intersection has a [`ConflictMatrix`] telling which movements may have green together and a
+//! [`SafetyMonitor`] checking each state against it before it is applied, and the entry stoppers
+//! against the lights after it was applied. The arms passing a walking pedestrian crossing have to
+//! be red. On a violation the intersection turns every arm red, locks the entry stoppers and stays
+//! like that until the board is reset.
+
+use crate::intersection::IntersectionActionDirection::{self, *};
+use crate::intersection::IntersectionActionLight::{self, *};
+use crate::intersection::{LEFT_ARM, MAX_ARMS, RIGHT_ARM, UPPER_ARM};
+/// Number of movements, every arm has one to the right and one to the left
+const MOVEMENTS: usize = 2 * MAX_ARMS;
+
+/// A movement through the intersection, given by the arm the cars come from and their direction
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Movement {
+    pub arm: usize,
+    pub direction: IntersectionActionDirection,
+}
+
+impl Movement {
+    /// Returns the movement from the arm with the index in the given direction
+    pub const fn new(arm: usize, direction: IntersectionActionDirection) -> Movement {
+        Movement { arm, direction }
+    }
+
+    const fn index(&self) -> usize {
+        2 * self.arm
+            + match self.direction {
+                Right => 0,
+                Left => 1,
+            }
+    }
+}
+
+/// Struct telling which movements conflict
+///
+/// Every pair of movements from different arms conflicts unless it was allowed with
+/// [`ConflictMatrix::allow`], so a forgotten entry can only make the monitor stricter.
+#[derive(Clone, Copy)]
+pub struct ConflictMatrix {
+    /// A bit for every movement conflicting with the movement of the index
+    conflicts: [u16; MOVEMENTS],
+}
+
+impl ConflictMatrix {
+    /// The T-junction preset, allowing the movements its default phase plan runs together
+    pub const T_JUNCTION: ConflictMatrix = ConflictMatrix::new()
+        .allow(Movement::new(LEFT_ARM, Right), Movement::new(RIGHT_ARM, Right))
+        .allow(Movement::new(LEFT_ARM, Right), Movement::new(UPPER_ARM, Right))
+        .allow(Movement::new(RIGHT_ARM, Right), Movement::new(UPPER_ARM, Right))
+        .allow(Movement::new(LEFT_ARM, Left), Movement::new(UPPER_ARM, Right))
+        .allow(Movement::new(LEFT_ARM, Right), Movement::new(RIGHT_ARM, Left))
+        .allow(Movement::new(RIGHT_ARM, Right), Movement::new(UPPER_ARM, Left));
+
+    /// Returns a matrix in which all movements of different arms conflict
+    pub const fn new() -> ConflictMatrix {
+        let mut conflicts = [0; MOVEMENTS];
+        let mut index = 0;
+        while index < MOVEMENTS {
+            // the two movements of an arm share the green and can't conflict
+            let arm: u16 = 0b11 << (index / 2 * 2);
+            conflicts[index] = !arm;
+            index += 1;
+        }
+        ConflictMatrix { conflicts }
+    }
+
+    /// Returns the matrix with the two movements allowed to have green together
+    ///
+    /// # Panic
+    /// Panics when an arm index isn't below MAX_ARMS
+    pub const fn allow(mut self, first: Movement, second: Movement) -> ConflictMatrix {
+        if first.arm >= MAX_ARMS || second.arm >= MAX_ARMS {
+            panic!("no more than 8 arms allowed");
+        }
+        self.conflicts[first.index()] &= !(1 << second.index());
+        self.conflicts[second.index()] &= !(1 << first.index());
+        self
+    }
+
+    /// Returns if the two movements conflict
+    pub fn conflicts(&self, first: Movement, second: Movement) -> bool {
+        self.conflicts[first.index()] & (1 << second.index()) != 0
+    }
+}
+
+impl Default for ConflictMatrix {
+    fn default() -> Self {
+        ConflictMatrix::new()
+    }
+}
+
+/// A state which must not be applied or stay applied
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Violation {
+    /// The two arms have green for conflicting movements
+    Conflict(usize, usize),
+    /// The entry stopper of the arm is released but its light isn't green
+    OpenStopper(usize),
+    /// The arm isn't red but passes a pedestrian crossing with walk
+    Crossing(usize),
+}
+
+/// Struct which checks the states of an intersection, see the module documentation
+pub struct SafetyMonitor {
+    matrix: ConflictMatrix,
+    /// If a violation was found, the intersection stays red from then on
+    tripped: bool,
+}
+
+impl SafetyMonitor {
+    /// Returns a monitor checking against the matrix
+    pub const fn new(matrix: ConflictMatrix) -> SafetyMonitor {
+        SafetyMonitor {
+            matrix,
+            tripped: false,
+        }
+    }
+
+    /// Checks the lights of a state before it is applied
+    ///
+    /// # Arguments
+    ///
+    /// * `lights` - the light of every arm by its index
+    pub fn check_lights(&mut self, lights: &[IntersectionActionLight]) -> Result<(), Violation> {
+        for (first, first_light) in lights.iter().enumerate() {
+            for (second, second_light) in lights.iter().enumerate().skip(first + 1) {
+                if let (Green(first_direction), Green(second_direction)) = (first_light, second_light) {
+                    let first_movement = Movement::new(first, *first_direction);
+                    let second_movement = Movement::new(second, *second_direction);
+                    if self.matrix.conflicts(first_movement, second_movement) {
+                        return self.trip(Violation::Conflict(first, second));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the entry stoppers after a state was applied, only arms with green may be released
+    ///
+    /// # Arguments
+    ///
+    /// * `lights` - the light of every arm by its index
+    /// * `locked` - returns if the entry stopper of the arm with the index is locked
+    pub fn check_stoppers(
+        &mut self,
+        lights: &[IntersectionActionLight],
+        locked: impl Fn(usize) -> bool,
+    ) -> Result<(), Violation> {
+        for (arm, light) in lights.iter().enumerate() {
+            if !matches!(light, Green(_)) && !locked(arm) {
+                return self.trip(Violation::OpenStopper(arm));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the lights of a state against a pedestrian crossing with walk or its clearance
+    ///
+    /// # Arguments
+    ///
+    /// * `lights` - the light of every arm by its index
+    /// * `arms` - the arms passing the crossing by their index
+    pub fn check_crossing(
+        &mut self,
+        lights: &[IntersectionActionLight],
+        arms: &[bool; MAX_ARMS],
+    ) -> Result<(), Violation> {
+        for (arm, light) in lights.iter().enumerate() {
+            if arms[arm] && *light != Red {
+                return self.trip(Violation::Crossing(arm));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the matrix the monitor checks against
+    pub fn matrix(&self) -> &ConflictMatrix {
+        &self.matrix
+    }
+
+    /// Returns if a violation was found
+    pub fn tripped(&self) -> bool {
+        self.tripped
+    }
+
+    fn trip(&mut self, violation: Violation) -> Result<(), Violation> {
+        self.tripped = true;
+        Err(violation)
+    }
+}
@@ -0,0 +1,264 @@
+//! Night mode of intersections
+//!
+//! For unattended running an intersection can be switched to a night mode, by the `NIGHT` command
+//! or every day by the schedule of its [`NightMode`] settings. Every arm then shows a flashing
+//! yellow light instead of running through the states, and the entry stoppers follow a simple
+//! priority rule:
+//!
+//! * the arms of the main road are released and let their cars in with their direction
+//! * a car waiting on a yielding arm is let in when the box is free and no car arrived on a
+//!   conflicting arm of the main road within the crossing time, the conflicting arms are locked
+//!   until it crossed
+//! * the yielding arms are served one after another in the order their cars arrived, the main road
+//!   gets at least the crossing time between two of them
+//!
+//! Yielding arms without an approach count as always waiting, arms of the main road without one
+//! as free, so they are only protected by the crossing time they get between two yielding arms.
+//! Arms without a direction stay locked. When the night mode ends, every arm turns red for the
+//! all-red clearance and the intersection continues with its next state without yellow or
+//! red-yellow lights. The intersection runs the stages with a [`Night`], emergency vehicles can't
+//! preempt it in the meantime.
+
+use crate::intersection::IntersectionActionDirection::{self, *};
+use crate::intersection::{LEFT_ARM, MAX_ARMS, RIGHT_ARM, UPPER_ARM};
+use crate::time::DailyWindow;
+
+/// Settings of the night mode of an intersection, see the module documentation
+#[derive(Clone, Copy)]
+pub struct NightMode {
+    /// The direction every arm lets its cars in, arms without one stay locked
+    pub directions: [Option<IntersectionActionDirection>; MAX_ARMS],
+    /// The arms of the main road, the others yield
+    pub main_road: [bool; MAX_ARMS],
+    /// The milliseconds a car needs to cross the intersection
+    pub crossing_time: u32,
+    /// The time of every day the night mode is switched on for by itself
+    pub schedule: Option<DailyWindow>,
+}
+
+impl NightMode {
+    /// The night mode of the T-junction preset, the left and the right arm are the main road and
+    /// the cars of the upper arm turn left into it
+    pub const T_JUNCTION: NightMode = NightMode {
+        directions: {
+            let mut directions = [None; MAX_ARMS];
+            directions[LEFT_ARM] = Some(Right);
+            directions[RIGHT_ARM] = Some(Right);
+            directions[UPPER_ARM] = Some(Left);
+            directions
+        },
+        main_road: {
+            let mut main_road = [false; MAX_ARMS];
+            main_road[LEFT_ARM] = true;
+            main_road[RIGHT_ARM] = true;
+            main_road
+        },
+        crossing_time: 3_000,
+        schedule: None,
+    };
+}
+
+/// The stage of the night mode
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum NightStage {
+    /// The cars let in before clear the box and the servos of the main road are commanded
+    Entering,
+    /// The main road is released
+    MainRoad,
+    /// The conflicting arms of the main road are locked for a car of the yielding arm, which is
+    /// released once its servo settled
+    Yielding { arm: usize, released: bool },
+}
+
+/// What an intersection does for the next stage of its night mode, it applies the arms
+/// [`Night::released`] after every step
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum NightStep {
+    /// Release the main road
+    MainRoad,
+    /// Command the servo of the yielding arm with the index
+    Yield(usize),
+    /// Release the yielding arm, its servo settled
+    Release,
+    /// The car of the yielding arm with the index crossed, release the main road
+    Crossed(usize),
+}
+
+/// The night mode an intersection runs while it is on
+#[derive(Clone, Copy)]
+pub struct Night {
+    stage: NightStage,
+    /// The time in milliseconds the stage started
+    start: u64,
+}
+
+impl Night {
+    /// Returns the night mode switched on at the time, the cars let in before clear the box first
+    ///
+    /// # Arguments
+    ///
+    /// * `time` - the current time in milliseconds
+    pub fn new(time: u64) -> Night {
+        Night {
+            stage: NightStage::Entering,
+            start: time,
+        }
+    }
+
+    /// Returns the time in milliseconds the stage started
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    /// Returns if the arm lets its cars in
+    ///
+    /// # Arguments
+    ///
+    /// * `night_mode` - the settings
+    /// * `arm` - the index of the arm
+    /// * `conflicts` - tells if the movement of the yielding arm, the first index, conflicts with
+    ///   the one of the arm of the main road, the second index
+    pub fn released(
+        &self,
+        night_mode: &NightMode,
+        arm: usize,
+        conflicts: impl Fn(usize, usize) -> bool,
+    ) -> bool {
+        match self.stage {
+            NightStage::Entering => false,
+            NightStage::MainRoad => night_mode.main_road[arm],
+            NightStage::Yielding { arm: yielding, released } if yielding == arm => released,
+            NightStage::Yielding { arm: yielding, .. } => {
+                night_mode.main_road[arm] && !conflicts(yielding, arm)
+            }
+        }
+    }
+
+    /// Moves on to the next stage when the current one is over and returns what the intersection
+    /// does for it
+    ///
+    /// Has to be called once the servos of the stage settled
+    ///
+    /// # Arguments
+    ///
+    /// * `night_mode` - the settings
+    /// * `time` - the current time in milliseconds
+    /// * `clearance` - the milliseconds of the all-red clearance
+    /// * `occupied` - if cars are inside the box
+    /// * `next_yielding` - returns the yielding arm whose car may be let in next
+    pub fn step(
+        &mut self,
+        night_mode: &NightMode,
+        time: u64,
+        clearance: u32,
+        occupied: bool,
+        next_yielding: impl FnOnce() -> Option<usize>,
+    ) -> Option<NightStep> {
+        let elapsed = time.saturating_sub(self.start);
+        let crossed = elapsed >= night_mode.crossing_time as u64;
+        let (stage, step) = match self.stage {
+            NightStage::Entering if elapsed >= clearance as u64 && !occupied => {
+                (NightStage::MainRoad, NightStep::MainRoad)
+            }
+            NightStage::MainRoad if crossed && !occupied => {
+                let arm = next_yielding()?;
+                let stage = NightStage::Yielding {
+                    arm,
+                    released: false,
+                };
+                (stage, NightStep::Yield(arm))
+            }
+            NightStage::Yielding {
+                arm,
+                released: false,
+            } => {
+                let stage = NightStage::Yielding {
+                    arm,
+                    released: true,
+                };
+                (stage, NightStep::Release)
+            }
+            NightStage::Yielding {
+                arm,
+                released: true,
+            } if crossed && !occupied => (NightStage::MainRoad, NightStep::Crossed(arm)),
+            _ => return None,
+        };
+        self.stage = stage;
+        self.start = time;
+        Some(step)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Night, NightMode, NightStep};
+    use crate::intersection::{LEFT_ARM, RIGHT_ARM, UPPER_ARM};
+
+    const CLEARANCE: u32 = 1_000;
+    const NIGHT_MODE: NightMode = NightMode::T_JUNCTION;
+
+    /// The upper arm turns left across the right arm, but not across the left one
+    fn conflicts(yielding: usize, arm: usize) -> bool {
+        yielding == UPPER_ARM && arm == RIGHT_ARM
+    }
+
+    fn released(night: &Night) -> [bool; 3] {
+        [LEFT_ARM, RIGHT_ARM, UPPER_ARM].map(|arm| night.released(&NIGHT_MODE, arm, conflicts))
+    }
+
+    #[test]
+    fn main_road_is_released_once_the_box_cleared_after_the_clearance() {
+        let mut night = Night::new(0);
+        assert_eq!(released(&night), [false; 3]);
+        assert!(night.step(&NIGHT_MODE, 999, CLEARANCE, false, || None).is_none());
+        assert!(night.step(&NIGHT_MODE, 1_000, CLEARANCE, true, || None).is_none());
+        let step = night.step(&NIGHT_MODE, 1_200, CLEARANCE, false, || None);
+        assert!(step == Some(NightStep::MainRoad));
+        assert_eq!(released(&night), [true, true, false]);
+        assert_eq!(night.start(), 1_200);
+    }
+
+    #[test]
+    fn yielding_arm_gets_the_crossing_time_after_the_main_road() {
+        let mut night = Night::new(0);
+        night.step(&NIGHT_MODE, 1_000, CLEARANCE, false, || None);
+        let crossing = NIGHT_MODE.crossing_time as u64;
+
+        // the main road keeps the crossing time, and without a car waiting even longer
+        let step = night.step(&NIGHT_MODE, 1_000 + crossing - 1, CLEARANCE, false, || Some(2));
+        assert!(step.is_none());
+        assert!(night.step(&NIGHT_MODE, 1_000 + crossing, CLEARANCE, false, || None).is_none());
+        let step = night.step(&NIGHT_MODE, 1_000 + crossing, CLEARANCE, true, || Some(2));
+        assert!(step.is_none());
+        assert_eq!(released(&night), [true, true, false]);
+
+        let time = 1_000 + crossing;
+        let step = night.step(&NIGHT_MODE, time, CLEARANCE, false, || Some(UPPER_ARM));
+        assert!(step == Some(NightStep::Yield(UPPER_ARM)));
+        // the conflicting arm of the main road is locked before the yielding arm is released
+        assert_eq!(released(&night), [true, false, false]);
+    }
+
+    #[test]
+    fn yielding_arm_is_released_after_its_servo_and_hands_back_once_its_car_crossed() {
+        let mut night = Night::new(0);
+        night.step(&NIGHT_MODE, 1_000, CLEARANCE, false, || None);
+        let crossing = NIGHT_MODE.crossing_time as u64;
+        let mut time = 1_000 + crossing;
+        night.step(&NIGHT_MODE, time, CLEARANCE, false, || Some(UPPER_ARM));
+
+        time += 500;
+        let step = night.step(&NIGHT_MODE, time, CLEARANCE, false, || None);
+        assert!(step == Some(NightStep::Release));
+        assert_eq!(released(&night), [true, false, true]);
+
+        // the car needs the crossing time and has to leave the box
+        let crossed = time + crossing;
+        assert!(night.step(&NIGHT_MODE, crossed - 1, CLEARANCE, false, || None).is_none());
+        assert!(night.step(&NIGHT_MODE, crossed, CLEARANCE, true, || None).is_none());
+        let step = night.step(&NIGHT_MODE, crossed, CLEARANCE, false, || None);
+        assert!(step == Some(NightStep::Crossed(UPPER_ARM)));
+        assert_eq!(released(&night), [true, true, false]);
+    }
+}
@@ -0,0 +1,319 @@
+//! Pedestrian crossings of intersections
+//!
+//! A [`Crossing`] has a signal head for the pedestrians and push buttons, which are wired like
+//! sensors and report the pedestrians waiting to the [`Demand`] of the crossing. A crossing is only
+//! walked on demand, with [`PedestrianTiming`] settings telling how:
+//!
+//! * concurrent, a crossing gets walk during a state in which every arm whose cars pass it is red,
+//!   the state lasts until the crossing is red again
+//! * exclusive, after the current state every arm turns yellow and red and all crossings walk
+//!   together, then the intersection continues with its next state, see [`ExclusivePhase`]
+//!
+//! The walk signal lasts `walk` and is followed by the flashing green of the `clearance`, both in
+//! milliseconds. The safety monitor checks that every arm a walking crossing passes shows red. In
+//! the night mode the signal heads are dark.
+
+use core::cell::Cell;
+use embedded_hal::digital::v2::{OutputPin, PinState};
+
+use crate::error::{Error, Result};
+use crate::intersection::MAX_ARMS;
+use crate::lights::{blink_level, LIGHT_ACTIVE};
+
+/// Maximum number of crossings of an intersection
+pub const MAX_CROSSINGS: usize = 4;
+
+/// Milliseconds the arms losing their green show yellow before an exclusive pedestrian phase
+pub const YELLOW_TIME: u32 = 2_000;
+
+/// The pedestrians waiting at a crossing, shared by its push buttons and the intersection
+pub struct Demand {
+    /// If a button was pressed since the crossing last got walk
+    requested: Cell<bool>,
+}
+
+impl Demand {
+    /// Returns a demand without waiting pedestrians
+    pub const fn new() -> Demand {
+        Demand {
+            requested: Cell::new(false),
+        }
+    }
+
+    /// Records a button being pressed
+    pub fn request(&self) {
+        self.requested.set(true);
+    }
+
+    /// Returns if pedestrians are waiting
+    pub fn requested(&self) -> bool {
+        self.requested.get()
+    }
+
+    /// Records the crossing getting walk
+    pub fn serve(&self) {
+        self.requested.set(false);
+    }
+}
+
+impl Default for Demand {
+    fn default() -> Self {
+        Demand::new()
+    }
+}
+
+/// What a pedestrian signal head shows
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum PedestrianAspect {
+    /// Red, don't walk
+    DontWalk,
+    /// Green, walk
+    Walk,
+    /// Flashing green, finish crossing
+    Clearance,
+    /// Dark
+    Dark,
+}
+
+/// Structure to represent the signal head of a pedestrian crossing
+pub struct PedestrianSignal<W>
+where
+    W: OutputPin,
+{
+    /// the output pin representing the green light
+    green_light: W,
+    /// the output pin representing the red light
+    red_light: W,
+    /// if the green light flashes
+    flashing: bool,
+}
+
+impl<W> PedestrianSignal<W>
+where
+    W: OutputPin,
+{
+    /// Returns a dark pedestrian signal head
+    ///
+    /// # Arguments
+    ///
+    /// * `green_light` - the output pin which represents the green light
+    /// * `red_light` - the output pin which represents the red light
+    pub fn new(green_light: W, red_light: W) -> Result<PedestrianSignal<W>> {
+        let mut signal = PedestrianSignal {
+            green_light,
+            red_light,
+            flashing: false,
+        };
+        signal.set_aspect(PedestrianAspect::Dark)?;
+        Ok(signal)
+    }
+
+    /// Sets what the signal head shows
+    ///
+    /// Both pins are written even if the first one failed
+    ///
+    /// # Arguments
+    ///
+    /// * `aspect` - the aspect which should be shown
+    pub fn set_aspect(&mut self, aspect: PedestrianAspect) -> Result<()> {
+        let light_states = match aspect {
+            PedestrianAspect::DontWalk => (!LIGHT_ACTIVE, LIGHT_ACTIVE),
+            PedestrianAspect::Walk => (LIGHT_ACTIVE, !LIGHT_ACTIVE),
+            PedestrianAspect::Clearance => (blink_level(), !LIGHT_ACTIVE),
+            PedestrianAspect::Dark => (!LIGHT_ACTIVE, !LIGHT_ACTIVE),
+        };
+        self.flashing = aspect == PedestrianAspect::Clearance;
+
+        let green_result = self.green_light.set_state(PinState::from(light_states.0));
+        let red_result = self.red_light.set_state(PinState::from(light_states.1));
+        match (green_result, red_result) {
+            (Ok(()), Ok(())) => Ok(()),
+            _ => Err(Error::LightPin),
+        }
+    }
+
+    /// Switches the green light of a flashing signal head on or off by the clock
+    ///
+    /// Does nothing if the signal head doesn't flash, has to be called regularily
+    pub fn blink(&mut self) -> Result<()> {
+        if !self.flashing {
+            return Ok(());
+        }
+        self.green_light
+            .set_state(PinState::from(blink_level()))
+            .map_err(|_| Error::LightPin)
+    }
+}
+
+/// A pedestrian crossing of an intersection
+pub struct Crossing<'l, W>
+where
+    W: OutputPin,
+{
+    pub signal: PedestrianSignal<W>,
+    /// The pedestrians waiting, reported by the push buttons
+    pub demand: &'l Demand,
+    /// The arms whose cars pass the crossing by their index
+    pub arms: [bool; MAX_ARMS],
+}
+
+/// Settings of the pedestrian crossings of an intersection, see the module documentation
+#[derive(Clone, Copy)]
+pub struct PedestrianTiming {
+    /// Minimum milliseconds of the walk signal
+    pub walk: u32,
+    /// Milliseconds of the flashing green after the walk signal
+    pub clearance: u32,
+    /// If the crossings walk in a phase of their own instead of together with the states
+    pub exclusive: bool,
+}
+
+impl Default for PedestrianTiming {
+    fn default() -> Self {
+        PedestrianTiming {
+            walk: 5_000,
+            clearance: 3_000,
+            exclusive: false,
+        }
+    }
+}
+
+/// The stage of an exclusive pedestrian phase
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ExclusiveStage {
+    /// The arms which had green show yellow
+    Yellow,
+    /// Every arm is red while the cars clear the box
+    Clearing,
+    /// The crossings have walk or their clearance
+    Walking,
+}
+
+/// What an intersection does for the next stage of its exclusive pedestrian phase
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ExclusiveStep {
+    /// Turn every arm red
+    AllRed,
+    /// Give walk to every crossing
+    Walk,
+    /// Continue with the next state, the phase is over
+    Continue,
+}
+
+/// An exclusive pedestrian phase an intersection runs after its current state
+#[derive(Clone, Copy)]
+pub struct ExclusivePhase {
+    stage: ExclusiveStage,
+    /// The time in milliseconds the stage started
+    start: u64,
+}
+
+impl ExclusivePhase {
+    /// Returns a phase starting with yellow for the arms which had green
+    ///
+    /// # Arguments
+    ///
+    /// * `yellow` - if an arm shows yellow, else the phase starts with the all-red clearance
+    /// * `time` - the current time in milliseconds
+    pub fn new(yellow: bool, time: u64) -> ExclusivePhase {
+        let stage = match yellow {
+            true => ExclusiveStage::Yellow,
+            false => ExclusiveStage::Clearing,
+        };
+        ExclusivePhase { stage, start: time }
+    }
+
+    /// Moves on to the next stage when the current one is over and returns what the intersection
+    /// does for it
+    ///
+    /// # Arguments
+    ///
+    /// * `time` - the current time in milliseconds
+    /// * `clearance` - the milliseconds of the all-red clearance
+    /// * `occupied` - if cars are inside the box
+    /// * `walking` - if a crossing has walk or its clearance
+    pub fn step(
+        &mut self,
+        time: u64,
+        clearance: u32,
+        occupied: bool,
+        walking: bool,
+    ) -> Option<ExclusiveStep> {
+        let elapsed = time.saturating_sub(self.start);
+        let (stage, step) = match self.stage {
+            ExclusiveStage::Yellow if elapsed >= YELLOW_TIME as u64 => {
+                (ExclusiveStage::Clearing, ExclusiveStep::AllRed)
+            }
+            ExclusiveStage::Clearing if elapsed >= clearance as u64 && !occupied => {
+                (ExclusiveStage::Walking, ExclusiveStep::Walk)
+            }
+            ExclusiveStage::Walking if !walking => return Some(ExclusiveStep::Continue),
+            _ => return None,
+        };
+        self.stage = stage;
+        self.start = time;
+        Some(step)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::RefCell;
+
+    use super::PedestrianAspect::*;
+    use super::*;
+    use crate::pin_mockup::Pin;
+
+    const CLEARANCE: u32 = 1_000;
+
+    #[test]
+    fn demand_is_kept_until_the_crossing_is_served() {
+        let demand = Demand::new();
+        assert!(!demand.requested());
+        demand.request();
+        demand.request();
+        assert!(demand.requested());
+        demand.serve();
+        assert!(!demand.requested());
+    }
+
+    #[test]
+    fn signal_head_starts_dark_and_shows_the_aspects() {
+        let (green, red) = (RefCell::new(true), RefCell::new(true));
+        let mut signal = PedestrianSignal::new(Pin::new(&green), Pin::new(&red)).unwrap();
+        let levels = || (*green.borrow(), *red.borrow());
+        assert_eq!(levels(), (!LIGHT_ACTIVE, !LIGHT_ACTIVE));
+        signal.set_aspect(DontWalk).unwrap();
+        assert_eq!(levels(), (!LIGHT_ACTIVE, LIGHT_ACTIVE));
+        signal.set_aspect(Walk).unwrap();
+        assert_eq!(levels(), (LIGHT_ACTIVE, !LIGHT_ACTIVE));
+        signal.set_aspect(Clearance).unwrap();
+        assert_eq!(levels().1, !LIGHT_ACTIVE);
+    }
+
+    #[test]
+    fn exclusive_phase_walks_after_the_yellow_and_the_cleared_box() {
+        let mut phase = ExclusivePhase::new(true, 0);
+        let yellow = YELLOW_TIME as u64;
+        assert!(phase.step(yellow - 1, CLEARANCE, false, false).is_none());
+        assert!(phase.step(yellow, CLEARANCE, false, false) == Some(ExclusiveStep::AllRed));
+
+        // the all-red clearance is counted from the end of the yellow and waits for the box
+        let cleared = yellow + CLEARANCE as u64;
+        assert!(phase.step(cleared - 1, CLEARANCE, false, false).is_none());
+        assert!(phase.step(cleared, CLEARANCE, true, false).is_none());
+        assert!(phase.step(cleared + 1, CLEARANCE, false, false) == Some(ExclusiveStep::Walk));
+
+        assert!(phase.step(cleared + 2, CLEARANCE, false, true).is_none());
+        let step = phase.step(cleared + 3, CLEARANCE, false, false);
+        assert!(step == Some(ExclusiveStep::Continue));
+    }
+
+    #[test]
+    fn exclusive_phase_without_yellow_starts_with_the_clearance() {
+        let mut phase = ExclusivePhase::new(false, 500);
+        assert!(phase.step(500 + CLEARANCE as u64 - 1, CLEARANCE, false, false).is_none());
+        let step = phase.step(500 + CLEARANCE as u64, CLEARANCE, false, false);
+        assert!(step == Some(ExclusiveStep::Walk));
+    }
+}
@@ -0,0 +1,249 @@
+//! Phase plans of intersections
+//!
+//! A [`PhasePlan`] is the list of states an intersection cycles through, its [`Schedule`]. It is
+//! built as a const table or at runtime:
+//!
+//! ```text
+//! const PLAN: PhasePlan = PhasePlan::new()
+//!     .phase(&[Green(Right), Red], 10_000)
+//!     .phase(&[Yellow, RedYellow], 2_000)
+//!     .phase(&[Red, Green(Left)], 10_000)
+//!     .phase(&[RedYellow, Yellow], 2_000);
+//! ```
+//!
+//! Before a plan is used it is checked with [`PhasePlan::validate`]: no phase may give green to
+//! conflicting movements and every arm has to go from green over yellow to red and from red over
+//! red-yellow to green, also from the last phase back to the first one.
+
+use crate::intersection::IntersectionActionDirection::*;
+use crate::intersection::IntersectionActionLight::{self, *};
+use crate::intersection::{IntersectionState, MAX_ARMS};
+use crate::monitor::{ConflictMatrix, Movement};
+use crate::schedule::Schedule;
+
+/// Maximum number of phases of a plan
+pub const MAX_PHASES: usize = 16;
+
+const LONG_PHASE_TIME: u32 = 18_000;
+const SHORT_PHASE_TIME: u32 = 2_000;
+
+/// Why a plan can't be used
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PlanError {
+    /// The plan has no phases
+    Empty,
+    /// The intersection has more arms than a plan can describe
+    TooManyArms,
+    /// The phase with the index lasts no time
+    ZeroDuration(usize),
+    /// The phase with the index gives green to conflicting movements of the two arms with the
+    /// indices
+    Conflict(usize, usize, usize),
+    /// The arm can't switch to its light in the phase with the index, e.g. from green to red
+    Transition(usize, usize),
+}
+
+/// Struct holding the phases of an intersection, see the module documentation
+#[derive(Clone, Copy)]
+pub struct PhasePlan {
+    phases: [IntersectionState; MAX_PHASES],
+    length: usize,
+    /// Index of the current phase
+    count: usize,
+    /// The phase inserted by [`Schedule::preempt`] which wasn't returned yet
+    inserted: Option<IntersectionState>,
+    /// The inserted phase if it is the current one
+    preemption: Option<IntersectionState>,
+}
+
+impl PhasePlan {
+    /// The phase plan of the T-junction preset, to be used with
+    /// [`ConflictMatrix::T_JUNCTION`]
+    pub const T_JUNCTION: PhasePlan = PhasePlan::new()
+        // Phase 1
+        .phase(&[Green(Right), Green(Right), Green(Right)], LONG_PHASE_TIME)
+        .phase(&[Green(Right), Yellow, Green(Right)], SHORT_PHASE_TIME)
+        // Phase 2
+        .phase(&[Green(Left), Red, Green(Right)], LONG_PHASE_TIME)
+        .phase(&[Green(Left), RedYellow, Yellow], SHORT_PHASE_TIME)
+        // Phase 3
+        .phase(&[Green(Right), Green(Left), Red], LONG_PHASE_TIME)
+        .phase(&[Yellow, Green(Left), RedYellow], SHORT_PHASE_TIME)
+        // Phase 4
+        .phase(&[Red, Green(Right), Green(Left)], LONG_PHASE_TIME)
+        .phase(&[RedYellow, Green(Right), Green(Left)], SHORT_PHASE_TIME);
+
+    /// Returns a plan without phases
+    pub const fn new() -> PhasePlan {
+        PhasePlan {
+            phases: [IntersectionState::new(&[], 0); MAX_PHASES],
+            length: 0,
+            count: 0,
+            inserted: None,
+            preemption: None,
+        }
+    }
+
+    /// Returns the plan with a phase added at the end
+    ///
+    /// The plan starts with its first phase as the current one, the arms after the given lights
+    /// are off.
+    ///
+    /// # Panic
+    /// Panics when there are more than MAX_PHASES phases or MAX_ARMS lights
+    ///
+    /// # Arguments
+    ///
+    /// * `actions` - the light of every arm by its index
+    /// * `duration` - the milliseconds the phase lasts
+    pub const fn phase(mut self, actions: &[IntersectionActionLight], duration: u32) -> PhasePlan {
+        if self.length == MAX_PHASES {
+            panic!("no more than 16 phases allowed");
+        }
+        self.phases[self.length] = IntersectionState::new(actions, duration);
+        self.length += 1;
+        self
+    }
+
+    /// Adds a phase at the end, see [`PhasePlan::phase`]
+    pub fn push(&mut self, actions: &[IntersectionActionLight], duration: u32) {
+        *self = self.phase(actions, duration);
+    }
+
+    /// Returns the milliseconds of one cycle through all phases
+    pub fn cycle(&self) -> u32 {
+        self.phases[..self.length].iter().map(IntersectionState::duration).sum()
+    }
+
+    /// Checks the plan for an intersection, see the module documentation
+    ///
+    /// # Arguments
+    ///
+    /// * `arms` - the number of arms of the intersection
+    /// * `conflicts` - the movements of the intersection which mustn't have green together
+    pub fn validate(&self, arms: usize, conflicts: &ConflictMatrix) -> Result<(), PlanError> {
+        if self.length == 0 {
+            return Err(PlanError::Empty);
+        }
+        if arms > MAX_ARMS {
+            return Err(PlanError::TooManyArms);
+        }
+        let phases = &self.phases[..self.length];
+        for (index, phase) in phases.iter().enumerate() {
+            if phase.duration() == 0 {
+                return Err(PlanError::ZeroDuration(index));
+            }
+            for first in 0..arms {
+                for second in first + 1..arms {
+                    if let (Green(first_direction), Green(second_direction)) =
+                        (phase.action(first), phase.action(second))
+                    {
+                        let first_movement = Movement::new(first, first_direction);
+                        let second_movement = Movement::new(second, second_direction);
+                        if conflicts.conflicts(first_movement, second_movement) {
+                            return Err(PlanError::Conflict(index, first, second));
+                        }
+                    }
+                }
+            }
+            let previous = &phases[(index + phases.len() - 1) % phases.len()];
+            for arm in 0..arms {
+                if !may_follow(previous.action(arm), phase.action(arm)) {
+                    return Err(PlanError::Transition(index, arm));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for PhasePlan {
+    fn default() -> Self {
+        PhasePlan::new()
+    }
+}
+
+impl Schedule for PhasePlan {
+    fn next(&mut self) -> &IntersectionState {
+        self.preemption = self.inserted.take();
+        // a plan without phases stays at its empty first one
+        if self.preemption.is_none() && self.length > 0 {
+            self.count = (self.count + 1) % self.length;
+        }
+        self.current()
+    }
+
+    fn current(&self) -> &IntersectionState {
+        match &self.preemption {
+            Some(state) => state,
+            None => &self.phases[self.count],
+        }
+    }
+
+    fn index(&self) -> usize {
+        self.count
+    }
+
+    fn len(&self) -> usize {
+        self.length
+    }
+
+    fn validate(&self, arms: usize, conflicts: &ConflictMatrix) -> Result<(), PlanError> {
+        PhasePlan::validate(self, arms, conflicts)
+    }
+
+    fn reset(&mut self, index: usize) {
+        self.count = index.min(self.length.saturating_sub(1));
+        self.inserted = None;
+        self.preemption = None;
+    }
+
+    fn preempt(&mut self, state: IntersectionState) {
+        self.inserted = Some(state);
+    }
+
+    fn preempted(&self) -> bool {
+        self.preemption.is_some()
+    }
+}
+
+/// Returns if a light may show the next light right after the previous one
+///
+/// The direction of a green may change, the servo is switched with it.
+fn may_follow(previous: IntersectionActionLight, next: IntersectionActionLight) -> bool {
+    matches!(
+        (previous, next),
+        (Green(_), Green(_) | Yellow)
+            | (Yellow, Yellow | Red | Off)
+            | (Red, Red | RedYellow | Off)
+            | (RedYellow, RedYellow | Green(_))
+            | (Off, Off | Red | Yellow)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn t_junction_is_valid_for_its_conflicts() {
+        let plan = PhasePlan::T_JUNCTION;
+        assert_eq!(plan.validate(3, &ConflictMatrix::T_JUNCTION), Ok(()));
+    }
+
+    #[test]
+    fn plan_without_phases_is_empty_and_stays_put() {
+        let mut plan = PhasePlan::new();
+        assert_eq!(plan.validate(3, &ConflictMatrix::T_JUNCTION), Err(PlanError::Empty));
+        plan.next();
+        plan.skip(2);
+        assert_eq!(plan.index(), 0);
+    }
+
+    #[test]
+    fn reset_past_the_last_phase_saturates() {
+        let mut plan = PhasePlan::T_JUNCTION;
+        plan.reset(MAX_PHASES);
+        assert_eq!(plan.index(), plan.len() - 1);
+    }
+}
@@ -0,0 +1,216 @@
+//! Preemption of intersections by emergency vehicles
+//!
+//! An emergency vehicle calls green for its movement with a dedicated sensor on its approach or
+//! the `PREEMPT` command. The intersection then ends the current state safely and holds the
+//! movement green until the vehicle cleared the intersection:
+//!
+//! * an all-red clearance or a positioning of servos which runs is finished first
+//! * the arms which had green show yellow for [`YELLOW_TIME`], the other ones red, and walking
+//!   pedestrian crossings switch to their clearance
+//! * after the all-red clearance and the clearance of the crossings the servo of the arm is set to
+//!   the movement and the arm gets green
+//! * when a clearance sensor after the intersection fires or the `PREEMPT <id> CLEAR` command
+//!   arrives while the movement has green, the arm shows yellow and the intersection continues its plan with the phase after
+//!   the interrupted one, without yellow or red-yellow lights and after the all-red clearance
+//!
+//! A call during a preemption is dropped, the night mode ignores calls. The intersection runs
+//! the stages with a [`PreemptionRun`].
+
+use core::cell::Cell;
+
+use crate::monitor::Movement;
+use crate::pedestrian::YELLOW_TIME;
+
+/// The calls and clearances of emergency vehicles, shared by their sensors and the intersection
+pub struct Preemption {
+    /// The movement called green for since the intersection last looked
+    call: Cell<Option<Movement>>,
+    /// If an emergency vehicle cleared the intersection since it last looked
+    cleared: Cell<bool>,
+}
+
+impl Preemption {
+    /// Returns a preemption without calls
+    pub const fn new() -> Preemption {
+        Preemption {
+            call: Cell::new(None),
+            cleared: Cell::new(false),
+        }
+    }
+
+    /// Records an emergency vehicle calling green for the movement
+    ///
+    /// # Arguments
+    ///
+    /// * `movement` - the arm the vehicle comes from and its direction
+    pub fn call(&self, movement: Movement) {
+        self.call.set(Some(movement));
+    }
+
+    /// Records an emergency vehicle clearing the intersection
+    pub fn clear(&self) {
+        self.cleared.set(true);
+    }
+
+    /// Returns the movement called green for since the last time and forgets it
+    pub fn take_call(&self) -> Option<Movement> {
+        self.call.take()
+    }
+
+    /// Returns if an emergency vehicle cleared the intersection since the last time and forgets it
+    pub fn take_cleared(&self) -> bool {
+        self.cleared.replace(false)
+    }
+}
+
+impl Default for Preemption {
+    fn default() -> Self {
+        Preemption::new()
+    }
+}
+
+/// The stage of a preemption
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum PreemptionStage {
+    /// The movement waits for the transition to the current state to finish
+    Called(Movement),
+    /// The arms which had green show yellow before the movement gets green
+    Yellow(Movement),
+    /// The movement has green until the vehicle cleared the intersection
+    Holding,
+    /// The arm of the movement shows yellow before the plan continues
+    Leaving,
+}
+
+/// What an intersection does for the next stage of its preemption
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum PreemptionStep {
+    /// Turn the arms which have green yellow and the crossings which walk to their clearance
+    Yellow,
+    /// Turn every arm red and give green to the movement after the clearances
+    Green(Movement),
+    /// Turn the arm of the movement yellow
+    Leave,
+    /// Continue the plan after the all-red clearance, the preemption is over
+    Continue,
+}
+
+/// A preemption an intersection runs, from the call of the emergency vehicle until the plan
+/// continues
+#[derive(Clone, Copy)]
+pub struct PreemptionRun {
+    stage: PreemptionStage,
+    /// The time in milliseconds the stage started
+    start: u64,
+    /// If the emergency vehicle cleared the intersection
+    cleared: bool,
+}
+
+impl PreemptionRun {
+    /// Returns a preemption called for the movement
+    ///
+    /// # Arguments
+    ///
+    /// * `movement` - the arm the vehicle comes from and its direction
+    pub const fn new(movement: Movement) -> PreemptionRun {
+        PreemptionRun {
+            stage: PreemptionStage::Called(movement),
+            start: 0,
+            cleared: false,
+        }
+    }
+
+    /// Records the emergency vehicle clearing the intersection
+    ///
+    /// Ignored until the movement has green, a clearance sensor firing before can't be the vehicle
+    /// which called it
+    pub fn clear(&mut self) {
+        if self.stage == PreemptionStage::Holding {
+            self.cleared = true;
+        }
+    }
+
+    /// Moves on to the next stage when the current one is over and returns what the intersection
+    /// does for it
+    ///
+    /// Has to be called while the intersection has no transition running
+    ///
+    /// # Arguments
+    ///
+    /// * `time` - the current time in milliseconds
+    /// * `yellow` - if an arm of the intersection shows yellow
+    pub fn step(&mut self, time: u64, yellow: bool) -> Option<PreemptionStep> {
+        let elapsed = time.saturating_sub(self.start);
+        let (stage, step) = match self.stage {
+            PreemptionStage::Called(movement) => {
+                (PreemptionStage::Yellow(movement), PreemptionStep::Yellow)
+            }
+            PreemptionStage::Yellow(movement) if elapsed >= YELLOW_TIME as u64 || !yellow => {
+                (PreemptionStage::Holding, PreemptionStep::Green(movement))
+            }
+            PreemptionStage::Holding if self.cleared => {
+                (PreemptionStage::Leaving, PreemptionStep::Leave)
+            }
+            PreemptionStage::Leaving if elapsed >= YELLOW_TIME as u64 => {
+                return Some(PreemptionStep::Continue)
+            }
+            _ => return None,
+        };
+        self.stage = stage;
+        self.start = time;
+        Some(step)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intersection::IntersectionActionDirection::Left;
+    use crate::intersection::UPPER_ARM;
+
+    const MOVEMENT: Movement = Movement {
+        arm: UPPER_ARM,
+        direction: Left,
+    };
+    const YELLOW: u64 = YELLOW_TIME as u64;
+
+    #[test]
+    fn sensors_report_once_to_the_intersection() {
+        let preemption = Preemption::new();
+        preemption.call(MOVEMENT);
+        preemption.clear();
+        assert!(preemption.take_call() == Some(MOVEMENT));
+        assert!(preemption.take_call().is_none());
+        assert!(preemption.take_cleared());
+        assert!(!preemption.take_cleared());
+    }
+
+    #[test]
+    fn movement_gets_green_after_the_yellow() {
+        let mut run = PreemptionRun::new(MOVEMENT);
+        assert!(run.step(1_000, true) == Some(PreemptionStep::Yellow));
+        assert!(run.step(1_000 + YELLOW - 1, true).is_none());
+        assert!(run.step(1_000 + YELLOW, true) == Some(PreemptionStep::Green(MOVEMENT)));
+
+        // without an arm showing yellow there is nothing to wait for
+        let mut run = PreemptionRun::new(MOVEMENT);
+        run.step(1_000, false);
+        assert!(run.step(1_000, false) == Some(PreemptionStep::Green(MOVEMENT)));
+    }
+
+    #[test]
+    fn clear_before_the_green_is_ignored() {
+        let mut run = PreemptionRun::new(MOVEMENT);
+        run.clear();
+        run.step(0, true);
+        run.clear();
+        run.step(YELLOW, true);
+        assert!(run.step(YELLOW + 10_000, true).is_none());
+
+        run.clear();
+        assert!(run.step(YELLOW + 10_010, false) == Some(PreemptionStep::Leave));
+        assert!(run.step(2 * YELLOW + 10_009, true).is_none());
+        let step = run.step(2 * YELLOW + 10_010, true);
+        assert!(step == Some(PreemptionStep::Continue));
+    }
+}
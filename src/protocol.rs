@@ -0,0 +1,376 @@
+//! Line based protocol between the controller and a host connected over serial
+//!
+//! The controller reports [`Event`]s as lines starting with `EV`, every other line it sends is
+//! log output as described in [`crate::log`]. The host sends one [`Command`] per line.
+
+use core::str::SplitWhitespace;
+
+use crate::intersection::IntersectionActionDirection::{self, *};
+use crate::intersection::IntersectionActionLight;
+use crate::intersection::IntersectionActionLight::*;
+use crate::log::{Level, Module};
+use crate::serial::Serial;
+use crate::turnout::TurnoutPosition;
+
+/// Maximum length of a command line in bytes
+pub const LINE_LENGTH: usize = 32;
+
+/// Something that happened on the controller
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// A sensor detected a car at the given time in milliseconds
+    Sensor { id: u8, time: u64 },
+    /// The input of a sensor changed at the given time in milliseconds
+    SensorEdge { id: u8, active: bool, time: u64 },
+    /// The number of cars inside a section changed
+    Section { id: u8, locks: i8 },
+    /// A stopper was locked or released
+    Stopper { id: u8, locked: bool },
+    /// An intersection arm shows a new light
+    Light {
+        intersection: u8,
+        arm: u8,
+        light: IntersectionActionLight,
+    },
+    /// The emergency stop was engaged or cleared
+    EmergencyStop { engaged: bool },
+    /// An error with the code of [`Error::code`](crate::error::Error::code) happened to the part
+    /// with the id
+    Fault { code: u8, id: u8 },
+}
+
+impl Event {
+    /// Writes the event as one line
+    ///
+    /// # Arguments
+    ///
+    /// * `serial` - the output to write the event to
+    pub fn write(&self, serial: &mut dyn Serial) {
+        let result = match *self {
+            Event::Sensor { id, time } => ufmt::uwriteln!(serial, "EV SENSOR {} {}", id, time),
+            Event::SensorEdge { id, active, time } => {
+                ufmt::uwriteln!(serial, "EV EDGE {} {} {}", id, active as u8, time)
+            }
+            Event::Section { id, locks } => ufmt::uwriteln!(serial, "EV SECTION {} {}", id, locks),
+            Event::Stopper { id, locked } => {
+                ufmt::uwriteln!(serial, "EV STOPPER {} {}", id, locked as u8)
+            }
+            Event::Light {
+                intersection,
+                arm,
+                light,
+            } => ufmt::uwriteln!(
+                serial,
+                "EV LIGHT {} {} {}",
+                intersection,
+                arm,
+                light_token(&light)
+            ),
+            Event::EmergencyStop { engaged } => ufmt::uwriteln!(serial, "EV ESTOP {}", engaged as u8),
+            Event::Fault { code, id } => ufmt::uwriteln!(serial, "EV FAULT {} {}", code, id),
+        };
+        result.unwrap();
+    }
+
+    /// Parses a line written by [`Event::write`]
+    ///
+    /// Returns `None` if the line is not an event
+    ///
+    /// # Arguments
+    ///
+    /// * `line` - the line without the line ending
+    pub fn parse(line: &str) -> Option<Event> {
+        let mut words = line.split_whitespace();
+        if words.next()? != "EV" {
+            return None;
+        }
+        let event = match words.next()? {
+            "SENSOR" => Event::Sensor {
+                id: parse_word(&mut words)?,
+                time: parse_word(&mut words)?,
+            },
+            "EDGE" => Event::SensorEdge {
+                id: parse_word(&mut words)?,
+                active: parse_flag(&mut words)?,
+                time: parse_word(&mut words)?,
+            },
+            "SECTION" => Event::Section {
+                id: parse_word(&mut words)?,
+                locks: parse_word(&mut words)?,
+            },
+            "STOPPER" => Event::Stopper {
+                id: parse_word(&mut words)?,
+                locked: parse_flag(&mut words)?,
+            },
+            "LIGHT" => Event::Light {
+                intersection: parse_word(&mut words)?,
+                arm: parse_word(&mut words)?,
+                light: parse_light(words.next()?)?,
+            },
+            "ESTOP" => Event::EmergencyStop {
+                engaged: parse_flag(&mut words)?,
+            },
+            "FAULT" => Event::Fault {
+                code: parse_word(&mut words)?,
+                id: parse_word(&mut words)?,
+            },
+            _ => return None,
+        };
+        end_of_line(words, event)
+    }
+}
+
+/// Instruction sent by the host
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// Locks the stopper regardless of its sections and intersections
+    Lock(u8),
+    /// Removes the lock set by [`Command::Lock`]
+    Release(u8),
+    /// Locks every stopper
+    EmergencyStop,
+    /// Clears the emergency stop and every lock set by [`Command::Lock`]
+    Resume,
+    /// Ends the current phase of the intersection
+    NextPhase(u8),
+    /// Switches the night mode of the intersection on or off
+    Night(u8, bool),
+    /// Gives green to the arm with the index of the intersection for an emergency vehicle going in
+    /// the direction
+    Preempt(u8, u8, IntersectionActionDirection),
+    /// Tells the emergency vehicle of the preemption of the intersection cleared it
+    ClearPreemption(u8),
+    /// Moves the turnout to the position once no car is on it
+    Turnout(u8, TurnoutPosition),
+    /// Sets the turnouts of the route
+    Route(u8),
+    /// Sets the log level of a module or of every module if `None`
+    Log(Option<Module>, Level),
+    /// Writes the event history
+    Dump,
+    /// Writes the crash record
+    Crash,
+    /// Removes the crash record
+    ClearCrash,
+}
+
+impl Command {
+    /// Writes the command as one line
+    ///
+    /// # Arguments
+    ///
+    /// * `serial` - the output to write the command to
+    pub fn write(&self, serial: &mut dyn Serial) {
+        let result = match *self {
+            Command::Lock(id) => ufmt::uwriteln!(serial, "LOCK {}", id),
+            Command::Release(id) => ufmt::uwriteln!(serial, "RELEASE {}", id),
+            Command::EmergencyStop => ufmt::uwriteln!(serial, "ESTOP"),
+            Command::Resume => ufmt::uwriteln!(serial, "RESUME"),
+            Command::NextPhase(id) => ufmt::uwriteln!(serial, "NEXT {}", id),
+            Command::Night(id, night) => {
+                let mode = match night {
+                    true => "ON",
+                    false => "OFF",
+                };
+                ufmt::uwriteln!(serial, "NIGHT {} {}", id, mode)
+            }
+            Command::Preempt(id, arm, direction) => {
+                ufmt::uwriteln!(serial, "PREEMPT {} {} {}", id, arm, light_token(&Green(direction)))
+            }
+            Command::ClearPreemption(id) => ufmt::uwriteln!(serial, "PREEMPT {} CLEAR", id),
+            Command::Turnout(id, position) => {
+                let position = match position {
+                    TurnoutPosition::Straight => "STRAIGHT",
+                    TurnoutPosition::Diverging => "DIVERGING",
+                };
+                ufmt::uwriteln!(serial, "TURNOUT {} {}", id, position)
+            }
+            Command::Route(id) => ufmt::uwriteln!(serial, "ROUTE {}", id),
+            Command::Log(module, level) => ufmt::uwriteln!(
+                serial,
+                "LOG {} {}",
+                module.map_or("ALL", |module| module.name()),
+                level.name()
+            ),
+            Command::Dump => ufmt::uwriteln!(serial, "DUMP"),
+            Command::Crash => ufmt::uwriteln!(serial, "CRASH"),
+            Command::ClearCrash => ufmt::uwriteln!(serial, "CRASH CLEAR"),
+        };
+        result.unwrap();
+    }
+
+    /// Parses a line written by [`Command::write`]
+    ///
+    /// Returns `None` if the line is not a valid command
+    ///
+    /// # Arguments
+    ///
+    /// * `line` - the line without the line ending
+    pub fn parse(line: &str) -> Option<Command> {
+        let mut words = line.split_whitespace();
+        let command = match words.next()? {
+            "LOCK" => Command::Lock(parse_word(&mut words)?),
+            "RELEASE" => Command::Release(parse_word(&mut words)?),
+            "ESTOP" => Command::EmergencyStop,
+            "RESUME" => Command::Resume,
+            "NEXT" => Command::NextPhase(parse_word(&mut words)?),
+            "NIGHT" => {
+                let id = parse_word(&mut words)?;
+                match words.next()? {
+                    "ON" => Command::Night(id, true),
+                    "OFF" => Command::Night(id, false),
+                    _ => return None,
+                }
+            }
+            "PREEMPT" => {
+                let id = parse_word(&mut words)?;
+                match words.next()? {
+                    "CLEAR" => Command::ClearPreemption(id),
+                    arm => match parse_light(words.next()?)? {
+                        Green(direction) => Command::Preempt(id, arm.parse().ok()?, direction),
+                        _ => return None,
+                    },
+                }
+            }
+            "TURNOUT" => {
+                let id = parse_word(&mut words)?;
+                match words.next()? {
+                    "STRAIGHT" => Command::Turnout(id, TurnoutPosition::Straight),
+                    "DIVERGING" => Command::Turnout(id, TurnoutPosition::Diverging),
+                    _ => return None,
+                }
+            }
+            "ROUTE" => Command::Route(parse_word(&mut words)?),
+            "LOG" => {
+                let module = match words.next()? {
+                    "ALL" => None,
+                    name => Some(Module::parse(name)?),
+                };
+                Command::Log(module, Level::parse(words.next()?)?)
+            }
+            "DUMP" => Command::Dump,
+            "CRASH" => match words.next() {
+                None => return Some(Command::Crash),
+                Some("CLEAR") => Command::ClearCrash,
+                Some(_) => return None,
+            },
+            _ => return None,
+        };
+        end_of_line(words, command)
+    }
+}
+
+/// Collects received bytes until a complete line arrived
+pub struct LineBuffer {
+    buffer: [u8; LINE_LENGTH],
+    length: usize,
+    /// If the current line didn't fit into the buffer
+    overflow: bool,
+}
+
+impl LineBuffer {
+    /// Returns an empty line buffer
+    pub const fn new() -> LineBuffer {
+        LineBuffer {
+            buffer: [0; LINE_LENGTH],
+            length: 0,
+            overflow: false,
+        }
+    }
+
+    /// Adds a received byte
+    ///
+    /// Returns the command when the byte completed a valid command line
+    ///
+    /// # Arguments
+    ///
+    /// * `byte` - the received byte
+    pub fn push(&mut self, byte: u8) -> Option<Command> {
+        self.push_line(byte).and_then(Command::parse)
+    }
+
+    /// Adds a byte of any line
+    ///
+    /// Returns the line without the line ending when the byte completed it, lines which didn't fit
+    /// into the buffer are dropped
+    ///
+    /// # Arguments
+    ///
+    /// * `byte` - the received byte
+    pub fn push_line(&mut self, byte: u8) -> Option<&str> {
+        match byte {
+            b'\r' | b'\n' => {
+                let complete = !self.overflow;
+                let length = self.length;
+                self.length = 0;
+                self.overflow = false;
+                match complete {
+                    true => core::str::from_utf8(&self.buffer[..length]).ok(),
+                    false => None,
+                }
+            }
+            _ => {
+                if self.length < LINE_LENGTH {
+                    self.buffer[self.length] = byte;
+                    self.length += 1;
+                } else {
+                    self.overflow = true;
+                }
+                None
+            }
+        }
+    }
+}
+
+impl Default for LineBuffer {
+    fn default() -> Self {
+        LineBuffer::new()
+    }
+}
+
+/// Returns the token used for a light on the wire
+pub fn light_token(light: &IntersectionActionLight) -> &'static str {
+    match light {
+        Green(Right) => "GR",
+        Green(Left) => "GL",
+        Yellow => "Y",
+        Red => "R",
+        RedYellow => "RY",
+        Off => "OFF",
+        FlashingYellow => "FY",
+    }
+}
+
+/// Parses the token used for a light on the wire
+pub fn parse_light(word: &str) -> Option<IntersectionActionLight> {
+    match word {
+        "GR" => Some(Green(Right)),
+        "GL" => Some(Green(Left)),
+        "Y" => Some(Yellow),
+        "R" => Some(Red),
+        "RY" => Some(RedYellow),
+        "OFF" => Some(Off),
+        "FY" => Some(FlashingYellow),
+        _ => None,
+    }
+}
+
+fn parse_word<T: core::str::FromStr>(words: &mut SplitWhitespace) -> Option<T> {
+    words.next()?.parse().ok()
+}
+
+fn parse_flag(words: &mut SplitWhitespace) -> Option<bool> {
+    match words.next()? {
+        "0" => Some(false),
+        "1" => Some(true),
+        _ => None,
+    }
+}
+
+/// Returns the parsed value only if no words are left on the line
+fn end_of_line<T>(mut words: SplitWhitespace, value: T) -> Option<T> {
+    match words.next() {
+        Some(_) => None,
+        None => Some(value),
+    }
+}
@@ -0,0 +1,137 @@
+use core::cell::RefCell;
+use embedded_hal::digital::v2::OutputPin;
+
+use crate::error::Result;
+use crate::protocol::{Command, Event, LineBuffer};
+use crate::serial::Serial;
+use crate::slots::Slots;
+use crate::stopper::Stopper;
+
+/// Maximum number of stoppers a remote reports
+pub const MAX_STOPPERS: usize = 16;
+
+/// Struct which executes the commands of a host and reports the stoppers to it
+///
+/// Has to be called regularily to report stopper changes
+pub struct Remote<'l, W>
+where
+    W: OutputPin,
+{
+    /// The stoppers the host can lock and release
+    stoppers: Slots<&'l RefCell<Stopper<W>>, MAX_STOPPERS>,
+    /// The output events are written to
+    serial: &'l RefCell<dyn Serial + 'l>,
+    /// The command line being received
+    line: LineBuffer,
+    /// The stopper states last reported to the host
+    reported_states: [Option<bool>; MAX_STOPPERS],
+    /// If the emergency stop is engaged
+    emergency_stop: bool,
+}
+
+impl<'l, W> Remote<'l, W>
+where
+    W: OutputPin,
+{
+    /// Returns a new remote for the given stoppers
+    ///
+    /// Fails with [`Error::Config`](crate::error::Error::Config) of id 0 when there are more than
+    /// MAX_STOPPERS stoppers
+    ///
+    /// # Arguments
+    ///
+    /// * `stoppers` - the stoppers the host can lock and release
+    /// * `serial` - the output events are written to
+    pub fn new(
+        stoppers: &[&'l RefCell<Stopper<W>>],
+        serial: &'l RefCell<dyn Serial + 'l>,
+    ) -> Result<Remote<'l, W>> {
+        let mut slots = Slots::new();
+        for stopper in stoppers {
+            slots.push(*stopper, 0)?;
+        }
+        Ok(Remote {
+            stoppers: slots,
+            serial,
+            line: LineBuffer::new(),
+            reported_states: [None; MAX_STOPPERS],
+            emergency_stop: false,
+        })
+    }
+
+    /// Handles a byte received from the host
+    ///
+    /// Commands for stoppers are executed right away, all other commands are returned so the
+    /// caller can pass them on. Fails when a stopper can't be written, the emergency stop still
+    /// tries every stopper.
+    ///
+    /// # Arguments
+    ///
+    /// * `byte` - the received byte
+    pub fn receive(&mut self, byte: u8) -> Result<Option<Command>> {
+        let command = match self.line.push(byte) {
+            Some(command) => command,
+            None => return Ok(None),
+        };
+        match command {
+            Command::Lock(id) => self.for_stopper(id, Stopper::manual_lock)?,
+            Command::Release(id) => self.for_stopper(id, Stopper::manual_release)?,
+            Command::EmergencyStop => {
+                let result = self.for_all_stoppers(Stopper::manual_lock);
+                self.set_emergency_stop(true);
+                result?;
+            }
+            Command::Resume => {
+                let result = self.for_all_stoppers(Stopper::manual_release);
+                self.set_emergency_stop(false);
+                result?;
+            }
+            command => return Ok(Some(command)),
+        }
+        Ok(None)
+    }
+
+    /// Returns if the emergency stop is engaged
+    pub fn emergency_stop(&self) -> bool {
+        self.emergency_stop
+    }
+
+    /// Reports every stopper whose state changed since the last call
+    pub fn call(&mut self) {
+        for (stopper, reported_state) in self.stoppers.iter().zip(&mut self.reported_states) {
+            let stopper = stopper.borrow();
+            let state = stopper.get_state();
+            if *reported_state != Some(state) {
+                *reported_state = Some(state);
+                Event::Stopper {
+                    id: stopper.get_id(),
+                    locked: state,
+                }
+                .write(&mut *self.serial.borrow_mut());
+            }
+        }
+    }
+
+    fn for_stopper(&self, id: u8, action: fn(&mut Stopper<W>) -> Result<()>) -> Result<()> {
+        for stopper in self.stoppers.iter() {
+            if stopper.borrow().get_id() == id {
+                action(&mut stopper.borrow_mut())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Calls the action for every stopper, returns the first error after trying all of them
+    fn for_all_stoppers(&self, action: fn(&mut Stopper<W>) -> Result<()>) -> Result<()> {
+        let mut result = Ok(());
+        for stopper in self.stoppers.iter() {
+            result = result.and(action(&mut stopper.borrow_mut()));
+        }
+        result
+    }
+
+    fn set_emergency_stop(&mut self, engaged: bool) {
+        self.emergency_stop = engaged;
+        Event::EmergencyStop { engaged }.write(&mut *self.serial.borrow_mut());
+    }
+}
@@ -0,0 +1,319 @@
+//! Roundabouts without lights
+//!
+//! The cars of a [`Roundabout`] yield to the circulating ones. Each [`RoundaboutEntry`] has an
+//! entry stopper and the section of the circle upstream of it, from which circulating cars pass
+//! the entry. The entry stopper is only released while that section is free and the last car left
+//! it at least the `gap` of the [`RoundaboutTiming`] ago.
+//!
+//! So that no entry waits forever for a gap in busy traffic, an entry which held a waiting car for
+//! `max_wait` starves: the entry upstream of it, whose cars drive into its circulating section, is
+//! held until the starving entry was released. If several entries starve, the one waiting longest
+//! goes first. An entry with an approach waits when a car arrived since it was last released,
+//! without an approach it always counts as waiting. The times are in milliseconds.
+
+use core::cell::RefCell;
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+
+use crate::actuation::Approach;
+use crate::error::{Error, Result};
+use crate::log::{Logger, Module};
+use crate::section::Section;
+use crate::stopper::Stopper;
+use crate::time::millis;
+
+/// Maximum number of entries of a roundabout
+pub const MAX_ENTRIES: usize = 6;
+
+/// An entry of a roundabout, see the module documentation
+pub struct RoundaboutEntry<'l, W, R>
+where
+    W: OutputPin,
+    R: InputPin,
+{
+    pub stopper: &'l RefCell<Stopper<W>>,
+    /// The section of the circle upstream of the entry
+    pub circulating: &'l RefCell<Section<'l, W, R>>,
+    /// The cars arriving at the entry, without an approach the entry always counts as waiting
+    pub approach: Option<&'l Approach>,
+}
+
+/// Settings of a roundabout, see the module documentation
+#[derive(Clone, Copy)]
+pub struct RoundaboutTiming {
+    /// Minimum milliseconds since the last car left the circulating section before an entry is
+    /// released
+    pub gap: u32,
+    /// Milliseconds an entry holds a waiting car before it starves
+    pub max_wait: u32,
+}
+
+impl Default for RoundaboutTiming {
+    fn default() -> Self {
+        RoundaboutTiming {
+            gap: 1_000,
+            max_wait: 20_000,
+        }
+    }
+}
+
+/// Structure to represent a roundabout, see the module documentation
+pub struct Roundabout<'l, W, R>
+where
+    W: OutputPin,
+    R: InputPin,
+{
+    /// The id of the roundabout
+    id: u8,
+    /// The entries in the direction the cars circulate, the first `entry_count` are set
+    entries: [Option<RoundaboutEntry<'l, W, R>>; MAX_ENTRIES],
+    entry_count: usize,
+    timing: RoundaboutTiming,
+    /// If every entry is released
+    released: [bool; MAX_ENTRIES],
+    /// The time in milliseconds every entry was last released
+    last_release: [u64; MAX_ENTRIES],
+    /// The starving entry whose upstream entry is held, if any
+    starving: Option<usize>,
+}
+
+impl<'l, W, R> Roundabout<'l, W, R>
+where
+    W: OutputPin,
+    R: InputPin,
+{
+    /// Returns a new roundabout with the entries released whose circulating section is free
+    ///
+    /// Fails with [`Error::Config`] when there are more than MAX_ENTRIES entries
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - the id of the roundabout
+    /// * `entries` - the entries in the direction the cars circulate
+    pub fn new(
+        id: u8,
+        entries: impl IntoIterator<Item = RoundaboutEntry<'l, W, R>>,
+    ) -> Result<Roundabout<'l, W, R>> {
+        let mut roundabout = Roundabout {
+            id,
+            entries: Default::default(),
+            entry_count: 0,
+            timing: RoundaboutTiming::default(),
+            released: [false; MAX_ENTRIES],
+            last_release: [0; MAX_ENTRIES],
+            starving: None,
+        };
+        for entry in entries {
+            if roundabout.entry_count == MAX_ENTRIES {
+                return Err(Error::Config(id));
+            }
+            roundabout.entries[roundabout.entry_count] = Some(entry);
+            roundabout.entry_count += 1;
+        }
+        let time = millis();
+        roundabout.starving = roundabout.starving_entry(time);
+        roundabout.release_entries(time)?;
+        Ok(roundabout)
+    }
+
+    /// Sets the gap and the waiting time after which an entry starves
+    ///
+    /// # Arguments
+    ///
+    /// * `timing` - the settings, see the module documentation
+    pub fn set_timing(&mut self, timing: RoundaboutTiming) {
+        self.timing = timing;
+    }
+
+    /// Returns the id of the roundabout
+    pub fn get_id(&self) -> u8 {
+        self.id
+    }
+
+    /// Releases the entries whose circulating section is free for the gap and locks the other ones
+    ///
+    /// Has to be called regularily, the stoppers are written every time, so a stopper which failed
+    /// is written again by the next call
+    ///
+    /// # Arguments
+    ///
+    /// * `log` - the logger the held entries are logged to
+    pub fn call(&mut self, log: &Logger) -> Result<()> {
+        let time = millis();
+        let starving = self.starving_entry(time);
+        if starving != self.starving {
+            if let Some(starving) = starving {
+                // the entry upstream of the starving one is held
+                let (id, held) = (self.id, (starving + self.entry_count - 1) % self.entry_count);
+                crate::info!(log, Module::Roundabout, "roundabout {} holds entry {}", id, held);
+            }
+            self.starving = starving;
+        }
+        self.release_entries(time)
+    }
+
+    /// Returns the entry which starved the longest, if any
+    fn starving_entry(&self, time: u64) -> Option<usize> {
+        let count = self.entry_count;
+        let mut starving: Option<(usize, u64)> = None;
+        for (index, entry) in self.entries[..count].iter().flatten().enumerate() {
+            let last_release = self.last_release[index];
+            let waiting = match entry.approach.map(Approach::last_arrival) {
+                Some(Some(arrival)) => arrival > last_release,
+                Some(None) => false,
+                None => true,
+            };
+            let waited = time.saturating_sub(last_release);
+            let starved = !self.released[index] && waiting && waited >= self.timing.max_wait as u64;
+            let longest = match starving {
+                Some((_, longest)) => waited > longest,
+                None => true,
+            };
+            if starved && longest {
+                starving = Some((index, waited));
+            }
+        }
+        starving.map(|(index, _)| index)
+    }
+
+    /// Releases the entries whose circulating section is free for the gap unless the starving
+    /// entry holds them and locks the other ones
+    fn release_entries(&mut self, time: u64) -> Result<()> {
+        let count = self.entry_count;
+        for (index, entry) in self.entries[..count].iter().flatten().enumerate() {
+            let free = {
+                let section = entry.circulating.borrow();
+                let gap = match section.last_exit() {
+                    Some(last_exit) => time.saturating_sub(last_exit) >= self.timing.gap as u64,
+                    None => true,
+                };
+                !section.occupied() && gap
+            };
+            let held = matches!(self.starving, Some(starving) if (index + 1) % count == starving);
+            let released = free && !held;
+            self.released[index] = released;
+            let mut stopper = entry.stopper.borrow_mut();
+            match released {
+                true => {
+                    self.last_release[index] = time;
+                    stopper.intersection_release()?;
+                }
+                false => stopper.intersection_lock()?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pin_mockup::Pin;
+    use crate::serial::Serial;
+    use crate::time::{set_millis, ClockGuard};
+
+    const TIMING: RoundaboutTiming = RoundaboutTiming {
+        gap: 1_000,
+        max_wait: 20_000,
+    };
+
+    struct Discard;
+
+    impl Serial for Discard {
+        fn write_str(&mut self, _: &str) {}
+    }
+
+    type TestRoundabout<'l> = Roundabout<'l, Pin<'l>, Pin<'l>>;
+    type TestSection<'l> = RefCell<Section<'l, Pin<'l>, Pin<'l>>>;
+
+    /// Builds a roundabout with three entries without approaches on mock pins and passes it with
+    /// its entry stoppers and circulating sections to `f`
+    fn roundabout(
+        f: impl for<'l> FnOnce(
+            &mut TestRoundabout<'l>,
+            &'l [RefCell<Stopper<Pin<'l>>>],
+            &'l [TestSection<'l>],
+        ),
+    ) {
+        let serial = RefCell::new(Discard);
+        let levels: [RefCell<bool>; 3] = Default::default();
+        let stoppers: Vec<_> = (0..3)
+            .map(|index| Stopper::new(Pin::new(&levels[index]), index as u8).unwrap())
+            .map(RefCell::new)
+            .collect();
+        let sections: Vec<_> =
+            (0..3).map(|index| RefCell::new(Section::new(index, &serial))).collect();
+        let entries = (0..3).map(|index| RoundaboutEntry {
+            stopper: &stoppers[index],
+            circulating: &sections[index],
+            approach: None,
+        });
+        let mut roundabout = Roundabout::new(1, entries).unwrap();
+        roundabout.set_timing(TIMING);
+        f(&mut roundabout, &stoppers, &sections);
+    }
+
+    fn call(roundabout: &mut TestRoundabout, time: u64) {
+        set_millis(time);
+        roundabout.call(&Logger::new()).unwrap();
+    }
+
+    fn locked(stoppers: &[RefCell<Stopper<Pin>>]) -> Vec<bool> {
+        stoppers.iter().map(|stopper| stopper.borrow().get_state()).collect()
+    }
+
+    #[test]
+    fn entry_is_released_the_gap_after_its_circulating_section_cleared() {
+        let _clock = ClockGuard::take();
+        roundabout(|roundabout, stoppers, sections| {
+            assert_eq!(locked(stoppers), [false, false, false]);
+            sections[1].borrow_mut().start_sensor_callback().unwrap();
+            call(roundabout, 100);
+            assert_eq!(locked(stoppers), [false, true, false]);
+
+            set_millis(2_000);
+            sections[1].borrow_mut().end_sensor_callback().unwrap();
+            call(roundabout, 2_000 + TIMING.gap as u64 - 1);
+            assert_eq!(locked(stoppers), [false, true, false]);
+            call(roundabout, 2_000 + TIMING.gap as u64);
+            assert_eq!(locked(stoppers), [false, false, false]);
+        });
+    }
+
+    #[test]
+    fn starving_entry_holds_the_entry_upstream_until_it_was_released() {
+        let _clock = ClockGuard::take();
+        roundabout(|roundabout, stoppers, sections| {
+            sections[0].borrow_mut().start_sensor_callback().unwrap();
+            call(roundabout, 100);
+            let max_wait = TIMING.max_wait as u64;
+            call(roundabout, max_wait - 1);
+            assert_eq!(locked(stoppers), [true, false, false]);
+
+            // the cars of the last entry drive into the circulating section of the first one
+            call(roundabout, max_wait);
+            assert_eq!(locked(stoppers), [true, false, true]);
+
+            set_millis(21_000);
+            sections[0].borrow_mut().end_sensor_callback().unwrap();
+            call(roundabout, 21_000 + TIMING.gap as u64);
+            assert_eq!(locked(stoppers), [false, false, true]);
+            call(roundabout, 21_010 + TIMING.gap as u64);
+            assert_eq!(locked(stoppers), [false, false, false]);
+        });
+    }
+
+    #[test]
+    fn entry_past_the_maximum_is_a_config_error() {
+        let serial = RefCell::new(Discard);
+        let level = RefCell::new(false);
+        let stopper = RefCell::new(Stopper::new(Pin::new(&level), 1).unwrap());
+        let section = RefCell::new(Section::new(1, &serial));
+        let entries = (0..=MAX_ENTRIES).map(|_| RoundaboutEntry {
+            stopper: &stopper,
+            circulating: &section,
+            approach: None,
+        });
+        let roundabout: Result<TestRoundabout> = Roundabout::new(2, entries);
+        assert!(roundabout.err() == Some(Error::Config(2)));
+    }
+}
@@ -0,0 +1,90 @@
+//! Schedules of the states of intersections
+//!
+//! An intersection runs the states its [`Schedule`] gives it, starting with the current one when
+//! it is created and asking for the next one whenever a state is over. Besides going round, a
+//! schedule can be reset to a phase, skip phases and run an inserted phase, e.g. for a preemption
+//! by an emergency vehicle, before it continues with the phase after the one it interrupted.
+//! [`crate::plan::PhasePlan`] is the schedule of a fixed list of phases.
+//!
+//! Jumping around the phases doesn't go through yellow and red-yellow lights by itself, the caller
+//! has to make sure the lights may switch to the new phase.
+
+use crate::intersection::IntersectionState;
+use crate::monitor::ConflictMatrix;
+use crate::plan::PlanError;
+
+/// The states an intersection runs through, see the module documentation
+pub trait Schedule {
+    /// Moves on to the next phase and returns it
+    ///
+    /// A phase inserted with [`Schedule::preempt`] comes first, after it the schedule continues
+    /// with the phase after the interrupted one.
+    fn next(&mut self) -> &IntersectionState;
+
+    /// Returns the current phase
+    fn current(&self) -> &IntersectionState;
+
+    /// Returns the index of the current phase, of the interrupted one while an inserted phase runs
+    fn index(&self) -> usize;
+
+    /// Returns the number of phases
+    fn len(&self) -> usize;
+
+    /// Returns if the schedule has no phases
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Checks that the schedule has phases and may run on an intersection, an intersection
+    /// refuses a schedule which fails
+    ///
+    /// # Arguments
+    ///
+    /// * `arms` - the number of arms of the intersection
+    /// * `conflicts` - the movements of the intersection which mustn't have green together
+    fn validate(&self, arms: usize, conflicts: &ConflictMatrix) -> Result<(), PlanError>;
+
+    /// Makes the phase with the index the current one and drops an inserted phase
+    ///
+    /// An index past the last phase makes the last phase the current one
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - the index of the phase
+    fn reset(&mut self, index: usize);
+
+    /// Moves on past the given number of phases and returns the phase after them
+    ///
+    /// Only returns the current phase when the schedule has no phases
+    ///
+    /// # Arguments
+    ///
+    /// * `phases` - the number of phases to skip
+    fn skip(&mut self, phases: usize) -> &IntersectionState {
+        if !self.is_empty() {
+            self.reset((self.index() + phases + 1) % self.len());
+        }
+        self.current()
+    }
+
+    /// Inserts a phase, which the next call of [`Schedule::next`] returns
+    ///
+    /// A phase inserted before replaces the one not yet returned.
+    ///
+    /// # Arguments
+    ///
+    /// * `state` - the lights of the phase and the milliseconds it lasts
+    fn preempt(&mut self, state: IntersectionState);
+
+    /// Returns if the current phase was inserted with [`Schedule::preempt`]
+    fn preempted(&self) -> bool;
+
+    /// Returns the milliseconds left of the current phase by its duration
+    ///
+    /// # Arguments
+    ///
+    /// * `elapsed` - the milliseconds since the phase started
+    fn remaining(&self, elapsed: u64) -> u32 {
+        (self.current().duration() as u64).saturating_sub(elapsed) as u32
+    }
+}
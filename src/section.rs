@@ -2,33 +2,31 @@ use core::cell::RefCell;
 use core::default::Default;
 use core::option::Option;
 use core::option::Option::*;
-use core::panic;
-use arduino_hal::hal::Usart;
-use arduino_hal::clock::MHz16;
-use arduino_hal::hal::port::PE0;
-use arduino_hal::hal::port::PE1;
-use arduino_hal::pac::USART0;
-use arduino_hal::port::Pin;
-use arduino_hal::port::mode::Input;
-use arduino_hal::port::mode::Output;
 use embedded_hal::digital::v2::InputPin;
 use embedded_hal::digital::v2::OutputPin;
 
+use crate::error::Result;
+use crate::protocol::Event;
 use crate::sensor::SensorEnum::*;
 use crate::sensor::*;
+use crate::serial::Serial;
+use crate::slots::Slots;
 use crate::stopper::*;
+use crate::time::millis;
 
 pub struct Section<'l, W: 'l, R: 'l>
 where
     R: InputPin,
     W: OutputPin,
 {
-    serial: &'l RefCell<Usart<USART0, Pin<Input, PE0>, Pin<Output, PE1>, MHz16>>,
+    serial: &'l RefCell<dyn Serial + 'l>,
     id: u8,
     locks: i8,
-    start_sensors: [Option<&'l RefCell<Sensor<'l, W, R>>>; 2],
-    end_sensors: [Option<&'l RefCell<Sensor<'l, W, R>>>; 2],
-    stoppers: [Option<&'l RefCell<Stopper<W>>>; 2],
+    /// The time in milliseconds the last car left the section
+    last_exit: Option<u64>,
+    start_sensors: Slots<&'l RefCell<Sensor<'l, W, R>>, 2>,
+    end_sensors: Slots<&'l RefCell<Sensor<'l, W, R>>, 2>,
+    stoppers: Slots<&'l RefCell<Stopper<W>>, 2>,
     self_reference: Option<&'l RefCell<Section<'l, W, R>>>,
 }
 
@@ -37,11 +35,12 @@ where
     W: OutputPin,
     R: InputPin,
 {
-    pub fn new(id: u8, serial: &'l RefCell<Usart<USART0, Pin<Input, PE0>, Pin<Output, PE1>, MHz16>>) -> Self {
+    pub fn new(id: u8, serial: &'l RefCell<dyn Serial + 'l>) -> Self {
         Section {
             serial,
             id,
             locks: 0,
+            last_exit: None,
             start_sensors: Default::default(),
             end_sensors: Default::default(),
             stoppers: Default::default(),
@@ -53,63 +52,84 @@ where
         self.self_reference = Some(self_reference);
     }
 
-    pub fn start_sensor_callback(&mut self) {
-        ufmt::uwriteln!(&mut self.serial.borrow_mut(), "section {} start sensor detected", self.id).unwrap();
+    pub fn start_sensor_callback(&mut self) -> Result<()> {
         self.locks += 1;
-        for stopper in &self.stoppers {
-            match stopper {
-                Some(stopper) => stopper.borrow_mut().lock(),
-                None => return,
-            }
+        self.report();
+        for stopper in self.stoppers.iter() {
+            stopper.borrow_mut().lock()?;
         }
+        Ok(())
     }
 
-    pub fn end_sensor_callback(&mut self) {
-        ufmt::uwriteln!(&mut self.serial.borrow_mut(), "section {} end sensor detected", self.id).unwrap();
+    pub fn end_sensor_callback(&mut self) -> Result<()> {
         self.locks -= 1;
-        for stopper in &self.stoppers {
-            match stopper {
-                Some(stopper) => stopper.borrow_mut().release(),
-                None => return,
-            }
+        self.last_exit = Some(millis());
+        self.report();
+        for stopper in self.stoppers.iter() {
+            stopper.borrow_mut().release()?;
         }
+        Ok(())
     }
 
-    pub fn add_sensor(&mut self, sensor: SensorEnum<'l, W, R>) {
-        if let Some(self_reference) = self.self_reference {
-            match sensor {
-                StartSensor(sensor) => {
-                    sensor.borrow_mut().add_start_owner(self_reference);
-                    for sensor_loop in &mut self.start_sensors {
-                        if sensor_loop.is_none() {
-                            *sensor_loop = Some(sensor);
-                            return;
-                        }
-                    }
-                }
-                EndSensor(sensor) => {
-                    sensor.borrow_mut().add_end_owner(self_reference);
-                    for sensor_loop in &mut self.end_sensors {
-                        if sensor_loop.is_none() {
-                            *sensor_loop = Some(sensor);
-                            return;
-                        }
-                    }
-                }
-            }
-            panic!("no more than 2 start or end sensors allowed");
-        } else {
-            panic!("no self reference set");
+    /// Locks the stoppers of the section with a fault lock, which the host can't release and
+    /// which lasts until the board is reset
+    ///
+    /// Used when a sensor of the section failed and the cars in it can't be counted anymore
+    pub fn lock_stoppers(&mut self) -> Result<()> {
+        for stopper in self.stoppers.iter() {
+            stopper.borrow_mut().fault_lock()?;
         }
+        Ok(())
+    }
+
+    /// Returns if a car is in the section
+    pub fn occupied(&self) -> bool {
+        self.locks > 0
+    }
+
+    /// Returns the time in milliseconds the last car left the section
+    pub fn last_exit(&self) -> Option<u64> {
+        self.last_exit
     }
 
-    pub fn add_stopper(&mut self, stopper: &'l RefCell<Stopper<W>>) {
-        for stopper_loop in &mut self.stoppers {
-            if stopper_loop.is_none() {
-                *stopper_loop = Some(stopper);
-                return;
+    /// Reports the number of cars in the section to the host
+    fn report(&self) {
+        Event::Section {
+            id: self.id,
+            locks: self.locks,
+        }
+        .write(&mut *self.serial.borrow_mut());
+    }
+
+    /// Adds a sensor cars enter or leave the section by
+    ///
+    /// Fails with [`Error::Config`](crate::error::Error::Config) of the section when it already
+    /// has two sensors of the kind or of the sensor when it already starts or ends two sections
+    ///
+    /// # Panic
+    /// Panics when the self reference isn't set
+    pub fn add_sensor(&mut self, sensor: SensorEnum<'l, W, R>) -> Result<()> {
+        let self_reference = match self.self_reference {
+            Some(self_reference) => self_reference,
+            None => panic!("no self reference set"),
+        };
+        match sensor {
+            StartSensor(sensor) => {
+                self.start_sensors.push(sensor, self.id)?;
+                sensor.borrow_mut().add_start_owner(self_reference)
+            }
+            EndSensor(sensor) => {
+                self.end_sensors.push(sensor, self.id)?;
+                sensor.borrow_mut().add_end_owner(self_reference)
             }
         }
-        panic!("no more than two stoppers allowed");
+    }
+
+    /// Adds a stopper which is locked while a car is in the section
+    ///
+    /// Fails with [`Error::Config`](crate::error::Error::Config) when the section already has two
+    /// stoppers
+    pub fn add_stopper(&mut self, stopper: &'l RefCell<Stopper<W>>) -> Result<()> {
+        self.stoppers.push(stopper, self.id)
     }
 }
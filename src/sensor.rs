@@ -1,10 +1,17 @@
-use crate::{section::*, time::millis};
+use crate::actuation::Approach;
+use crate::clearance::Occupancy;
+use crate::error::{Error, Result};
+use crate::level_crossing::Track;
+use crate::log::{Logger, Module};
+use crate::monitor::Movement;
+use crate::pedestrian::Demand;
+use crate::preemption::Preemption;
+use crate::slots::Slots;
+use crate::{protocol::Event, section::*, serial::Serial, time::millis};
 use core::cell::RefCell;
 use core::default::Default;
 use core::option::Option;
 use core::option::Option::*;
-use core::panic;
-use arduino_hal::{hal::{Usart, port::{PE0, PE1}}, pac::USART0, port::{Pin, mode::{Input, Output}}, clock::MHz16};
 use embedded_hal::digital::v2::{InputPin, OutputPin};
 
 pub const SENSOR_ACTIVE: bool = false;
@@ -27,8 +34,21 @@ where
     pin: R,
     last_state: bool,
     last_time: u64,
-    start_section_owners: [Option<&'l RefCell<Section<'l, W, R>>>; 2],
-    end_section_owners: [Option<&'l RefCell<Section<'l, W, R>>>; 2],
+    /// If the sensor failed and is not polled anymore
+    degraded: bool,
+    start_section_owners: Slots<&'l RefCell<Section<'l, W, R>>, 2>,
+    end_section_owners: Slots<&'l RefCell<Section<'l, W, R>>, 2>,
+    /// The approaches of intersection arms the sensor reports arriving cars to
+    approaches: Slots<&'l Approach, 2>,
+    /// The boxes of intersections the sensor tells the occupancy of
+    boxes: Slots<&'l Occupancy, 2>,
+    /// The pedestrian crossings the sensor, a push button, requests walk for
+    requests: Slots<&'l Demand, 2>,
+    /// The preemptions of intersections the sensor calls green for the movement or, without one,
+    /// tells an emergency vehicle cleared the intersection
+    preemptions: Slots<(&'l Preemption, Option<Movement>), 2>,
+    /// The tracks of level crossings the sensor reports trains approaching or, if true, leaving to
+    tracks: Slots<(&'l Track, bool), 2>,
 }
 
 impl<'l, W, R> Sensor<'l, W, R>
@@ -36,65 +56,164 @@ where
     W: OutputPin,
     R: InputPin,
 {
-    pub fn get_state(&self) -> bool {
-        match self.pin.is_high() {
-            Ok(state) => state,
-            Err(_) => panic!("read failed"),
-        }
+    pub fn get_state(&self) -> Result<bool> {
+        self.pin.is_high().map_err(|_| Error::SensorPin(self.id))
     }
 
-    pub fn add_start_owner(&mut self, section: &'l RefCell<Section<'l, W, R>>) {
-        for option in &mut self.start_section_owners {
-            if option.is_none() {
-                *option = Some(section);
-                return;
-            }
-        }
-        panic!("no more than two start owners possible");
+    /// Returns the id of the sensor
+    pub fn get_id(&self) -> u8 {
+        self.id
     }
 
-    pub fn add_end_owner(&mut self, section: &'l RefCell<Section<'l, W, R>>) {
-        for option in &mut self.end_section_owners {
-            if option.is_none() {
-                *option = Some(section);
-                return;
+    /// Stops polling the sensor and locks the stoppers of the sections it belongs to
+    ///
+    /// Without the sensor the cars in these sections can't be counted anymore, so their stoppers
+    /// get a fault lock, which the host can't release, and stay locked until the board is reset.
+    /// The boxes the sensor is in count as occupied from then on, so their intersections don't
+    /// start conflicting greens anymore
+    pub fn degrade(&mut self) -> Result<()> {
+        self.degraded = true;
+        if self.last_state != SENSOR_ACTIVE {
+            for occupancy in self.boxes.iter() {
+                occupancy.enter();
             }
         }
-        panic!("no more than two end owners possible");
+        let owners = self.start_section_owners.iter().chain(self.end_section_owners.iter());
+        for section in owners {
+            section.borrow_mut().lock_stoppers()?;
+        }
+        Ok(())
+    }
+
+    /// Reports the cars detected by the sensor as entering the section
+    ///
+    /// Fails with [`Error::Config`] when the sensor already starts two sections
+    pub fn add_start_owner(&mut self, section: &'l RefCell<Section<'l, W, R>>) -> Result<()> {
+        self.start_section_owners.push(section, self.id)
+    }
+
+    /// Reports the cars detected by the sensor as leaving the section
+    ///
+    /// Fails with [`Error::Config`] when the sensor already ends two sections
+    pub fn add_end_owner(&mut self, section: &'l RefCell<Section<'l, W, R>>) -> Result<()> {
+        self.end_section_owners.push(section, self.id)
+    }
+
+    /// Reports the cars detected by the sensor to the approach of an intersection arm
+    ///
+    /// Fails with [`Error::Config`] when the sensor already has two approaches
+    pub fn add_approach(&mut self, approach: &'l Approach) -> Result<()> {
+        self.approaches.push(approach, self.id)
+    }
+
+    /// Reports the cars detected by the sensor to the occupancy of an intersection box
+    ///
+    /// Fails with [`Error::Config`] when the sensor is already in two boxes
+    pub fn add_box(&mut self, occupancy: &'l Occupancy) -> Result<()> {
+        self.boxes.push(occupancy, self.id)
+    }
+
+    /// Requests walk for a pedestrian crossing whenever the sensor, a push button, detects
+    ///
+    /// Fails with [`Error::Config`] when the sensor already requests walk for two crossings
+    pub fn add_request(&mut self, demand: &'l Demand) -> Result<()> {
+        self.requests.push(demand, self.id)
+    }
+
+    /// Calls green for the movement of an emergency vehicle whenever the sensor detects
+    ///
+    /// Fails with [`Error::Config`] when the sensor already has two preemptions
+    pub fn add_preemption_call(
+        &mut self,
+        preemption: &'l Preemption,
+        movement: Movement,
+    ) -> Result<()> {
+        self.preemptions.push((preemption, Some(movement)), self.id)
+    }
+
+    /// Tells an emergency vehicle cleared the intersection whenever the sensor detects
+    ///
+    /// Fails with [`Error::Config`] when the sensor already has two preemptions
+    pub fn add_preemption_clear(&mut self, preemption: &'l Preemption) -> Result<()> {
+        self.preemptions.push((preemption, None), self.id)
     }
 
-    pub fn check_pin_change(&mut self, serial: Option<&RefCell<Usart<USART0, Pin<Input, PE0>, Pin<Output, PE1>, MHz16>>>) {
-        let state = self.get_state();
+    /// Reports a train approaching a level crossing whenever the sensor detects
+    ///
+    /// Fails with [`Error::Config`] when the sensor already has two tracks
+    pub fn add_track_approach(&mut self, track: &'l Track) -> Result<()> {
+        self.tracks.push((track, false), self.id)
+    }
+
+    /// Reports a train leaving a level crossing whenever the sensor detects
+    ///
+    /// Fails with [`Error::Config`] when the sensor already has two tracks
+    pub fn add_track_exit(&mut self, track: &'l Track) -> Result<()> {
+        self.tracks.push((track, true), self.id)
+    }
+
+    pub fn check_pin_change(
+        &mut self,
+        serial: Option<&RefCell<dyn Serial + '_>>,
+        log: &Logger,
+    ) -> Result<()> {
+        if self.degraded {
+            return Ok(());
+        }
+        let state = self.get_state()?;
         let time = millis();
-        #[cfg(debug_assertions)]
-        {
+        if state != self.last_state {
             if let Some(serial) = serial {
-                ufmt::uwriteln!(&mut serial.borrow_mut(), "state: {}, last_state: {}, time: {}, last_time: {}", state, self.last_state, time, self.last_time).unwrap();
+                Event::SensorEdge {
+                    id: self.id,
+                    active: state == SENSOR_ACTIVE,
+                    time,
+                }
+                .write(&mut *serial.borrow_mut());
+            }
+            for occupancy in self.boxes.iter() {
+                match state {
+                    SENSOR_ACTIVE => occupancy.enter(),
+                    _ => occupancy.leave(),
+                }
             }
         }
+        crate::trace!(log, Module::Sensor, "sensor {} state: {}, last_state: {}, last_time: {}", self.id, state, self.last_state, self.last_time);
         if state == SENSOR_ACTIVE && time - self.last_time >= 1_000 {//&& state != self.last_state {
-            #[cfg(debug_assertions)]
-            {
-                if let Some(serial) = serial {
-                    ufmt::uwriteln!(&mut serial.borrow_mut(), "sensor {} detected; time: {}; last_time: {}; state: {}, last_state: {}", self.id, time, self.last_time, state, self.last_state).unwrap();
-                }
+            crate::debug!(log, Module::Sensor, "sensor {} detected; last_time: {}; state: {}, last_state: {}", self.id, self.last_time, state, self.last_state);
+            if let Some(serial) = serial {
+                Event::Sensor { id: self.id, time }.write(&mut *serial.borrow_mut());
             }
-            for start_section_owner in self.start_section_owners {
-                match start_section_owner {
-                    Some(start_section) => start_section.borrow_mut().start_sensor_callback(),
-                    None => break,
-                }
+            for start_section in self.start_section_owners.iter() {
+                start_section.borrow_mut().start_sensor_callback()?;
+            }
+
+            for end_section in self.end_section_owners.iter() {
+                end_section.borrow_mut().end_sensor_callback()?;
             }
 
-            for end_section_owner in self.end_section_owners {
-                match end_section_owner {
-                    Some(end_section) => end_section.borrow_mut().end_sensor_callback(),
-                    None => break,
+            for approach in self.approaches.iter() {
+                approach.arrive(time);
+            }
+            for demand in self.requests.iter() {
+                demand.request();
+            }
+            for (preemption, movement) in self.preemptions.iter() {
+                match movement {
+                    Some(movement) => preemption.call(*movement),
+                    None => preemption.clear(),
+                }
+            }
+            for (track, exit) in self.tracks.iter() {
+                match exit {
+                    true => track.exit(time),
+                    false => track.approach(time),
                 }
             }
             self.last_time = time;
         }
         self.last_state = state;
+        Ok(())
     }
 
     pub fn new(pin: R, id: u8) -> Sensor<'l, W, R> {
@@ -103,8 +222,14 @@ where
             pin,
             last_state: !SENSOR_ACTIVE,
             last_time: 0,
+            degraded: false,
             start_section_owners: Default::default(),
             end_section_owners: Default::default(),
+            approaches: Default::default(),
+            boxes: Default::default(),
+            requests: Default::default(),
+            preemptions: Default::default(),
+            tracks: Default::default(),
         }
     }
 }
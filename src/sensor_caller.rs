@@ -1,39 +1,62 @@
 // dependency imports
-use arduino_hal::{port::{
-    mode::{Input, Output},
-    Pin,
-}, hal::{Atmega, port::{PE0, PE1}, Usart}, pac::USART0, clock::MHz16};
 use core::cell::RefCell;
+use embedded_hal::digital::v2::{InputPin, OutputPin};
 
 // crate imports
-use crate::{sensor::Sensor, time::millis};
+use crate::{error::Result, log::Logger, sensor::Sensor, serial::Serial};
 
 /// Struct which calls multiple sensors but has to be called itself regularily
-pub struct SensorCaller<'l> {
+pub struct SensorCaller<'l, W, R>
+where
+    W: OutputPin,
+    R: InputPin,
+{
     /// array slice of the sensors it should call
-    sensors: &'l [&'l RefCell<Sensor<'l, Pin<Output>, Pin<Input>>>],
-    last_time: u64,
+    sensors: &'l [&'l RefCell<Sensor<'l, W, R>>],
 }
 
-impl<'l> SensorCaller<'l> {
+impl<'l, W, R> SensorCaller<'l, W, R>
+where
+    W: OutputPin,
+    R: InputPin,
+{
     /// Returns a new SensorCaller with the given sensors
     ///
     /// # Arguments
     ///
     /// * `sensors` - array slice of the sensors it should call
-    pub fn new(
-        sensors: &'l [&'l RefCell<Sensor<'l, Pin<Output>, Pin<Input>>>],
-    ) -> SensorCaller<'l> {
-        SensorCaller { sensors, last_time: 0 }
+    pub fn new(sensors: &'l [&'l RefCell<Sensor<'l, W, R>>]) -> SensorCaller<'l, W, R> {
+        SensorCaller { sensors }
     }
 
     /// Calls all the sensors
-    pub fn call(&self, serial: Option<&RefCell<Usart<USART0, Pin<Input, PE0>, Pin<Output, PE1>, MHz16>>>) {
-        let time = millis();
-        let debug = time - self.last_time >= 100;
-        let debug = false;
+    ///
+    /// A failing sensor doesn't keep the others from being called, the first error is returned
+    ///
+    /// # Arguments
+    ///
+    /// * `serial` - the output the events of the sensors are written to
+    /// * `log` - the logger the sensors write their messages to
+    pub fn call(&self, serial: Option<&RefCell<dyn Serial + '_>>, log: &Logger) -> Result<()> {
+        let mut result = Ok(());
+        for sensor in self.sensors {
+            let sensor_result = sensor.borrow_mut().check_pin_change(serial, log);
+            result = result.and(sensor_result);
+        }
+        result
+    }
+
+    /// Takes the sensor with the id out of service, see [`Sensor::degrade`]
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - the id of the failed sensor
+    pub fn degrade(&self, id: u8) -> Result<()> {
         for sensor in self.sensors {
-            sensor.borrow_mut().check_pin_change(serial);
+            if sensor.borrow().get_id() == id {
+                sensor.borrow_mut().degrade()?;
+            }
         }
+        Ok(())
     }
 }
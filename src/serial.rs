@@ -0,0 +1,63 @@
+use core::cell::RefCell;
+use core::convert::Infallible;
+
+/// Output channel of the controller
+///
+/// This is the USART on the board and a pseudo-terminal when the logic runs on the host. Every
+/// `ufmt::uWrite` is a `Serial` and `dyn Serial` can be written to with `ufmt::uwriteln!`.
+pub trait Serial {
+    /// Writes the string to the output
+    ///
+    /// # Arguments
+    ///
+    /// * `s` - the string to write
+    fn write_str(&mut self, s: &str);
+}
+
+impl<U> Serial for U
+where
+    U: ufmt::uWrite,
+{
+    fn write_str(&mut self, s: &str) {
+        // there is nobody to tell when the output itself fails
+        let _ = ufmt::uWrite::write_str(self, s);
+    }
+}
+
+impl ufmt::uWrite for dyn Serial + '_ {
+    type Error = Infallible;
+
+    fn write_str(&mut self, s: &str) -> Result<(), Self::Error> {
+        Serial::write_str(self, s);
+        Ok(())
+    }
+}
+
+/// Serial which writes everything to two outputs
+///
+/// Used to keep a copy of the output, e.g. in the [`History`](crate::history::History).
+pub struct Tee<'l> {
+    outputs: [&'l RefCell<dyn Serial + 'l>; 2],
+}
+
+impl<'l> Tee<'l> {
+    /// Returns a serial writing to both outputs
+    ///
+    /// # Arguments
+    ///
+    /// * `first` - the output which is written to first
+    /// * `second` - the output which is written to second
+    pub fn new(first: &'l RefCell<dyn Serial + 'l>, second: &'l RefCell<dyn Serial + 'l>) -> Tee<'l> {
+        Tee {
+            outputs: [first, second],
+        }
+    }
+}
+
+impl Serial for Tee<'_> {
+    fn write_str(&mut self, s: &str) {
+        for output in self.outputs {
+            output.borrow_mut().write_str(s);
+        }
+    }
+}
@@ -2,6 +2,7 @@ use core::cell::RefCell;
 
 use embedded_hal::{blocking::i2c::SevenBitAddress, digital::v2::OutputPin};
 
+use crate::error::{Error, Result};
 use crate::intersection::IntersectionActionDirection;
 
 use embedded_hal::blocking::i2c;
@@ -36,25 +37,27 @@ where
     /// # Arguments
     ///
     /// * `direction` - The direction to set the servo in the next pulse to
-    pub fn set_direction(&mut self, direction: &IntersectionActionDirection) {
+    pub fn set_direction(&mut self, direction: &IntersectionActionDirection) -> Result<()> {
         let angle = match direction {
             IntersectionActionDirection::Right => self.right_angle,
             IntersectionActionDirection::Left => self.left_angle,
         };
         let id = self.id;
-        if self
-            .i2c
+        self.i2c
             .borrow_mut()
             .write(self.address as u8, &[id, angle])
-            .is_err()
-        {
-            panic!("sending servo direction via i2c failed");
-        }
+            .map_err(|_| Error::ServoI2c(id))
+    }
+
+    /// Returns the id of the servo
+    pub fn get_id(&self) -> u8 {
+        self.id
     }
 
     /// Returns a new servo with the given pin and right and left angles
     ///
-    /// Sets the servo to the default direction right
+    /// Sets the servo to the default direction right, fails when an angle is above 180 degrees or
+    /// the direction can't be sent
     ///
     /// # Arguments
     ///
@@ -68,10 +71,12 @@ where
         i2c: &'l RefCell<I2C>,
         id: u8,
         address: u8,
-    ) -> Servo<'l, I2C, S> {
+    ) -> Result<Servo<'l, I2C, S>> {
         // check bounds of angles
-        if !(right_angle <= 180 && left_angle <= 180) {
-            panic!("angle must be between 0 and 180");
+        for angle in [right_angle, left_angle] {
+            if angle > 180 {
+                return Err(Error::InvalidAngle(angle));
+            }
         }
 
         // set to right angle as default
@@ -84,7 +89,7 @@ where
             id,
         };
 
-        servo.set_direction(&IntersectionActionDirection::Right);
-        servo
+        servo.set_direction(&IntersectionActionDirection::Right)?;
+        Ok(servo)
     }
 }
@@ -0,0 +1,62 @@
+//! Fixed number of slots for the parts another part reports to
+//!
+//! Without an allocator a sensor keeps the sections, approaches, boxes and so on it reports to in
+//! [`Slots`], which fail with [`Error::Config`] instead of growing when every slot is taken.
+
+use crate::error::{Error, Result};
+
+/// Up to `N` items, kept in the order they were pushed
+pub struct Slots<T, const N: usize> {
+    slots: [Option<T>; N],
+}
+
+impl<T: Copy, const N: usize> Slots<T, N> {
+    /// Returns slots without items
+    pub const fn new() -> Slots<T, N> {
+        Slots { slots: [None; N] }
+    }
+
+    /// Puts the item into the first free slot
+    ///
+    /// Fails with [`Error::Config`] of the part the slots belong to when every slot is taken
+    ///
+    /// # Arguments
+    ///
+    /// * `item` - the item to keep
+    /// * `id` - the id of the part the slots belong to, 0 for a part without one
+    pub fn push(&mut self, item: T, id: u8) -> Result<()> {
+        match self.slots.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => {
+                *slot = Some(item);
+                Ok(())
+            }
+            None => Err(Error::Config(id)),
+        }
+    }
+
+    /// Returns the items in the order they were pushed
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.slots.iter().flatten()
+    }
+}
+
+impl<T: Copy, const N: usize> Default for Slots<T, N> {
+    fn default() -> Self {
+        Slots::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Slots;
+    use crate::error::Error;
+
+    #[test]
+    fn full_slots_fail_with_the_id_and_keep_their_items() {
+        let mut slots: Slots<u8, 2> = Slots::new();
+        assert!(slots.push(1, 7).is_ok());
+        assert!(slots.push(2, 7).is_ok());
+        assert!(slots.push(3, 7) == Err(Error::Config(7)));
+        assert_eq!(slots.iter().copied().collect::<Vec<_>>(), [1, 2]);
+    }
+}
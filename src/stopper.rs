@@ -1,5 +1,7 @@
 use embedded_hal::digital::v2::{OutputPin, PinState};
 
+use crate::error::{Error, Result};
+
 pub const STOPPER_ACTIVE: bool = false;
 
 /// Struct which controls a stopper which stops cars
@@ -9,12 +11,25 @@ where
 {
     /// The pin connected to the stopper
     pin: W,
+    /// The id reported to the host
+    id: u8,
     /// The number of locks set by sections through lock() and released by release()
     number_locks: usize,
-    /// If the intersection locked the stopper
+    /// If the intersection or roundabout locked the stopper
     ///
     /// Overwrites the number_locks
     intersection_lock: bool,
+    /// If the host locked the stopper
+    ///
+    /// Overwrites the number_locks and the intersection_lock
+    manual_lock: bool,
+    /// If a failed part, e.g. a sensor of a section, locked the stopper
+    ///
+    /// Overwrites all other locks, releasing the manual_lock doesn't release it, only a reset
+    /// does
+    fault_lock: bool,
+    /// The state last written to the pin
+    written_state: bool,
 }
 
 impl<W> Stopper<W>
@@ -23,81 +38,110 @@ where
 {
     /// Writes to the pin of the stopper according to the locks
     ///
-    /// # Panic
-    /// Panics when writing the pin failes
-    fn write_pin(&mut self) {
-        #[allow(clippy::redundant_pattern_matching)]
-        if let Err(_) = self.pin.set_state(PinState::from(match self.get_state() {
-            STOPPER_ACTIVE => true,
-            _ => false,
-        })) {
-            panic!("write failed");
+    /// The state only counts as written when the write succeeded, so the next update tries again
+    fn write_pin(&mut self) -> Result<()> {
+        let state = self.get_state();
+        self.pin
+            .set_state(PinState::from(match state {
+                STOPPER_ACTIVE => true,
+                _ => false,
+            }))
+            .map_err(|_| Error::StopperPin(self.id))?;
+        self.written_state = state;
+        Ok(())
+    }
+
+    /// Calls write_pin() only if the state differs from the state last written
+    fn update_pin(&mut self) -> Result<()> {
+        if self.get_state() != self.written_state {
+            self.write_pin()?;
         }
+        Ok(())
+    }
+
+    /// Returns the id of the stopper
+    pub fn get_id(&self) -> u8 {
+        self.id
     }
 
-    /// Returns the current state which depends on the locks (number_locks and intersection_lock)
+    /// Returns the current state which depends on the locks (number_locks, intersection_lock,
+    /// manual_lock and fault_lock)
     pub fn get_state(&self) -> bool {
-        self.number_locks > 0 || self.intersection_lock
+        self.number_locks > 0 || self.intersection_lock || self.manual_lock || self.fault_lock
     }
 
     /// Locks the stopper by increasing number_locks by one and then calling write_pin()
     ///
     /// Only calles write_pin() when a change occured
-    pub fn lock(&mut self) {
+    pub fn lock(&mut self) -> Result<()> {
         self.number_locks += 1;
-
-        // only call write_pin() if the stopper changes the lock state
-        if self.number_locks == 1 && !self.intersection_lock {
-            self.write_pin();
-        }
+        self.update_pin()
     }
 
     /// Releases the stopper by decreasing number_locks by one if number_locks is 0
     ///
     /// Only calles write_pin() if a change occured
-    pub fn release(&mut self) {
+    pub fn release(&mut self) -> Result<()> {
         if self.number_locks > 0 {
             self.number_locks -= 1;
         }
-
-        // only call write_pin() if the stopper changes the lock state
-        if self.number_locks == 0 && !self.intersection_lock {
-            self.write_pin();
-        }
+        self.update_pin()
     }
 
     /// Locks the stopper overwriting number_locks
     ///
-    /// Only meant to be called from a intersection
-    pub fn intersection_lock(&mut self) {
+    /// Only meant to be called from a intersection or roundabout
+    pub fn intersection_lock(&mut self) -> Result<()> {
         self.intersection_lock = true;
-
-        // only call write_pin() if the stopper changes the lock state
-        if self.number_locks > 0 {
-            self.write_pin();
-        }
+        self.update_pin()
     }
 
     /// Releases the intersection lock overwrite
     ///
-    /// Only meant to be called from a intersection
-    pub fn intersection_release(&mut self) {
+    /// Only meant to be called from a intersection or roundabout
+    pub fn intersection_release(&mut self) -> Result<()> {
         self.intersection_lock = false;
+        self.update_pin()
+    }
 
-        // only call write_pin() if the stopper changes the lock state
-        if self.number_locks == 0 {
-            self.write_pin();
-        }
+    /// Locks the stopper overwriting all other locks
+    ///
+    /// Only meant to be called on behalf of the host
+    pub fn manual_lock(&mut self) -> Result<()> {
+        self.manual_lock = true;
+        self.update_pin()
+    }
+
+    /// Releases the manual lock overwrite
+    ///
+    /// Only meant to be called on behalf of the host
+    pub fn manual_release(&mut self) -> Result<()> {
+        self.manual_lock = false;
+        self.update_pin()
+    }
+
+    /// Locks the stopper overwriting all other locks until the board is reset
+    ///
+    /// Only meant to be called on behalf of the fault handler, a failed part isn't used again
+    pub fn fault_lock(&mut self) -> Result<()> {
+        self.fault_lock = true;
+        self.update_pin()
     }
 
-    /// Returns a stopper with the given pin
-    pub fn new(pin: W) -> Self {
+    /// Returns a stopper with the given pin and id
+    ///
+    /// Fails when the released state can't be written to the pin
+    pub fn new(pin: W, id: u8) -> Result<Self> {
         let mut stopper = Stopper {
             pin,
+            id,
             number_locks: 0,
             intersection_lock: false,
+            manual_lock: false,
+            fault_lock: false,
+            written_state: false,
         };
-        stopper.write_pin();
-        stopper
+        stopper.write_pin()?;
+        Ok(stopper)
     }
 }
@@ -1,44 +1,145 @@
-use core::cell::Cell;
+//! Millisecond clock of the controller
+//!
+//! On the board the clock is driven by timer 0. Everywhere else it is a mock clock which only
+//! moves when it is set, so the host tools control the time the logic sees.
 
-const PRESCALER: u64 = 1024;
-const TIMER_COUNTS: u64 = 125;
+#[cfg(target_arch = "avr")]
+pub use self::avr::*;
+#[cfg(not(target_arch = "avr"))]
+pub use self::mock::*;
 
-const MILLIS_INCREMENT: u64 = PRESCALER * TIMER_COUNTS / 16000;
+/// Milliseconds of a day, the default length a [`DailyWindow`] repeats after
+pub const DAY: u64 = 24 * 60 * 60 * 1_000;
 
-static MILLIS_COUNTER: avr_device::interrupt::Mutex<Cell<u64>> =
-    avr_device::interrupt::Mutex::new(Cell::new(0));
+/// Time span of the clock which repeats every day, e.g. the night
+///
+/// The board has no clock of the day, the day starts when the clock starts. A window whose start
+/// is after its end wraps around the end of the day.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct DailyWindow {
+    /// The milliseconds into the day the window starts
+    from: u64,
+    /// The milliseconds into the day the window ends
+    until: u64,
+    /// The milliseconds of a day
+    day: u64,
+}
 
-pub fn millis_init(tc0: arduino_hal::pac::TC0) {
-    // Configure the timer for the above interval (in CTC mode)
-    // and enable its interrupt.
-    tc0.tccr0a.write(|w| w.wgm0().ctc());
-    tc0.ocr0a.write(|w| unsafe { w.bits(TIMER_COUNTS as u8) });
-    tc0.tccr0b.write(|w| match PRESCALER {
-        8 => w.cs0().prescale_8(),
-        64 => w.cs0().prescale_64(),
-        256 => w.cs0().prescale_256(),
-        1024 => w.cs0().prescale_1024(),
-        _ => panic!(),
-    });
-    tc0.timsk0.write(|w| w.ocie0a().set_bit());
+impl DailyWindow {
+    /// Returns the window, `None` for a day without milliseconds, a window which starts when it
+    /// ends or one which isn't within the day
+    ///
+    /// # Arguments
+    ///
+    /// * `from` - the milliseconds into the day the window starts
+    /// * `until` - the milliseconds into the day the window ends
+    /// * `day` - the milliseconds of a day, e.g. [`DAY`]
+    pub const fn new(from: u64, until: u64, day: u64) -> Option<DailyWindow> {
+        match day > 0 && from != until && from <= day && until <= day {
+            true => Some(DailyWindow { from, until, day }),
+            false => None,
+        }
+    }
 
-    // Reset the global millisecond counter
-    avr_device::interrupt::free(|cs| {
-        MILLIS_COUNTER.borrow(cs).set(0);
-    });
+    /// Returns if the time of the clock in milliseconds is inside the window
+    pub fn contains(&self, time: u64) -> bool {
+        let time = time % self.day;
+        match self.from <= self.until {
+            true => self.from <= time && time < self.until,
+            false => self.from <= time || time < self.until,
+        }
+    }
 }
 
-#[avr_device::interrupt(atmega2560)]
-fn TIMER0_COMPA() {
-    avr_device::interrupt::free(|cs| {
-        let counter_cell = MILLIS_COUNTER.borrow(cs);
-        let counter = counter_cell.get();
-        counter_cell.set(counter + MILLIS_INCREMENT);
-    })
+#[cfg(target_arch = "avr")]
+mod avr {
+    use core::cell::Cell;
+
+    const PRESCALER: u64 = 1024;
+    const TIMER_COUNTS: u64 = 125;
+
+    const MILLIS_INCREMENT: u64 = PRESCALER * TIMER_COUNTS / 16000;
+
+    static MILLIS_COUNTER: avr_device::interrupt::Mutex<Cell<u64>> =
+        avr_device::interrupt::Mutex::new(Cell::new(0));
+
+    pub fn millis_init(tc0: arduino_hal::pac::TC0) {
+        // Configure the timer for the above interval (in CTC mode)
+        // and enable its interrupt.
+        tc0.tccr0a.write(|w| w.wgm0().ctc());
+        tc0.ocr0a.write(|w| unsafe { w.bits(TIMER_COUNTS as u8) });
+        tc0.tccr0b.write(|w| match PRESCALER {
+            8 => w.cs0().prescale_8(),
+            64 => w.cs0().prescale_64(),
+            256 => w.cs0().prescale_256(),
+            1024 => w.cs0().prescale_1024(),
+            _ => panic!(),
+        });
+        tc0.timsk0.write(|w| w.ocie0a().set_bit());
+
+        // Reset the global millisecond counter
+        avr_device::interrupt::free(|cs| {
+            MILLIS_COUNTER.borrow(cs).set(0);
+        });
+    }
+
+    #[avr_device::interrupt(atmega2560)]
+    fn TIMER0_COMPA() {
+        avr_device::interrupt::free(|cs| {
+            let counter_cell = MILLIS_COUNTER.borrow(cs);
+            let counter = counter_cell.get();
+            counter_cell.set(counter + MILLIS_INCREMENT);
+        })
+    }
+
+    pub fn millis() -> u64 {
+        avr_device::interrupt::free(|cs| MILLIS_COUNTER.borrow(cs).get())
+    }
 }
 
-pub fn millis() -> u64 {
-    avr_device::interrupt::free(|cs| MILLIS_COUNTER.borrow(cs).get())
+#[cfg(not(target_arch = "avr"))]
+mod mock {
+    #[cfg(test)]
+    use core::sync::atomic::AtomicBool;
+    use core::sync::atomic::{AtomicU64, Ordering};
+
+    static MILLIS_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    /// If a test uses the mock clock, the tests run in parallel but share it
+    #[cfg(test)]
+    static CLOCK_TAKEN: AtomicBool = AtomicBool::new(false);
+
+    /// Sets the mock clock to the given time in milliseconds
+    pub fn set_millis(time: u64) {
+        MILLIS_COUNTER.store(time, Ordering::Relaxed);
+    }
+
+    pub fn millis() -> u64 {
+        MILLIS_COUNTER.load(Ordering::Relaxed)
+    }
+
+    /// The mock clock for one test, released when dropped
+    #[cfg(test)]
+    pub(crate) struct ClockGuard;
+
+    #[cfg(test)]
+    impl ClockGuard {
+        /// Waits until no other test uses the mock clock and sets it to 0
+        pub(crate) fn take() -> ClockGuard {
+            while CLOCK_TAKEN.swap(true, Ordering::Acquire) {
+                std::thread::yield_now();
+            }
+            set_millis(0);
+            ClockGuard
+        }
+    }
+
+    #[cfg(test)]
+    impl Drop for ClockGuard {
+        fn drop(&mut self) {
+            CLOCK_TAKEN.store(false, Ordering::Release);
+        }
+    }
 }
 /*const MICROS_INCREMENT: u32 = PRESCALER * TIMER_COUNTS / 2;
 
@@ -77,3 +178,28 @@ fn TIMER0_COMPA() {
 pub fn micros() -> u32 {
     avr_device::interrupt::free(|cs| MICROS_COUNTER.borrow(cs).get())
 }*/
+
+#[cfg(test)]
+mod tests {
+    use super::DailyWindow;
+
+    #[test]
+    fn daily_window_repeats_and_wraps_around_the_end_of_the_day() {
+        let night = DailyWindow::new(900, 300, 1_200).unwrap();
+        let inside = [0, 299, 900, 1_199, 1_200, 1_499, 2_100];
+        let outside = [300, 899, 1_500, 2_099];
+        assert!(inside.iter().all(|time| night.contains(*time)));
+        assert!(!outside.iter().any(|time| night.contains(*time)));
+
+        let morning = DailyWindow::new(300, 900, 1_200).unwrap();
+        assert!(morning.contains(1_500) && !morning.contains(2_100));
+    }
+
+    #[test]
+    fn daily_window_has_to_be_within_a_day_with_milliseconds() {
+        assert!(DailyWindow::new(0, 300, 0).is_none());
+        assert!(DailyWindow::new(300, 300, 1_200).is_none());
+        assert!(DailyWindow::new(300, 1_500, 1_200).is_none());
+        assert!(DailyWindow::new(0, 1_200, 1_200).is_some());
+    }
+}
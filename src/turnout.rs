@@ -0,0 +1,393 @@
+//! Turnouts switching the cars between two roads
+//!
+//! A [`Turnout`] is a guide moved by a servo, like the ones of intersection arms, but on its own,
+//! e.g. at a Y-junction or the entry of a depot. It is straight in the right direction of its
+//! servo, which is the one a new servo is set to, and diverging in the left direction.
+//!
+//! A position is requested by the `TURNOUT` command, by a [`Route`] setting several turnouts at
+//! once with the `ROUTE` command or by the schedule of the turnout, which makes it diverge from
+//! and straight again at the given times of every day. While a car is in a section covering the
+//! switch the turnout is locked, a position requested meanwhile is commanded once the sections are
+//! free. The last request wins, so a turnout only moves to the position requested last.
+
+use core::cell::RefCell;
+use embedded_hal::blocking::i2c;
+use embedded_hal::blocking::i2c::SevenBitAddress;
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+
+use crate::error::Result;
+use crate::intersection::IntersectionActionDirection;
+use crate::log::{Logger, Module};
+use crate::section::Section;
+use crate::servo::Servo;
+use crate::slots::Slots;
+use crate::time::{millis, DailyWindow};
+
+/// Maximum number of turnouts of a route
+pub const MAX_ROUTE_TURNOUTS: usize = 4;
+
+/// The positions of a turnout
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum TurnoutPosition {
+    /// Along the main road, the right direction of the servo
+    Straight,
+    /// Off the main road, the left direction of the servo
+    Diverging,
+}
+
+/// Structure to represent a turnout, see the module documentation
+pub struct Turnout<'l, I2C, W, R, S>
+where
+    I2C: i2c::Write<SevenBitAddress>,
+    W: OutputPin,
+    R: InputPin,
+    S: OutputPin,
+{
+    /// The id of the turnout
+    id: u8,
+    servo: &'l RefCell<Servo<'l, I2C, S>>,
+    /// The sections covering the switch, the turnout is locked while a car is in one of them
+    sections: Slots<&'l RefCell<Section<'l, W, R>>, 2>,
+    /// The position last commanded, None while commanding it failed
+    position: Option<TurnoutPosition>,
+    /// The position requested but not yet commanded
+    requested: Option<TurnoutPosition>,
+    /// The time of every day the turnout diverges
+    schedule: Option<DailyWindow>,
+    /// If the schedule made the turnout diverge
+    scheduled: bool,
+}
+
+impl<'l, I2C, W, R, S> Turnout<'l, I2C, W, R, S>
+where
+    I2C: i2c::Write<SevenBitAddress>,
+    W: OutputPin,
+    R: InputPin,
+    S: OutputPin,
+{
+    /// Returns a straight turnout
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - the id of the turnout
+    /// * `servo` - the servo moving the guide, which has to be set to the right direction
+    pub fn new(id: u8, servo: &'l RefCell<Servo<'l, I2C, S>>) -> Turnout<'l, I2C, W, R, S> {
+        Turnout {
+            id,
+            servo,
+            sections: Default::default(),
+            position: Some(TurnoutPosition::Straight),
+            requested: None,
+            schedule: None,
+            scheduled: false,
+        }
+    }
+
+    /// Adds a section covering the switch
+    ///
+    /// Fails with [`Error::Config`](crate::error::Error::Config) when the turnout already has two
+    /// sections
+    pub fn add_section(&mut self, section: &'l RefCell<Section<'l, W, R>>) -> Result<()> {
+        self.sections.push(section, self.id)
+    }
+
+    /// Makes the turnout diverge from and straight again at the given times of every day
+    ///
+    /// # Arguments
+    ///
+    /// * `schedule` - the time of every day the turnout diverges
+    pub fn set_schedule(&mut self, schedule: DailyWindow) {
+        self.schedule = Some(schedule);
+    }
+
+    /// Returns the id of the turnout
+    pub fn get_id(&self) -> u8 {
+        self.id
+    }
+
+    /// Returns the position last commanded, None while commanding it failed
+    pub fn position(&self) -> Option<TurnoutPosition> {
+        self.position
+    }
+
+    /// Returns if a car is in a section covering the switch
+    pub fn locked(&self) -> bool {
+        self.sections.iter().any(|section| section.borrow().occupied())
+    }
+
+    /// Requests the position and commands it unless the turnout is locked
+    ///
+    /// # Arguments
+    ///
+    /// * `position` - the position to move to
+    /// * `log` - the logger the moves are logged to
+    pub fn request(&mut self, position: TurnoutPosition, log: &Logger) -> Result<()> {
+        self.requested = Some(position);
+        self.command(log)
+    }
+
+    /// Requests the position of the schedule when it changes and commands the position requested
+    /// once the turnout isn't locked anymore
+    ///
+    /// Has to be called regularily, a position which failed to be commanded is commanded again
+    ///
+    /// # Arguments
+    ///
+    /// * `log` - the logger the moves are logged to
+    pub fn call(&mut self, log: &Logger) -> Result<()> {
+        if let Some(schedule) = self.schedule {
+            let scheduled = schedule.contains(millis());
+            if scheduled != self.scheduled {
+                self.scheduled = scheduled;
+                self.requested = Some(match scheduled {
+                    true => TurnoutPosition::Diverging,
+                    false => TurnoutPosition::Straight,
+                });
+            }
+        }
+        self.command(log)
+    }
+
+    /// Commands the position requested unless the turnout is locked
+    fn command(&mut self, log: &Logger) -> Result<()> {
+        let requested = match self.requested {
+            Some(requested) => requested,
+            None => return Ok(()),
+        };
+        if self.locked() {
+            return Ok(());
+        }
+        if self.position != Some(requested) {
+            let (direction, name) = match requested {
+                TurnoutPosition::Straight => (IntersectionActionDirection::Right, "straight"),
+                TurnoutPosition::Diverging => (IntersectionActionDirection::Left, "diverging"),
+            };
+            crate::info!(log, Module::Turnout, "turnout {} moves {}", self.id, name);
+            self.position = None;
+            self.servo.borrow_mut().set_direction(&direction)?;
+            self.position = Some(requested);
+        }
+        self.requested = None;
+        Ok(())
+    }
+}
+
+/// A turnout of a route and the position it has on the route
+type RouteTurnout<'l, I2C, W, R, S> = (&'l RefCell<Turnout<'l, I2C, W, R, S>>, TurnoutPosition);
+
+/// Structure to represent a route, the positions of several turnouts set together
+pub struct Route<'l, I2C, W, R, S>
+where
+    I2C: i2c::Write<SevenBitAddress>,
+    W: OutputPin,
+    R: InputPin,
+    S: OutputPin,
+{
+    /// The id of the route
+    id: u8,
+    /// The turnouts with their position on the route
+    turnouts: Slots<RouteTurnout<'l, I2C, W, R, S>, MAX_ROUTE_TURNOUTS>,
+}
+
+impl<'l, I2C, W, R, S> Route<'l, I2C, W, R, S>
+where
+    I2C: i2c::Write<SevenBitAddress>,
+    W: OutputPin,
+    R: InputPin,
+    S: OutputPin,
+{
+    /// Returns a route without turnouts
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - the id of the route
+    pub fn new(id: u8) -> Route<'l, I2C, W, R, S> {
+        Route {
+            id,
+            turnouts: Default::default(),
+        }
+    }
+
+    /// Adds a turnout and the position it has on the route
+    ///
+    /// Fails with [`Error::Config`](crate::error::Error::Config) when the route already has
+    /// MAX_ROUTE_TURNOUTS turnouts
+    pub fn add_turnout(
+        &mut self,
+        turnout: &'l RefCell<Turnout<'l, I2C, W, R, S>>,
+        position: TurnoutPosition,
+    ) -> Result<()> {
+        self.turnouts.push((turnout, position), self.id)
+    }
+
+    /// Returns the id of the route
+    pub fn get_id(&self) -> u8 {
+        self.id
+    }
+
+    /// Requests the position of every turnout of the route
+    ///
+    /// Every turnout is requested even if an earlier one failed, the first error is returned
+    ///
+    /// # Arguments
+    ///
+    /// * `log` - the logger the moves are logged to
+    pub fn set(&self, log: &Logger) -> Result<()> {
+        let mut result = Ok(());
+        for (turnout, position) in self.turnouts.iter() {
+            result = result.and(turnout.borrow_mut().request(*position, log));
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use crate::pin_mockup::Pin;
+    use crate::serial::Serial;
+    use crate::time::{set_millis, ClockGuard};
+
+    const LEFT_ANGLE: u8 = 120;
+    const RIGHT_ANGLE: u8 = 60;
+
+    struct Discard;
+
+    impl Serial for Discard {
+        fn write_str(&mut self, _: &str) {}
+    }
+
+    /// Servo controller remembering the servo ids and angles written
+    #[derive(Default)]
+    struct TestI2c {
+        writes: Vec<[u8; 2]>,
+    }
+
+    impl i2c::Write for TestI2c {
+        type Error = ();
+
+        fn write(&mut self, _address: u8, bytes: &[u8]) -> core::result::Result<(), ()> {
+            self.writes.push([bytes[0], bytes[1]]);
+            Ok(())
+        }
+    }
+
+    type TestTurnout<'l> = Turnout<'l, TestI2c, Pin<'l>, Pin<'l>, Pin<'l>>;
+    type TestSection<'l> = RefCell<Section<'l, Pin<'l>, Pin<'l>>>;
+
+    /// Builds two turnouts on mock pins, each covered by a section of its own, and passes them
+    /// with their sections and servo controller to `f`
+    fn turnouts(
+        f: impl for<'l> FnOnce(
+            &'l [RefCell<TestTurnout<'l>>],
+            &'l [TestSection<'l>],
+            &'l RefCell<TestI2c>,
+        ),
+    ) {
+        let serial = RefCell::new(Discard);
+        let levels: [RefCell<bool>; 2] = Default::default();
+        let i2c = RefCell::new(TestI2c::default());
+        let servos: Vec<_> = (0..2)
+            .map(|index| {
+                let pin = Pin::new(&levels[index]);
+                let servo = Servo::new(pin, RIGHT_ANGLE, LEFT_ANGLE, &i2c, index as u8, 4);
+                RefCell::new(servo.unwrap())
+            })
+            .collect();
+        let sections: Vec<_> =
+            (0..2).map(|index| RefCell::new(Section::new(index, &serial))).collect();
+        let turnouts: Vec<_> = (0..2)
+            .map(|index| {
+                let mut turnout = Turnout::new(index as u8 + 1, &servos[index]);
+                turnout.add_section(&sections[index]).unwrap();
+                RefCell::new(turnout)
+            })
+            .collect();
+        i2c.borrow_mut().writes.clear();
+        f(&turnouts, &sections, &i2c);
+    }
+
+    fn take_writes(i2c: &RefCell<TestI2c>) -> Vec<[u8; 2]> {
+        i2c.borrow_mut().writes.drain(..).collect()
+    }
+
+    #[test]
+    fn locked_turnout_moves_to_the_last_request_once_its_section_is_free() {
+        let _clock = ClockGuard::take();
+        turnouts(|turnouts, sections, i2c| {
+            let log = Logger::new();
+            let mut turnout = turnouts[0].borrow_mut();
+            sections[0].borrow_mut().start_sensor_callback().unwrap();
+            assert!(turnout.locked());
+            turnout.request(TurnoutPosition::Diverging, &log).unwrap();
+            turnout.request(TurnoutPosition::Straight, &log).unwrap();
+            turnout.request(TurnoutPosition::Diverging, &log).unwrap();
+            turnout.call(&log).unwrap();
+            assert!(take_writes(i2c).is_empty());
+            assert!(turnout.position() == Some(TurnoutPosition::Straight));
+
+            sections[0].borrow_mut().end_sensor_callback().unwrap();
+            turnout.call(&log).unwrap();
+            assert_eq!(take_writes(i2c), [[0, LEFT_ANGLE]]);
+            assert!(turnout.position() == Some(TurnoutPosition::Diverging));
+            // a position commanded isn't commanded again
+            turnout.call(&log).unwrap();
+            assert!(take_writes(i2c).is_empty());
+        });
+    }
+
+    #[test]
+    fn schedule_diverges_the_turnout_inside_its_window() {
+        let _clock = ClockGuard::take();
+        turnouts(|turnouts, _, i2c| {
+            let log = Logger::new();
+            let mut turnout = turnouts[0].borrow_mut();
+            turnout.set_schedule(DailyWindow::new(3_000, 10_000, 20_000).unwrap());
+            set_millis(2_999);
+            turnout.call(&log).unwrap();
+            assert!(take_writes(i2c).is_empty());
+            set_millis(3_000);
+            turnout.call(&log).unwrap();
+            assert_eq!(take_writes(i2c), [[0, LEFT_ANGLE]]);
+            set_millis(10_000);
+            turnout.call(&log).unwrap();
+            assert_eq!(take_writes(i2c), [[0, RIGHT_ANGLE]]);
+        });
+    }
+
+    #[test]
+    fn route_requests_every_turnout_and_the_locked_one_follows() {
+        let _clock = ClockGuard::take();
+        turnouts(|turnouts, sections, i2c| {
+            let log = Logger::new();
+            let mut route = Route::new(1);
+            route.add_turnout(&turnouts[0], TurnoutPosition::Diverging).unwrap();
+            route.add_turnout(&turnouts[1], TurnoutPosition::Diverging).unwrap();
+            sections[1].borrow_mut().start_sensor_callback().unwrap();
+            route.set(&log).unwrap();
+            assert_eq!(take_writes(i2c), [[0, LEFT_ANGLE]]);
+
+            sections[1].borrow_mut().end_sensor_callback().unwrap();
+            turnouts[1].borrow_mut().call(&log).unwrap();
+            assert_eq!(take_writes(i2c), [[1, LEFT_ANGLE]]);
+        });
+    }
+
+    #[test]
+    fn parts_past_the_maximum_are_config_errors() {
+        turnouts(|turnouts, sections, _| {
+            let mut turnout = turnouts[0].borrow_mut();
+            assert!(turnout.add_section(&sections[1]).is_ok());
+            assert!(turnout.add_section(&sections[1]) == Err(Error::Config(1)));
+            drop(turnout);
+
+            let mut route = Route::new(7);
+            for _ in 0..MAX_ROUTE_TURNOUTS {
+                route.add_turnout(&turnouts[1], TurnoutPosition::Straight).unwrap();
+            }
+            let result = route.add_turnout(&turnouts[1], TurnoutPosition::Straight);
+            assert!(result == Err(Error::Config(7)));
+        });
+    }
+}
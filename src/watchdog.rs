@@ -0,0 +1,121 @@
+//! Supervision of the periodic tasks of the main loop
+//!
+//! The hardware watchdog resets the board unless it is fed regularly. The main loop only feeds it
+//! while every task ran within its deadline, so a task which stops running or hangs leads to a
+//! reset and the failed task is recorded in the crash record.
+
+use crate::error::{Error, Result};
+
+/// Maximum number of tasks a supervisor watches
+pub const MAX_TASKS: usize = 8;
+
+/// A periodic task of the main loop
+pub struct Task {
+    /// Name reported when the task missed its deadline
+    pub name: &'static str,
+    /// Maximum milliseconds between two runs
+    pub deadline: u64,
+}
+
+/// Struct which watches that every task runs within its deadline but has to be told about every
+/// run of a task
+pub struct Supervisor<'l> {
+    tasks: &'l [Task],
+    /// Time in milliseconds of the last run of every task
+    last_runs: [u64; MAX_TASKS],
+}
+
+impl<'l> Supervisor<'l> {
+    /// Returns a supervisor for the tasks, counting every task as run at the given time
+    ///
+    /// Fails with [`Error::Config`] of id 0 when there are more than MAX_TASKS tasks
+    ///
+    /// # Arguments
+    ///
+    /// * `tasks` - the tasks to watch, they are referred to by their index
+    /// * `time` - the current time in milliseconds
+    pub fn new(tasks: &'l [Task], time: u64) -> Result<Supervisor<'l>> {
+        if tasks.len() > MAX_TASKS {
+            return Err(Error::Config(0));
+        }
+        Ok(Supervisor {
+            tasks,
+            last_runs: [time; MAX_TASKS],
+        })
+    }
+
+    /// Records a run of the task
+    ///
+    /// # Arguments
+    ///
+    /// * `task` - the index of the task
+    /// * `time` - the time in milliseconds the task finished
+    pub fn ran(&mut self, task: usize, time: u64) {
+        self.last_runs[task] = time;
+    }
+
+    /// Returns the index of the first task which missed its deadline
+    ///
+    /// # Arguments
+    ///
+    /// * `time` - the current time in milliseconds
+    pub fn overdue(&self, time: u64) -> Option<usize> {
+        self.tasks
+            .iter()
+            .zip(self.last_runs)
+            .position(|(task, last_run)| time.saturating_sub(last_run) > task.deadline)
+    }
+
+    /// Returns the task with the index
+    pub fn task(&self, task: usize) -> &Task {
+        &self.tasks[task]
+    }
+}
+
+/// Why the board was reset
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ResetCause {
+    PowerOn,
+    External,
+    Brownout,
+    Watchdog,
+    Jtag,
+    /// No flag was set, e.g. after a jump to the reset vector
+    Unknown,
+}
+
+impl ResetCause {
+    /// Returns the cause from the flags of the MCU status register
+    ///
+    /// # Arguments
+    ///
+    /// * `flags` - the value of `MCUSR` at boot
+    pub fn from_flags(flags: u8) -> ResetCause {
+        // flags which weren't cleared since an earlier reset may be set too, the order decides
+        if flags & 0x01 != 0 {
+            ResetCause::PowerOn
+        } else if flags & 0x08 != 0 {
+            ResetCause::Watchdog
+        } else if flags & 0x04 != 0 {
+            ResetCause::Brownout
+        } else if flags & 0x02 != 0 {
+            ResetCause::External
+        } else if flags & 0x10 != 0 {
+            ResetCause::Jtag
+        } else {
+            ResetCause::Unknown
+        }
+    }
+
+    /// Returns the name of the cause
+    pub fn name(&self) -> &'static str {
+        match self {
+            ResetCause::PowerOn => "power-on",
+            ResetCause::External => "external",
+            ResetCause::Brownout => "brownout",
+            ResetCause::Watchdog => "watchdog",
+            ResetCause::Jtag => "jtag",
+            ResetCause::Unknown => "unknown",
+        }
+    }
+}